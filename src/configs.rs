@@ -1,7 +1,10 @@
-use clap::{arg, command, value_parser};
+use clap::{arg, command, value_parser, ArgAction};
+use file_server_core::{archive::is_archive, DATA_DIR_NAME};
 
+use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct ServerConfigs {
@@ -10,6 +13,58 @@ pub struct ServerConfigs {
     pub port: u16,
     pub log_level: log::Level,
     pub workers: usize,
+    pub cache_dir: PathBuf,
+    pub max_image_pixels: u64,
+    pub image_concurrency: usize,
+    /// MIME types overriding detection, keyed by lowercase file name or extension
+    pub mime_types: HashMap<String, String>,
+    /// Where the server keeps its own data, such as previous versions of files
+    pub data_dir: PathBuf,
+    /// Previous versions kept per file, 0 disables versioning
+    pub max_versions: usize,
+    /// Days previous versions are kept for, 0 keeps them regardless of age
+    pub version_max_age_days: u64,
+    /// Days deleted entries are kept in the trash for, 0 keeps them until purged
+    pub trash_max_age_days: u64,
+    /// Hours an unfinished resumable upload is kept for
+    pub upload_expiry_hours: u64,
+    /// Port of the S3-compatible API, which is only served when set
+    pub s3_port: Option<u16>,
+    /// Name of the bucket holding the files of `base_dir`
+    pub s3_bucket: String,
+    /// Secret keys of the S3-compatible API, keyed by access key
+    pub s3_credentials: S3Credentials,
+    /// Directories outside of the served files that only accept uploads through `/drop/{name}`,
+    /// keyed by name
+    pub drop_boxes: HashMap<String, PathBuf>,
+    /// Largest file accepted by drop boxes in bytes
+    pub drop_box_max_size: u64,
+    /// File every upload to drop boxes is recorded in, if any
+    pub drop_box_log: Option<PathBuf>,
+    /// Where text posted to `/paste` is stored, outside of the served files so pastes are only
+    /// read through `/p/{id}`
+    pub paste_dir: PathBuf,
+    /// Directories merged under `base_dir` by name, from the lowest layer up. `base_dir` is the
+    /// top layer receiving the writes.
+    pub layers: Vec<(String, PathBuf)>,
+    /// Whether `index.html` is served instead of the listing of directories requested by browsers
+    pub index_files: bool,
+    /// Whether `/` serves `base_dir` as a website rather than the file manager
+    pub site: bool,
+    /// Path of the file manager's page in site mode
+    pub manager_path: String,
+    /// Whether the site answers paths matching no file with its `index.html`
+    pub spa_fallback: bool,
+}
+
+/// Secret keys keyed by access key, only the access keys are logged
+#[derive(Clone, Default)]
+pub struct S3Credentials(pub HashMap<String, String>);
+
+impl fmt::Debug for S3Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.0.keys()).finish()
+    }
 }
 
 impl Default for ServerConfigs {
@@ -20,11 +75,46 @@ impl Default for ServerConfigs {
             port: 8080,
             log_level: log::Level::Info,
             workers: 2,
+            cache_dir: env::temp_dir().join("file-server-cache"),
+            max_image_pixels: 50_000_000,
+            image_concurrency: 2,
+            mime_types: HashMap::new(),
+            data_dir: PathBuf::from(DATA_DIR_NAME),
+            max_versions: 10,
+            version_max_age_days: 30,
+            trash_max_age_days: 30,
+            upload_expiry_hours: 24,
+            s3_port: None,
+            s3_bucket: "files".to_string(),
+            s3_credentials: S3Credentials::default(),
+            drop_boxes: HashMap::new(),
+            drop_box_max_size: 1024 * 1024 * 1024,
+            drop_box_log: None,
+            paste_dir: PathBuf::from("pastes"),
+            layers: Vec::new(),
+            index_files: false,
+            site: false,
+            manager_path: String::from("/manager"),
+            spa_fallback: false,
         }
     }
 }
 
 impl ServerConfigs {
+    /// Path the file manager's routes are mounted under: `manager_path` in site mode, which
+    /// also serves the manager's page, or `/manager` beside the page at `/`
+    pub fn manager_scope(&self) -> &str {
+        match self.site {
+            true => &self.manager_path,
+            false => "/manager",
+        }
+    }
+
+    /// Path of the file manager's API, which its templates link to
+    pub fn manager_api(&self) -> String {
+        format!("{}/api/v1", self.manager_scope())
+    }
+
     pub fn builder() -> ServerConfigsBuilder {
         ServerConfigsBuilder {
             base_dir: None,
@@ -32,13 +122,34 @@ impl ServerConfigs {
             port: None,
             log_level: None,
             workers: None,
+            cache_dir: None,
+            max_image_pixels: None,
+            image_concurrency: None,
+            mime_types: HashMap::new(),
+            data_dir: None,
+            max_versions: None,
+            version_max_age_days: None,
+            trash_max_age_days: None,
+            upload_expiry_hours: None,
+            s3_port: None,
+            s3_bucket: None,
+            s3_credentials: HashMap::new(),
+            drop_boxes: HashMap::new(),
+            drop_box_max_size: None,
+            drop_box_log: None,
+            paste_dir: None,
+            layers: Vec::new(),
+            index_files: None,
+            site: None,
+            manager_path: None,
+            spa_fallback: None,
         }
     }
 
     pub fn from_cli_args() -> Self {
         let matches = command!()
             .arg(
-                arg!([base_dir] "Optional base directory to serve. Current working directory by default.")
+                arg!([base_dir] "Optional base directory, or .tar/.zip archive served read-only. Current working directory by default.")
                     .required(false)
                     .value_parser(value_parser!(PathBuf)),
             )
@@ -62,6 +173,117 @@ impl ServerConfigs {
                     .required(false)
                     .value_parser(value_parser!(usize)),
             )
+            .arg(
+                arg!(--"cache-dir" <CACHE_DIR> "Sets directory for generated files such as thumbnails. Default = <temp dir>/file-server-cache")
+                    .required(false)
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--"max-image-pixels" <PIXELS> "Sets the largest image in pixels that will be resized or converted. Default = 50000000")
+                    .required(false)
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"image-concurrency" <COUNT> "Sets number of images processed at the same time. Default = 2")
+                    .required(false)
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"mime-type" <MAPPING> "Overrides the detected MIME type of a file name or extension, e.g. log=text/plain. Can be repeated")
+                    .required(false)
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(--"data-dir" <DATA_DIR> "Sets directory for server data such as previous versions of files, which must not be served. Default = <base_dir>/.file-server, next to the archive when serving one")
+                    .required(false)
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--"max-versions" <COUNT> "Sets number of previous versions kept per overwritten file, 0 disables versioning. Default = 10")
+                    .required(false)
+                    .value_parser(value_parser!(usize)),
+            )
+            .arg(
+                arg!(--"version-max-age" <DAYS> "Sets number of days previous versions are kept, 0 keeps them forever. Default = 30")
+                    .required(false)
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"trash-max-age" <DAYS> "Sets number of days deleted files are kept in the trash, 0 keeps them until purged. Default = 30")
+                    .required(false)
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"upload-expiry" <HOURS> "Sets number of hours unfinished resumable uploads are kept. Default = 24")
+                    .required(false)
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"s3-port" <PORT> "Serves an S3-compatible API on this port, requires --s3-key")
+                    .required(false)
+                    .value_parser(value_parser!(u16)),
+            )
+            .arg(
+                arg!(--"s3-bucket" <NAME> "Sets the name of the bucket serving base_dir over the S3-compatible API. Default = files")
+                    .required(false)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(--"s3-key" <KEY> "Adds a key of the S3-compatible API, e.g. ACCESS_KEY:SECRET_KEY. Can be repeated")
+                    .required(false)
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(--"drop-box" <MAPPING> "Accepts uploads at /drop/<NAME> into a directory that isn't served, relative to data_dir/drop-boxes unless absolute, e.g. partners=partners. Can be repeated")
+                    .required(false)
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(--"drop-box-max-size" <MEGABYTES> "Sets the largest file accepted by drop boxes in megabytes. Default = 1024")
+                    .required(false)
+                    .value_parser(value_parser!(u64)),
+            )
+            .arg(
+                arg!(--"drop-box-log" <FILE> "Records every upload to drop boxes in this file as a line of JSON")
+                    .required(false)
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--"paste-dir" <PASTE_DIR> "Sets directory where pastes are stored, which must not be served. Default = <data_dir>/pastes")
+                    .required(false)
+                    .value_parser(value_parser!(PathBuf)),
+            )
+            .arg(
+                arg!(--layer <MAPPING> "Merges a directory under base_dir, which shadows it and receives the writes, e.g. shared=/srv/shared. Can be repeated, from the lowest layer up")
+                    .required(false)
+                    .action(ArgAction::Append)
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(--"index-files" "Serves index.html instead of the listing when a directory is requested from a browser")
+                    .required(false)
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                arg!(--site "Serves base_dir as a website at / and moves the file manager to --manager-path")
+                    .required(false)
+                    .action(ArgAction::SetTrue),
+            )
+            .arg(
+                arg!(--"manager-path" <PATH> "Sets path of the file manager in site mode. Default = /manager")
+                    .required(false)
+                    .requires("site")
+                    .value_parser(value_parser!(String)),
+            )
+            .arg(
+                arg!(--"spa-fallback" "Serves index.html of the site for paths matching no file, for single-page apps")
+                    .required(false)
+                    .requires("site")
+                    .action(ArgAction::SetTrue),
+            )
             .get_matches();
 
         let mut configs_builder = Self::builder();
@@ -72,9 +294,12 @@ impl ServerConfigs {
                 std::process::exit(1);
             }
 
-            if !base_dir.is_dir() {
-                println!("Error: base_dir is expected to be a directory.");
-                println!("{:?} is not a directory.", base_dir);
+            if !base_dir.is_dir() && !is_archive(base_dir) {
+                println!("Error: base_dir is expected to be a directory or an archive.");
+                println!(
+                    "{:?} is not a directory, .tar, .tar.gz or .zip file.",
+                    base_dir
+                );
                 std::process::exit(2);
             }
 
@@ -96,7 +321,211 @@ impl ServerConfigs {
             configs_builder.workers(workers);
         }
 
-        configs_builder.build()
+        if let Some(cache_dir) = matches.get_one::<PathBuf>("cache-dir") {
+            configs_builder.cache_dir(cache_dir);
+        }
+
+        if let Some(&max_image_pixels) = matches.get_one::<u64>("max-image-pixels") {
+            configs_builder.max_image_pixels(max_image_pixels);
+        }
+
+        if let Some(&image_concurrency) = matches.get_one::<usize>("image-concurrency") {
+            configs_builder.image_concurrency(image_concurrency);
+        }
+
+        if let Some(mappings) = matches.get_many::<String>("mime-type") {
+            for mapping in mappings {
+                match mapping.split_once('=') {
+                    Some((name, mime_type)) if mime_type.contains('/') => {
+                        configs_builder.mime_type(name, mime_type);
+                    }
+                    _ => {
+                        println!("Error: expected <NAME>=<MIME TYPE> for --mime-type.");
+                        println!("{:?} is not a valid mapping.", mapping);
+                        std::process::exit(3);
+                    }
+                }
+            }
+        }
+
+        if let Some(data_dir) = matches.get_one::<PathBuf>("data-dir") {
+            configs_builder.data_dir(data_dir);
+        }
+
+        if let Some(&max_versions) = matches.get_one::<usize>("max-versions") {
+            configs_builder.max_versions(max_versions);
+        }
+
+        if let Some(&days) = matches.get_one::<u64>("version-max-age") {
+            configs_builder.version_max_age_days(days);
+        }
+
+        if let Some(&days) = matches.get_one::<u64>("trash-max-age") {
+            configs_builder.trash_max_age_days(days);
+        }
+
+        if let Some(&hours) = matches.get_one::<u64>("upload-expiry") {
+            configs_builder.upload_expiry_hours(hours);
+        }
+
+        if let Some(&s3_port) = matches.get_one::<u16>("s3-port") {
+            configs_builder.s3_port(s3_port);
+        }
+
+        if let Some(bucket) = matches.get_one::<String>("s3-bucket") {
+            configs_builder.s3_bucket(bucket);
+        }
+
+        if let Some(keys) = matches.get_many::<String>("s3-key") {
+            for key in keys {
+                match key.split_once(':') {
+                    Some((access_key, secret_key))
+                        if !access_key.is_empty() && !secret_key.is_empty() =>
+                    {
+                        configs_builder.s3_key(access_key, secret_key);
+                    }
+                    _ => {
+                        println!("Error: expected <ACCESS_KEY>:<SECRET_KEY> for --s3-key.");
+                        std::process::exit(4);
+                    }
+                }
+            }
+        }
+
+        if let Some(mappings) = matches.get_many::<String>("drop-box") {
+            for mapping in mappings {
+                match mapping.split_once('=') {
+                    Some((name, path))
+                        if !name.is_empty()
+                            && name.bytes().all(|byte| {
+                                byte.is_ascii_alphanumeric() || b"-_".contains(&byte)
+                            })
+                            && !path.is_empty() =>
+                    {
+                        configs_builder.drop_box(name, &PathBuf::from(path));
+                    }
+                    _ => {
+                        println!("Error: expected <NAME>=<PATH> for --drop-box, the name being made of letters, digits, - and _.");
+                        println!("{:?} is not a valid drop box.", mapping);
+                        std::process::exit(5);
+                    }
+                }
+            }
+        }
+
+        if let Some(&megabytes) = matches.get_one::<u64>("drop-box-max-size") {
+            match megabytes.checked_mul(1024 * 1024) {
+                Some(bytes) => configs_builder.drop_box_max_size(bytes),
+                None => {
+                    println!(
+                        "Error: {} MB is too large for --drop-box-max-size.",
+                        megabytes
+                    );
+                    std::process::exit(5);
+                }
+            };
+        }
+
+        if let Some(path) = matches.get_one::<PathBuf>("drop-box-log") {
+            configs_builder.drop_box_log(path);
+        }
+
+        if let Some(paste_dir) = matches.get_one::<PathBuf>("paste-dir") {
+            configs_builder.paste_dir(paste_dir);
+        }
+
+        if let Some(mappings) = matches.get_many::<String>("layer") {
+            for mapping in mappings {
+                match mapping.split_once('=') {
+                    Some((name, path))
+                        if !name.is_empty()
+                            && name.bytes().all(|byte| {
+                                byte.is_ascii_alphanumeric() || b"-_".contains(&byte)
+                            })
+                            && PathBuf::from(path).is_dir() =>
+                    {
+                        configs_builder.layer(name, &PathBuf::from(path));
+                    }
+                    _ => {
+                        println!("Error: expected <NAME>=<DIRECTORY> for --layer, the name being made of letters, digits, - and _.");
+                        println!("{:?} is not a valid layer.", mapping);
+                        std::process::exit(6);
+                    }
+                }
+            }
+        }
+
+        if matches.get_flag("index-files") {
+            configs_builder.index_files(true);
+        }
+
+        if matches.get_flag("site") {
+            configs_builder.site(true);
+        }
+
+        if let Some(manager_path) = matches.get_one::<String>("manager-path") {
+            let trimmed = manager_path.trim_end_matches('/');
+            if !trimmed.starts_with('/') || trimmed.contains(['?', '#']) {
+                println!("Error: expected an absolute path other than / for --manager-path.");
+                println!("{:?} is not a valid path.", manager_path);
+                std::process::exit(7);
+            }
+            configs_builder.manager_path(trimmed);
+        }
+
+        if matches.get_flag("spa-fallback") {
+            configs_builder.spa_fallback(true);
+        }
+
+        let configs = configs_builder.build();
+        if configs.s3_port.is_some() && configs.s3_credentials.0.is_empty() {
+            println!("Error: the S3-compatible API requires at least one --s3-key.");
+            std::process::exit(4);
+        }
+        if let Some(served_dir) = configs.serving_dir(&configs.data_dir) {
+            println!("Error: --data-dir must not be in a served directory, which would let anyone read the versions, trash and share keys.");
+            println!("{:?} is in {:?}.", configs.data_dir, served_dir);
+            std::process::exit(9);
+        }
+        if let Some(served_dir) = configs.serving_dir(&configs.paste_dir) {
+            println!("Error: --paste-dir must not be in a served directory, which would let anyone read and change the pastes.");
+            println!("{:?} is in {:?}.", configs.paste_dir, served_dir);
+            std::process::exit(8);
+        }
+        for (name, path) in &configs.drop_boxes {
+            if let Some(served_dir) = configs.serving_dir(path) {
+                println!("Error: drop box {name} must not be in a served directory, which would let anyone read the uploads.");
+                println!("{:?} is in {:?}.", path, served_dir);
+                std::process::exit(5);
+            }
+        }
+
+        configs
+    }
+
+    /// Served directory `path` is in, if any. Only the `.file-server` directory at the root of
+    /// a served directory is hidden.
+    fn serving_dir(&self, path: &Path) -> Option<PathBuf> {
+        let path = resolve_path(path);
+
+        std::iter::once(&self.base_dir)
+            .chain(self.layers.iter().map(|(_, layer_dir)| layer_dir))
+            .filter(|dir| dir.is_dir())
+            .map(|dir| resolve_path(dir))
+            .find(|dir| path.starts_with(dir) && !path.starts_with(dir.join(DATA_DIR_NAME)))
+    }
+}
+
+/// Absolute path with symbolic links resolved, as far as the path exists
+fn resolve_path(path: &Path) -> PathBuf {
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => resolve_path(parent).join(name),
+        _ => absolute,
     }
 }
 
@@ -107,6 +536,27 @@ pub struct ServerConfigsBuilder {
     port: Option<u16>,
     log_level: Option<log::Level>,
     workers: Option<usize>,
+    cache_dir: Option<PathBuf>,
+    max_image_pixels: Option<u64>,
+    image_concurrency: Option<usize>,
+    mime_types: HashMap<String, String>,
+    data_dir: Option<PathBuf>,
+    max_versions: Option<usize>,
+    version_max_age_days: Option<u64>,
+    trash_max_age_days: Option<u64>,
+    upload_expiry_hours: Option<u64>,
+    s3_port: Option<u16>,
+    s3_bucket: Option<String>,
+    s3_credentials: HashMap<String, String>,
+    drop_boxes: HashMap<String, PathBuf>,
+    drop_box_max_size: Option<u64>,
+    drop_box_log: Option<PathBuf>,
+    paste_dir: Option<PathBuf>,
+    layers: Vec<(String, PathBuf)>,
+    index_files: Option<bool>,
+    site: Option<bool>,
+    manager_path: Option<String>,
+    spa_fallback: Option<bool>,
 }
 
 impl ServerConfigsBuilder {
@@ -142,6 +592,118 @@ impl ServerConfigsBuilder {
         self
     }
 
+    pub fn cache_dir(&mut self, path: &PathBuf) -> &Self {
+        self.cache_dir = Some(path.to_owned());
+        self
+    }
+
+    pub fn max_image_pixels(&mut self, max_image_pixels: u64) -> &Self {
+        self.max_image_pixels = Some(max_image_pixels);
+        self
+    }
+
+    pub fn image_concurrency(&mut self, image_concurrency: usize) -> &Self {
+        self.image_concurrency = Some(image_concurrency);
+        self
+    }
+
+    /// Overrides the MIME type of files with the name or extension (without the leading dot)
+    pub fn mime_type(&mut self, name: &str, mime_type: &str) -> &Self {
+        let name = name.trim().trim_start_matches('.').to_lowercase();
+        self.mime_types.insert(name, mime_type.trim().to_owned());
+        self
+    }
+
+    pub fn data_dir(&mut self, path: &PathBuf) -> &Self {
+        self.data_dir = Some(path.to_owned());
+        self
+    }
+
+    pub fn max_versions(&mut self, max_versions: usize) -> &Self {
+        self.max_versions = Some(max_versions);
+        self
+    }
+
+    pub fn version_max_age_days(&mut self, days: u64) -> &Self {
+        self.version_max_age_days = Some(days);
+        self
+    }
+
+    pub fn trash_max_age_days(&mut self, days: u64) -> &Self {
+        self.trash_max_age_days = Some(days);
+        self
+    }
+
+    pub fn upload_expiry_hours(&mut self, hours: u64) -> &Self {
+        self.upload_expiry_hours = Some(hours);
+        self
+    }
+
+    pub fn s3_port(&mut self, port: u16) -> &Self {
+        self.s3_port = Some(port);
+        self
+    }
+
+    pub fn s3_bucket(&mut self, bucket: &str) -> &Self {
+        self.s3_bucket = Some(bucket.to_string());
+        self
+    }
+
+    /// Allows requests to the S3-compatible API signed with the secret key of `access_key`
+    pub fn s3_key(&mut self, access_key: &str, secret_key: &str) -> &Self {
+        self.s3_credentials
+            .insert(access_key.to_owned(), secret_key.to_owned());
+        self
+    }
+
+    /// Accepts uploads to `path`, relative to `data_dir/drop-boxes` unless absolute, at
+    /// `/drop/{name}`
+    pub fn drop_box(&mut self, name: &str, path: &PathBuf) -> &Self {
+        self.drop_boxes.insert(name.to_owned(), path.to_owned());
+        self
+    }
+
+    pub fn drop_box_max_size(&mut self, bytes: u64) -> &Self {
+        self.drop_box_max_size = Some(bytes);
+        self
+    }
+
+    pub fn drop_box_log(&mut self, path: &PathBuf) -> &Self {
+        self.drop_box_log = Some(path.to_owned());
+        self
+    }
+
+    pub fn paste_dir(&mut self, path: &PathBuf) -> &Self {
+        self.paste_dir = Some(path.to_owned());
+        self
+    }
+
+    /// Merges the directory at `path` under `base_dir` and the layers added before it
+    pub fn layer(&mut self, name: &str, path: &PathBuf) -> &Self {
+        self.layers.push((name.to_owned(), path.to_owned()));
+        self
+    }
+
+    pub fn index_files(&mut self, enabled: bool) -> &Self {
+        self.index_files = Some(enabled);
+        self
+    }
+
+    pub fn site(&mut self, enabled: bool) -> &Self {
+        self.site = Some(enabled);
+        self
+    }
+
+    pub fn manager_path(&mut self, path: &str) -> &Self {
+        self.manager_path = Some(path.to_owned());
+        self
+    }
+
+    pub fn spa_fallback(&mut self, enabled: bool) -> &Self {
+        self.spa_fallback = Some(enabled);
+        self
+    }
+
     pub fn build(mut self) -> ServerConfigs {
         let mut config = ServerConfigs::default();
 
@@ -160,6 +722,71 @@ impl ServerConfigsBuilder {
         if let Some(workers) = self.workers.take() {
             config.workers = workers;
         }
+        if let Some(cache_dir) = self.cache_dir.take() {
+            config.cache_dir = cache_dir;
+        }
+        if let Some(max_image_pixels) = self.max_image_pixels.take() {
+            config.max_image_pixels = max_image_pixels;
+        }
+        if let Some(image_concurrency) = self.image_concurrency.take() {
+            config.image_concurrency = image_concurrency;
+        }
+        config.mime_types = self.mime_types;
+        // An archive served as base_dir can't be written to, its server data is kept next to it
+        let writable_dir = match is_archive(&config.base_dir) {
+            true => config.base_dir.parent().unwrap_or(&config.base_dir),
+            false => &config.base_dir,
+        }
+        .to_owned();
+        // Kept in base_dir unless configured, so files can be moved into place atomically
+        config.data_dir = match self.data_dir.take() {
+            Some(data_dir) => data_dir,
+            None => writable_dir.join(DATA_DIR_NAME),
+        };
+        if let Some(max_versions) = self.max_versions.take() {
+            config.max_versions = max_versions;
+        }
+        if let Some(days) = self.version_max_age_days.take() {
+            config.version_max_age_days = days;
+        }
+        if let Some(days) = self.trash_max_age_days.take() {
+            config.trash_max_age_days = days;
+        }
+        if let Some(hours) = self.upload_expiry_hours.take() {
+            config.upload_expiry_hours = hours;
+        }
+        config.s3_port = self.s3_port.take();
+        if let Some(bucket) = self.s3_bucket.take() {
+            config.s3_bucket = bucket;
+        }
+        config.s3_credentials = S3Credentials(self.s3_credentials);
+        let drop_box_dir = config.data_dir.join("drop-boxes");
+        config.drop_boxes = self
+            .drop_boxes
+            .into_iter()
+            .map(|(name, path)| (name, drop_box_dir.join(path)))
+            .collect();
+        if let Some(bytes) = self.drop_box_max_size.take() {
+            config.drop_box_max_size = bytes;
+        }
+        config.drop_box_log = self.drop_box_log.take();
+        config.paste_dir = match self.paste_dir.take() {
+            Some(paste_dir) => paste_dir,
+            None => config.data_dir.join("pastes"),
+        };
+        config.layers = self.layers;
+        if let Some(index_files) = self.index_files {
+            config.index_files = index_files;
+        }
+        if let Some(site) = self.site {
+            config.site = site;
+        }
+        if let Some(manager_path) = self.manager_path {
+            config.manager_path = manager_path;
+        }
+        if let Some(spa_fallback) = self.spa_fallback {
+            config.spa_fallback = spa_fallback;
+        }
 
         config
     }
@@ -0,0 +1,757 @@
+use actix_web::{
+    http::{header::HttpDate, StatusCode},
+    route,
+    web::{self, Data, Path},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
+};
+use file_server_core::dav::{
+    copy_recursive, create_collection, lock_discovery, parse_lockinfo, parse_propfind,
+    parse_proppatch, parse_timeout, submitted_tokens, Depth, LockTable, Multistatus,
+    PropfindRequest, DAV_NAMESPACE,
+};
+use file_server_core::detect::detect_type_by_name;
+use file_server_core::storage::{entity_tag, Storage, StorageMetadata};
+use file_server_core::trash::Trash;
+use file_server_core::versions::VersionStore;
+use file_server_core::*;
+use log::info;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+
+use crate::configs::ServerConfigs;
+use crate::file_server::handlers::{
+    check_writable, request_user, serve_storage_file, write_payload,
+};
+
+const DAV_PREFIX: &str = "/dav/";
+const ALLOWED_METHODS: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, PROPPATCH, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+/// Properties listed for `allprop` and `propname`
+const LIVE_PROPERTIES: [&str; 8] = [
+    "displayname",
+    "resourcetype",
+    "getcontentlength",
+    "getcontenttype",
+    "getlastmodified",
+    "getetag",
+    "supportedlock",
+    "lockdiscovery",
+];
+
+#[route("/dav/{path:.*}", method = "OPTIONS")]
+pub async fn dav_options() -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("DAV", "1, 2"))
+        .insert_header(("Allow", ALLOWED_METHODS))
+        // Makes Windows clients speak WebDAV instead of FrontPage extensions
+        .insert_header(("MS-Author-Via", "DAV"))
+        .finish()
+}
+
+#[route("/dav/{path:.*}", method = "PROPFIND")]
+pub async fn dav_propfind(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    path: Path<String>,
+    body: String,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let depth = match Depth::parse(header_value(&req, "Depth"), Depth::Infinity) {
+        Ok(depth) => depth,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let request = match parse_propfind(&body) {
+        Ok(request) => request,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    let listing_storage = storage.clone();
+    let listed = web::block(move || list_resources(listing_storage.as_ref(), relative_path, depth));
+    let resources = match listed.await {
+        Ok(Ok(resources)) => resources,
+        Ok(Err(err)) if err.kind() == ErrorKind::NotFound => {
+            return HttpResponse::NotFound().body(format!("{} does not exist", &path))
+        }
+        Ok(Err(err)) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to list {}: {}", &path, err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let mut multistatus = Multistatus::default();
+    for resource in &resources {
+        let href = resource.href();
+        let propstats = match &request {
+            PropfindRequest::AllProp => vec![(
+                "HTTP/1.1 200 OK",
+                LIVE_PROPERTIES
+                    .iter()
+                    .filter_map(|name| resource.property(name, &configs.mime_types, &locks))
+                    .collect(),
+            )],
+            PropfindRequest::PropName => vec![(
+                "HTTP/1.1 200 OK",
+                LIVE_PROPERTIES
+                    .iter()
+                    .map(|name| format!("<D:{name}/>"))
+                    .collect(),
+            )],
+            PropfindRequest::Prop(names) => {
+                let (mut found, mut missing) = (String::new(), String::new());
+                for (namespace, name) in names {
+                    let property = (namespace == DAV_NAMESPACE)
+                        .then(|| resource.property(name, &configs.mime_types, &locks))
+                        .flatten();
+                    match property {
+                        Some(property) => found.push_str(&property),
+                        None => missing.push_str(&empty_element(namespace, name)),
+                    }
+                }
+
+                [
+                    ("HTTP/1.1 200 OK", found),
+                    ("HTTP/1.1 404 Not Found", missing),
+                ]
+                .into_iter()
+                .filter(|(_, properties)| !properties.is_empty())
+                .collect()
+            }
+        };
+
+        multistatus.add_propstat(&href, &propstats);
+    }
+
+    multistatus_response(multistatus)
+}
+
+/// Properties can't be changed, every property of the request is refused
+#[route("/dav/{path:.*}", method = "PROPPATCH")]
+pub async fn dav_proppatch(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    path: Path<String>,
+    body: String,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let Ok(metadata) = storage.stat(&relative_path) else {
+        return HttpResponse::NotFound().body(format!("{} does not exist", &path));
+    };
+    if !locks.can_modify(&relative_path, false, &request_tokens(&req)) {
+        return locked_response(&path);
+    }
+
+    let properties = match parse_proppatch(&body) {
+        Ok(properties) => properties,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    let refused = properties
+        .iter()
+        .map(|(namespace, name)| empty_element(namespace, name))
+        .collect();
+    let mut multistatus = Multistatus::default();
+    multistatus.add_propstat(
+        &href(&relative_path, metadata.is_dir),
+        &[("HTTP/1.1 403 Forbidden", refused)],
+    );
+
+    multistatus_response(multistatus)
+}
+
+#[route("/dav/{path:.*}", method = "GET", method = "HEAD")]
+pub async fn dav_get(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+
+    match storage.stat(&relative_path) {
+        Ok(metadata) if metadata.is_dir => HttpResponse::MethodNotAllowed()
+            .insert_header(("Allow", ALLOWED_METHODS))
+            .body(format!("{} is a collection", &path)),
+        Ok(metadata) => {
+            serve_storage_file(
+                &req,
+                &storage,
+                &relative_path,
+                metadata,
+                &configs.mime_types,
+            )
+            .await
+        }
+        Err(_) => HttpResponse::NotFound().body(format!("{} does not exist", &path)),
+    }
+}
+
+/// Creates or overwrites a file, keeping the previous content as a version
+#[route("/dav/{path:.*}", method = "PUT")]
+pub async fn dav_put(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    versions: Data<VersionStore>,
+    path: Path<String>,
+    payload: web::Payload,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    let existed = match storage.stat(&relative_path) {
+        Ok(metadata) if metadata.is_dir => {
+            return HttpResponse::MethodNotAllowed().body(format!("{} is a collection", &path))
+        }
+        Ok(_) => true,
+        Err(_) => false,
+    };
+    if !has_parent_collection(storage.as_ref(), &relative_path) {
+        return HttpResponse::Conflict().body("The parent collection does not exist");
+    }
+    if !locks.can_modify(&relative_path, false, &request_tokens(&req)) {
+        return locked_response(&path);
+    }
+
+    let staging_versions = versions.clone();
+    let (staging_path, staging_file) =
+        match web::block(move || staging_versions.create_staging_file()).await {
+            Ok(Ok(staging)) => staging,
+            Ok(Err(err)) => {
+                return HttpResponse::InternalServerError()
+                    .body(format!("Failed to create staging file: {}", err))
+            }
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+
+    if let Err(err) = write_payload(payload, staging_file).await {
+        let _ = fs::remove_file(&staging_path);
+        return HttpResponse::InternalServerError()
+            .body(format!("Failed to write {}: {}", &path, err));
+    }
+
+    let relative = relative_path.to_string_lossy().into_owned();
+    let replace_path = staging_path.clone();
+    match web::block(move || versions.replace(storage.as_ref(), &relative, &replace_path)).await {
+        Ok(Ok(_)) => {
+            info!("Wrote {} over WebDAV", &path);
+            match existed {
+                true => HttpResponse::NoContent().finish(),
+                false => HttpResponse::Created().finish(),
+            }
+        }
+        Ok(Err(err)) if err.kind() == ErrorKind::InvalidInput => {
+            let _ = fs::remove_file(&staging_path);
+            HttpResponse::BadRequest().body(format!("Failed to write {}: {}", &path, err))
+        }
+        Ok(Err(err)) => {
+            let _ = fs::remove_file(&staging_path);
+            HttpResponse::InternalServerError().body(format!("Failed to write {}: {}", &path, err))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Moves a resource to the trash
+#[route("/dav/{path:.*}", method = "DELETE")]
+pub async fn dav_delete(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    trash: Data<Trash>,
+    path: Path<String>,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    if storage.stat(&relative_path).is_err() {
+        return HttpResponse::NotFound().body(format!("{} does not exist", &path));
+    }
+    if !locks.can_modify(&relative_path, true, &request_tokens(&req)) {
+        return locked_response(&path);
+    }
+
+    let user = request_user(&req);
+    let relative = relative_path.to_string_lossy().into_owned();
+    match web::block(move || trash.delete(storage.as_ref(), &relative, &user)).await {
+        Ok(Ok(entry)) => {
+            info!("{} moved {} to the trash", &entry.deleted_by, &path);
+            locks.remove_all(&relative_path);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(Err(err)) => {
+            HttpResponse::InternalServerError().body(format!("Failed to delete {}: {}", &path, err))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[route("/dav/{path:.*}", method = "MKCOL")]
+pub async fn dav_mkcol(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    path: Path<String>,
+    body: web::Bytes,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    if !body.is_empty() {
+        return HttpResponse::UnsupportedMediaType().body("MKCOL requests can't have a body");
+    }
+    if !locks.can_modify(&relative_path, false, &request_tokens(&req)) {
+        return locked_response(&path);
+    }
+
+    match web::block(move || create_collection(storage.as_ref(), &relative_path)).await {
+        Ok(Ok(())) => HttpResponse::Created().finish(),
+        Ok(Err(err)) if err.kind() == ErrorKind::AlreadyExists => {
+            HttpResponse::MethodNotAllowed().body(format!("{} already exists", &path))
+        }
+        Ok(Err(err)) if err.kind() == ErrorKind::NotFound => {
+            HttpResponse::Conflict().body(err.to_string())
+        }
+        Ok(Err(err)) => {
+            HttpResponse::InternalServerError().body(format!("Failed to create {}: {}", &path, err))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[route("/dav/{path:.*}", method = "COPY")]
+pub async fn dav_copy(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    trash: Data<Trash>,
+    path: Path<String>,
+) -> impl Responder {
+    transfer(req, storage, locks, trash, path, false).await
+}
+
+#[route("/dav/{path:.*}", method = "MOVE")]
+pub async fn dav_move(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    trash: Data<Trash>,
+    path: Path<String>,
+) -> impl Responder {
+    transfer(req, storage, locks, trash, path, true).await
+}
+
+/// Copies or moves a resource to the `Destination` header. A destination that exists is
+/// moved to the trash when it may be overwritten.
+async fn transfer(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    trash: Data<Trash>,
+    path: Path<String>,
+    is_move: bool,
+) -> HttpResponse {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    let Ok(metadata) = storage.stat(&relative_path) else {
+        return HttpResponse::NotFound().body(format!("{} does not exist", &path));
+    };
+
+    let Some(destination) = header_value(&req, "Destination").and_then(destination_path) else {
+        return HttpResponse::BadGateway().body("The Destination must be under /dav/");
+    };
+    let destination_relative = match dav_path(&destination) {
+        Ok(destination_relative) => destination_relative,
+        Err(response) => return response,
+    };
+
+    if destination_relative.starts_with(&relative_path) {
+        return HttpResponse::Forbidden()
+            .body("A resource can't be copied or moved onto itself or its members");
+    }
+    if !has_parent_collection(storage.as_ref(), &destination_relative) {
+        return HttpResponse::Conflict()
+            .body("The parent collection of the destination does not exist");
+    }
+
+    let depth = match Depth::parse(header_value(&req, "Depth"), Depth::Infinity) {
+        Ok(Depth::One) | Err(_) => {
+            return HttpResponse::BadRequest().body("Depth must be 0 or infinity")
+        }
+        Ok(Depth::Zero) if is_move => {
+            return HttpResponse::BadRequest().body("Collections are moved with all their members")
+        }
+        Ok(depth) => depth,
+    };
+
+    let tokens = request_tokens(&req);
+    if (is_move && !locks.can_modify(&relative_path, true, &tokens))
+        || !locks.can_modify(&destination_relative, true, &tokens)
+    {
+        return locked_response(&path);
+    }
+
+    let overwrite = header_value(&req, "Overwrite").map(str::trim) != Some("F");
+    let existed = storage.stat(&destination_relative).is_ok();
+    if existed && !overwrite {
+        return HttpResponse::PreconditionFailed().body(format!("{} already exists", &destination));
+    }
+
+    let user = request_user(&req);
+    let (from, to) = (relative_path.clone(), destination_relative.clone());
+    let transferred = web::block(move || {
+        if existed {
+            trash.delete(storage.as_ref(), &to.to_string_lossy(), &user)?;
+        }
+
+        match (is_move, depth) {
+            (true, _) => storage.rename(&from, &to),
+            (false, Depth::Zero) if metadata.is_dir => storage.create_dir(&to),
+            (false, _) => copy_recursive(storage.as_ref(), &from, &to),
+        }
+    });
+
+    match transferred.await {
+        Ok(Ok(())) => {
+            if is_move {
+                locks.remove_all(&relative_path);
+            }
+            locks.remove_all(&destination_relative);
+
+            match existed {
+                true => HttpResponse::NoContent().finish(),
+                false => HttpResponse::Created().finish(),
+            }
+        }
+        Ok(Err(err)) => HttpResponse::InternalServerError().body(format!(
+            "Failed to transfer {} to {}: {}",
+            &path, &destination, err
+        )),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Takes or refreshes a write lock. Locking a path that doesn't exist creates an empty file.
+#[route("/dav/{path:.*}", method = "LOCK")]
+pub async fn dav_lock(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    locks: Data<LockTable>,
+    path: Path<String>,
+    body: String,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let metadata = storage.stat(&relative_path).ok();
+    let is_dir = metadata.is_some_and(|metadata| metadata.is_dir);
+    let timeout = parse_timeout(header_value(&req, "Timeout"));
+    let href = href(&relative_path, is_dir);
+
+    // Locks are refreshed with an empty body and the token in the If header
+    if body.trim().is_empty() {
+        return match locks.refresh(&relative_path, &request_tokens(&req), timeout) {
+            Some(lock) => lock_response(StatusCode::OK, &lock.token, lock_discovery(&lock, &href)),
+            None => HttpResponse::PreconditionFailed().body("No lock to refresh was submitted"),
+        };
+    }
+
+    let lock_info = match parse_lockinfo(&body) {
+        Ok(lock_info) => lock_info,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let deep = match Depth::parse(header_value(&req, "Depth"), Depth::Infinity) {
+        Ok(Depth::Zero) => false,
+        Ok(Depth::Infinity) => is_dir,
+        Ok(Depth::One) | Err(_) => {
+            return HttpResponse::BadRequest().body("Depth must be 0 or infinity")
+        }
+    };
+
+    let created = metadata.is_none();
+    if created {
+        if let Err(response) = check_writable(storage.as_ref()) {
+            return response;
+        }
+        if !has_parent_collection(storage.as_ref(), &relative_path) {
+            return HttpResponse::Conflict().body("The parent collection does not exist");
+        }
+        if !locks.can_modify(&relative_path, false, &request_tokens(&req)) {
+            return locked_response(&path);
+        }
+    }
+
+    let Some(lock) = locks.lock(
+        &relative_path,
+        lock_info.exclusive,
+        deep,
+        lock_info.owner,
+        timeout,
+    ) else {
+        return locked_response(&path);
+    };
+
+    if created {
+        if let Err(err) = storage.write(&relative_path, &mut io::empty()) {
+            locks.unlock(&relative_path, &lock.token);
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to create {}: {}", &path, err));
+        }
+    }
+
+    let status = match created {
+        true => StatusCode::CREATED,
+        false => StatusCode::OK,
+    };
+    lock_response(status, &lock.token, lock_discovery(&lock, &href))
+}
+
+#[route("/dav/{path:.*}", method = "UNLOCK")]
+pub async fn dav_unlock(
+    req: HttpRequest,
+    locks: Data<LockTable>,
+    path: Path<String>,
+) -> impl Responder {
+    let relative_path = match dav_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let tokens = header_value(&req, "Lock-Token")
+        .map(submitted_tokens)
+        .unwrap_or_default();
+
+    match tokens.first() {
+        Some(token) if locks.unlock(&relative_path, token) => HttpResponse::NoContent().finish(),
+        _ => HttpResponse::Conflict().body(format!("{} is not locked with this token", &path)),
+    }
+}
+
+/// File or collection listed by `PROPFIND`
+struct Resource {
+    relative_path: PathBuf,
+    metadata: StorageMetadata,
+}
+
+impl Resource {
+    fn href(&self) -> String {
+        href(&self.relative_path, self.metadata.is_dir)
+    }
+
+    /// Value of a live property as an XML element, `None` when the resource doesn't have it
+    fn property(
+        &self,
+        name: &str,
+        mime_types: &HashMap<String, String>,
+        locks: &LockTable,
+    ) -> Option<String> {
+        let is_dir = self.metadata.is_dir;
+        let modified = self.metadata.modified;
+
+        let value = match name {
+            "displayname" => escape_html(
+                &self
+                    .relative_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            ),
+            "resourcetype" if is_dir => "<D:collection/>".to_owned(),
+            "resourcetype" => String::new(),
+            "getcontentlength" if !is_dir => self.metadata.len.to_string(),
+            // Sniffing every listed file would be too slow, types are detected by name
+            "getcontenttype" if !is_dir => {
+                detect_type_by_name(&self.relative_path, mime_types).mime_type
+            }
+            "getlastmodified" => HttpDate::from(modified?).to_string(),
+            // The same as the one sent with the content
+            "getetag" if !is_dir => format!("\"{}\"", entity_tag(&self.metadata)),
+            "supportedlock" => "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+                <D:locktype><D:write/></D:locktype></D:lockentry>\
+                <D:lockentry><D:lockscope><D:shared/></D:lockscope>\
+                <D:locktype><D:write/></D:locktype></D:lockentry>"
+                .to_owned(),
+            "lockdiscovery" => locks
+                .locks_on(&self.relative_path)
+                .iter()
+                .map(|lock| lock.to_xml(&href(&lock.path, lock.deep || is_dir)))
+                .collect(),
+            _ => return None,
+        };
+
+        Some(format!("<D:{name}>{value}</D:{name}>"))
+    }
+}
+
+/// Lists a resource and, depending on `depth`, its members with the same directory listing as
+/// the rest of the server. Archives are files here, their members are not listed.
+fn list_resources(
+    storage: &dyn Storage,
+    relative_path: PathBuf,
+    depth: Depth,
+) -> std::io::Result<Vec<Resource>> {
+    let metadata = storage.stat(&relative_path)?;
+    let is_dir = metadata.is_dir;
+    let mut resources = vec![Resource {
+        relative_path: relative_path.clone(),
+        metadata,
+    }];
+
+    if !is_dir || depth == Depth::Zero {
+        return Ok(resources);
+    }
+
+    let mut directory = Directory {
+        name: String::new(),
+        entries: Vec::new(),
+        path: relative_path,
+        layer: None,
+    };
+    match depth {
+        Depth::Infinity => get_directory_structure_recursive(storage, &mut directory)?,
+        _ => get_directory_structure(storage, &mut directory)?,
+    }
+    directory.sort_entries();
+
+    let mut pending: Vec<DirectoryEntry> = directory.entries.into_iter().rev().collect();
+    while let Some(entry) = pending.pop() {
+        let (path, entries) = match entry {
+            DirectoryEntry::Directory(directory) => (directory.path, directory.entries),
+            DirectoryEntry::File { path, .. } => (path, Vec::new()),
+        };
+
+        let Ok(metadata) = storage.stat(&path) else {
+            continue;
+        };
+        if metadata.is_dir {
+            pending.extend(entries.into_iter().rev());
+        }
+
+        resources.push(Resource {
+            relative_path: path,
+            metadata,
+        });
+    }
+
+    Ok(resources)
+}
+
+/// Confines a path of a request to the storage
+fn dav_path(path: &str) -> Result<PathBuf, HttpResponse> {
+    match normalize_path(path) {
+        Ok(relative_path) => Ok(relative_path),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            Err(HttpResponse::Forbidden().body(err.to_string()))
+        }
+        Err(err) => Err(HttpResponse::BadRequest().body(err.to_string())),
+    }
+}
+
+/// Whether the parent collection of a path exists, which new resources need
+fn has_parent_collection(storage: &dyn Storage, relative_path: &std::path::Path) -> bool {
+    relative_path
+        .parent()
+        .is_some_and(|parent| storage.stat(parent).is_ok_and(|metadata| metadata.is_dir))
+}
+
+/// Extracts the path under `/dav/` from a `Destination` header, an absolute URL or path
+fn destination_path(destination: &str) -> Option<String> {
+    let path = match destination.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => destination,
+    };
+    let path = path.strip_prefix(DAV_PREFIX)?;
+
+    percent_decode_str(path)
+        .decode_utf8()
+        .ok()
+        .map(|path| path.into_owned())
+}
+
+/// URL of a path relative to the storage, collections end with a slash
+fn href(relative_path: &std::path::Path, is_dir: bool) -> String {
+    let mut href = String::from(DAV_PREFIX);
+    for (i, segment) in relative_path.iter().enumerate() {
+        if i > 0 {
+            href.push('/');
+        }
+        href.extend(utf8_percent_encode(
+            &segment.to_string_lossy(),
+            PATH_SEGMENT,
+        ));
+    }
+
+    if is_dir && !href.ends_with('/') {
+        href.push('/');
+    }
+    href
+}
+
+fn empty_element(namespace: &str, name: &str) -> String {
+    match namespace {
+        DAV_NAMESPACE => format!("<D:{name}/>"),
+        "" => format!("<{name} xmlns=\"\"/>"),
+        namespace => format!("<{name} xmlns=\"{}\"/>", escape_html(namespace)),
+    }
+}
+
+fn request_tokens(req: &HttpRequest) -> Vec<String> {
+    header_value(req, "If")
+        .map(submitted_tokens)
+        .unwrap_or_default()
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name)?.to_str().ok()
+}
+
+fn multistatus_response(multistatus: Multistatus) -> HttpResponse {
+    HttpResponse::build(StatusCode::MULTI_STATUS)
+        .insert_header(("Content-Type", "application/xml; charset=utf-8"))
+        .body(multistatus.finish())
+}
+
+fn lock_response(status: StatusCode, token: &str, body: String) -> HttpResponse {
+    HttpResponseBuilder::new(status)
+        .insert_header(("Lock-Token", format!("<{}>", token)))
+        .insert_header(("Content-Type", "application/xml; charset=utf-8"))
+        .body(body)
+}
+
+fn locked_response(path: &str) -> HttpResponse {
+    HttpResponse::build(StatusCode::LOCKED).body(format!("{} is locked", path))
+}
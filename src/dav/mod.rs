@@ -0,0 +1,17 @@
+use actix_web::web;
+
+pub mod handlers;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(handlers::dav_options)
+        .service(handlers::dav_propfind)
+        .service(handlers::dav_proppatch)
+        .service(handlers::dav_get)
+        .service(handlers::dav_put)
+        .service(handlers::dav_delete)
+        .service(handlers::dav_mkcol)
+        .service(handlers::dav_copy)
+        .service(handlers::dav_move)
+        .service(handlers::dav_lock)
+        .service(handlers::dav_unlock);
+}
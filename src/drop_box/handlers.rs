@@ -0,0 +1,198 @@
+use std::{fs, io::ErrorKind};
+
+use actix_web::{
+    get,
+    http::header::{self, ContentType},
+    put,
+    web::{self, Data, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use askama::Template;
+use file_server_core::{
+    drop_box::{sanitize_file_name, store_upload, DropBoxLog, DropBoxUpload},
+    versions::VersionStore,
+};
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::{
+    configs::ServerConfigs, drop_box::templates::DropBoxTemplate,
+    file_server::handlers::write_limited_payload,
+};
+
+#[get("/drop/{name}")]
+pub async fn drop_box_page(configs: Data<ServerConfigs>, name: Path<String>) -> HttpResponse {
+    if !configs.drop_boxes.contains_key(name.as_str()) {
+        return HttpResponse::NotFound().body("No such drop box");
+    }
+
+    let template = DropBoxTemplate {
+        name: &name,
+        max_size_mb: configs.drop_box_max_size.div_ceil(1024 * 1024),
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DropBoxUploadQuery {
+    /// Name of the file on the uploader's side
+    name: String,
+}
+
+/// Stores the request body in a drop box. The response doesn't tell the name the file was
+/// stored under, which would reveal the names of files already received.
+#[put("/drop/{name}/upload")]
+pub async fn drop_box_upload(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    versions: Data<VersionStore>,
+    name: Path<String>,
+    query: Query<DropBoxUploadQuery>,
+    payload: web::Payload,
+) -> HttpResponse {
+    let Some(dir) = configs.drop_boxes.get(name.as_str()).cloned() else {
+        return HttpResponse::NotFound().body("No such drop box");
+    };
+
+    let max_size = configs.drop_box_max_size;
+    let content_length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok());
+    if content_length.is_some_and(|length| length > max_size) {
+        return HttpResponse::PayloadTooLarge()
+            .body(format!("Files are limited to {max_size} bytes"));
+    }
+
+    let staging_versions = versions.clone();
+    let (staging_path, staging_file) =
+        match web::block(move || staging_versions.create_staging_file()).await {
+            Ok(Ok(staging)) => staging,
+            Ok(Err(err)) => return HttpResponse::InternalServerError().body(err.to_string()),
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+    let size = match write_limited_payload(payload, staging_file, max_size).await {
+        Ok(size) => size,
+        Err(err) => {
+            let _ = fs::remove_file(&staging_path);
+            return match err.kind() {
+                ErrorKind::FileTooLarge => HttpResponse::PayloadTooLarge().body(err.to_string()),
+                _ => HttpResponse::InternalServerError().body(format!("Failed to upload: {err}")),
+            };
+        }
+    };
+
+    let original_name = query.into_inner().name;
+    let file_name = sanitize_file_name(&original_name);
+    let cleanup_path = staging_path.clone();
+    let stored_name = match web::block(move || store_upload(&dir, &file_name, &staging_path)).await
+    {
+        Ok(Ok(stored_name)) => stored_name,
+        Ok(Err(err)) => {
+            let _ = fs::remove_file(&cleanup_path);
+            warn!("Failed to store an upload to drop box {}: {}", name, err);
+            return HttpResponse::InternalServerError().body("Failed to store the file");
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let remote = req
+        .connection_info()
+        .realip_remote_addr()
+        .unwrap_or("unknown")
+        .to_owned();
+    let upload = DropBoxUpload::new(&name, &original_name, &stored_name, size, &remote);
+    info!(
+        "Drop box {} received {} ({} B) from {}",
+        upload.drop_box, upload.stored_name, upload.size, upload.remote
+    );
+    if let Some(log) = req.app_data::<Data<DropBoxLog>>().cloned() {
+        match web::block(move || log.record(&upload)).await {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => warn!("Failed to record a drop box upload: {}", err),
+            Err(err) => warn!("Failed to record a drop box upload: {}", err),
+        }
+    }
+
+    HttpResponse::Created().finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, sync::Arc};
+
+    use actix_web::{
+        http::StatusCode,
+        test::{self, TestRequest},
+        App,
+    };
+    use file_server_core::{
+        images::ImageLimits,
+        storage::{LocalStorage, Storage},
+        versions::Retention,
+    };
+
+    use super::*;
+    use crate::{drop_box, file_server};
+
+    #[actix_web::test]
+    async fn uploads_are_not_served() {
+        let base_dir =
+            std::env::temp_dir().join(format!("file-server-test-{:016x}", rand::random::<u64>()));
+        let mut builder = ServerConfigs::builder();
+        builder.base_dir(&base_dir);
+        builder.data_dir(&base_dir.join(".file-server"));
+        builder.drop_box("partners", &PathBuf::from("partners"));
+        let configs = builder.build();
+        fs::create_dir_all(&configs.drop_boxes["partners"]).unwrap();
+
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(&base_dir));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(configs.clone()))
+                .app_data(Data::from(storage))
+                .app_data(Data::new(ImageLimits::new(1_000_000, 1)))
+                .app_data(Data::new(VersionStore::new(
+                    &configs.data_dir,
+                    Retention {
+                        max_count: 0,
+                        max_age: None,
+                    },
+                )))
+                .configure(file_server::config)
+                .configure(drop_box::config),
+        )
+        .await;
+
+        let req = TestRequest::put()
+            .uri("/drop/partners/upload?name=report.txt")
+            .set_payload("confidential")
+            .to_request();
+        assert_eq!(
+            test::call_service(&app, req).await.status(),
+            StatusCode::CREATED
+        );
+        assert_eq!(
+            fs::read_to_string(configs.drop_boxes["partners"].join("report.txt")).unwrap(),
+            "confidential"
+        );
+
+        for uri in [
+            "/api/v1/files/.file-server/drop-boxes/partners/report.txt",
+            "/api/v1/files/partners/report.txt",
+            "/api/v1/files/report.txt",
+        ] {
+            let status = test::call_service(&app, TestRequest::get().uri(uri).to_request())
+                .await
+                .status();
+            assert!(status.is_client_error(), "{uri} answered {status}");
+        }
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
@@ -0,0 +1,9 @@
+use actix_web::web;
+
+pub mod handlers;
+pub mod templates;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(handlers::drop_box_page)
+        .service(handlers::drop_box_upload);
+}
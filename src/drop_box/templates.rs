@@ -0,0 +1,9 @@
+use askama::Template;
+
+/// Upload page of a drop box, which never shows what it received
+#[derive(Debug, Template)]
+#[template(path = "drop-box.html", escape = "none")]
+pub struct DropBoxTemplate<'a> {
+    pub name: &'a str,
+    pub max_size_mb: u64,
+}
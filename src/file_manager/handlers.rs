@@ -1,28 +1,69 @@
-use std::{fs, path::PathBuf};
+use std::path::PathBuf;
 
 use actix_web::{
-    get,
+    delete, get,
     http::header::ContentType,
-    web::{Data, Path, Query},
+    post,
+    web::{self, Data, Path, Query},
     HttpResponse, Responder,
 };
 use askama::Template;
+use encoding_rs::UTF_8;
+use file_server_core::charset::{
+    decode, detect_encoding, encoding_for_label, resolve_line_encoding, resolve_storage_encoding,
+    COMMON_ENCODINGS,
+};
+use file_server_core::detect::{describe, detect_type, detect_type_with_header};
+use file_server_core::diff::{diff_hunks, read_text, DEFAULT_CONTEXT};
+use file_server_core::hex::{
+    find_pattern, parse_offset, parse_pattern, read_range, HEX_BYTES_PER_ROW,
+};
+use file_server_core::highlight::{
+    find_syntax, highlight_css, highlight_to_html, MAX_HIGHLIGHT_SIZE,
+};
+use file_server_core::images::DEFAULT_THUMBNAIL_SIZE;
+use file_server_core::lines::{read_lines, LineIndexCache};
+use file_server_core::metadata::extract_metadata;
+use file_server_core::render::{
+    parse_tree, pretty_print, read_table, render_markdown, render_tree, Renderer, TableSort,
+    MAX_RENDER_SIZE,
+};
+use file_server_core::storage::{self, read_header, read_to_end, Storage};
+use file_server_core::trash::Trash;
 use file_server_core::*;
 
 use serde::Deserialize;
 
-use crate::file_manager::templates::{HomePageTemplate, ProgramListTemplate};
+use crate::file_manager::templates::{
+    DiffTemplate, GalleryEntryTemplate, GalleryTemplate, HexRowTemplate, HexViewTemplate,
+    HomePageTemplate, LinePageTemplate, MetadataTemplate, ProgramListTemplate, RichPreviewTemplate,
+    SourcePreviewTemplate, TableHeaderTemplate, TrashEntryTemplate, TrashTemplate,
+};
 use crate::{
     configs::ServerConfigs,
     file_manager::templates::{DirectoryTemplate, FileContentTemplate},
+    file_server::handlers::{
+        find_storage_archive, is_storage_file, list_storage_archive, local_path, storage_path,
+    },
 };
 
 const CSS_FILE: &[u8] = include_bytes!("../../public/css/main.css");
 
 #[get("/")]
-pub async fn home_page() -> impl Responder {
-    let css_content = String::from_utf8(Vec::from(CSS_FILE)).unwrap_or("".to_string());
-    let template = HomePageTemplate { css_content }.render().unwrap();
+pub async fn home_page(configs: Data<ServerConfigs>) -> impl Responder {
+    manager_page(configs).await
+}
+
+/// Page of the file manager, served at `/` unless the site mode serves the files there
+pub async fn manager_page(configs: Data<ServerConfigs>) -> HttpResponse {
+    let mut css_content = String::from_utf8(Vec::from(CSS_FILE)).unwrap_or("".to_string());
+    css_content.push_str(highlight_css());
+    let template = HomePageTemplate {
+        css_content,
+        manager_api: &configs.manager_api(),
+    }
+    .render()
+    .unwrap();
 
     HttpResponse::Ok()
         .insert_header(ContentType::html())
@@ -43,37 +84,44 @@ pub struct FileManagerDirectoryStructureQuery {
     pub expanded: Option<bool>,
 }
 
-#[get("/manager/api/v1/directory-structure/{path:.*}")]
+#[get("/api/v1/directory-structure/{path:.*}")]
 pub async fn directory_structure_template(
     configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
     path: Path<String>,
     query: Query<FileManagerDirectoryStructureQuery>,
 ) -> impl Responder {
-    let mut root_dir_path = configs.base_dir.clone();
+    let root_dir_path = match storage_path(&path) {
+        Ok(root_dir_path) => root_dir_path,
+        Err(response) => return response,
+    };
 
-    // Remove prefix '/' for queries not pointing to base_dir
-    // TODO: Solve this in a cleaner way (at the type level)
-    let mut path = path.into_inner();
-    if !path.is_empty() && path.starts_with('/') {
-        // Remove the '/'
-        path.remove(0);
-    }
+    // Archives and directories inside of them expand like directories on disk
+    let archive_path =
+        find_storage_archive(&storage, &root_dir_path).map(|(archive_path, _)| archive_path);
 
-    root_dir_path.push(path);
+    if archive_path.is_none() {
+        let metadata = match storage.stat(&root_dir_path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                return HttpResponse::BadRequest()
+                    .body(format!("Failed to get metadata for: {:?}", root_dir_path))
+            }
+        };
 
-    let metadata = match fs::metadata(&root_dir_path) {
-        Ok(metadata) => metadata,
-        Err(_) => {
+        if !metadata.is_dir {
             return HttpResponse::BadRequest()
-                .body(format!("Failed to get metadata for: {:?}", root_dir_path))
-        }
-    };
-
-    if !metadata.is_dir() {
-        return HttpResponse::BadRequest().body(format!("{:?} is not a directory", root_dir_path));
-    };
+                .body(format!("{:?} is not a directory", root_dir_path));
+        };
+    }
 
-    let name = match root_dir_path.file_name() {
+    // The root is named after its directory on the local disk, when there is one
+    let local_dir_path = storage.local_path(&root_dir_path);
+    let name = match local_dir_path
+        .as_deref()
+        .unwrap_or(&root_dir_path)
+        .file_name()
+    {
         Some(file_name) => file_name.to_str().unwrap_or("Unknown Filename").to_owned(),
         None => "Unknown Filename".to_owned(),
     };
@@ -82,15 +130,14 @@ pub async fn directory_structure_template(
         name,
         path: root_dir_path,
         entries: Vec::new(),
+        layer: None,
     };
 
     // Return early if expanded
     if let Some(expanded) = query.expanded {
         if expanded {
-            base_dir.sanitize_path(&configs.base_dir.to_string_lossy());
-
             let template = ProgramListTemplate {
-                base_dir: DirectoryTemplate::from(&base_dir),
+                base_dir: DirectoryTemplate::new(&base_dir, &configs.manager_api()),
                 expanded: false,
             }
             .render()
@@ -102,46 +149,124 @@ pub async fn directory_structure_template(
         }
     }
 
-    let get_dir_structure_result = match query.recursive {
-        Some(recursive) if recursive => get_directory_structure_recursive(&mut base_dir),
-        _ => get_directory_structure(&mut base_dir),
+    let get_dir_structure_result = match (&archive_path, query.recursive) {
+        (Some(archive_path), recursive) => list_storage_archive(
+            &storage,
+            &mut base_dir,
+            archive_path,
+            recursive.unwrap_or(false),
+        ),
+        (None, Some(recursive)) if recursive => {
+            get_directory_structure_recursive(storage.as_ref(), &mut base_dir)
+        }
+        _ => get_directory_structure(storage.as_ref(), &mut base_dir),
     };
 
     base_dir.sort_entries();
 
     match get_dir_structure_result {
         Ok(_) => {
-            base_dir.sanitize_path(&configs.base_dir.to_string_lossy());
-
             let template = ProgramListTemplate {
-                base_dir: DirectoryTemplate::from(&base_dir),
+                base_dir: DirectoryTemplate::new(&base_dir, &configs.manager_api()),
                 expanded: true,
             }
             .render()
             .unwrap();
 
-            return HttpResponse::Ok()
+            HttpResponse::Ok()
                 .insert_header(ContentType::plaintext())
-                .body(template);
+                .body(template)
         }
         Err(err) => HttpResponse::BadRequest().body(err.to_string()),
     }
 }
 
-#[get("/manager/api/v1/file-content/{path:.*}")]
-pub async fn file_content(path: Path<PathBuf>) -> impl Responder {
+#[derive(Debug, Deserialize)]
+pub struct FileContentQuery {
+    pub raw: Option<bool>,
+    /// Overrides the detected charset of text
+    pub charset: Option<String>,
+}
+
+#[get("/api/v1/file-content/{path:.*}")]
+pub async fn file_content(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<PathBuf>,
+    query: Query<FileContentQuery>,
+) -> impl Responder {
     let path = path.into_inner();
     let name = path.file_name().unwrap().to_str().unwrap();
-    let mime_type = mime_guess::from_path(name)
-        .first_raw()
-        .unwrap_or("text/plain");
-    let media_type = MediaType::from(mime_type);
+    let file_path = match storage_path(&path.to_string_lossy()) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    let header = read_header(storage.as_ref(), &file_path).ok();
+    let detected_type = detect_type_with_header(&file_path, header.as_deref(), &configs.mime_types);
+    let mime_type = detected_type.mime_type.as_str();
+    let mut media_type = detected_type.media_type();
+
+    let file_size = storage
+        .stat(&file_path)
+        .map(|metadata| metadata.len)
+        .unwrap_or(u64::MAX);
+
+    // Prefer a highlighted preview for anything with a known syntax that isn't too large
+    if matches!(media_type, MediaType::TEXT | MediaType::OTHER)
+        && file_size <= MAX_HIGHLIGHT_SIZE
+        && find_syntax(&file_path, header.as_deref().unwrap_or_default()).is_some()
+    {
+        media_type = MediaType::SOURCE;
+    }
+
+    let renderer = Renderer::from_path(&path).filter(|_| file_size <= MAX_RENDER_SIZE);
+
+    let charset = match query.charset.as_deref().filter(|label| !label.is_empty()) {
+        Some(label) => match encoding_for_label(label) {
+            Some(encoding) => Some(encoding),
+            None => return HttpResponse::BadRequest().body(format!("Unknown charset {}", label)),
+        },
+        None => None,
+    };
+    let is_text = matches!(media_type, MediaType::TEXT | MediaType::SOURCE) || renderer.is_some();
+    let detected_charset = header.as_deref().map(detect_encoding).unwrap_or(UTF_8);
+    let encoding = charset.unwrap_or(detected_charset);
+
+    let in_archive = find_storage_archive(&storage, &file_path)
+        .is_some_and(|(_, member_path)| !member_path.as_os_str().is_empty());
+    let read_whole = in_archive || !encoding.is_ascii_compatible();
+
+    // Text that can't be read by line is only decoded whole up to the render limit, larger
+    // files are previewed as hex. Archive members are already read within that limit.
+    if read_whole
+        && !in_archive
+        && matches!(media_type, MediaType::TEXT)
+        && file_size > MAX_RENDER_SIZE
+    {
+        media_type = MediaType::OTHER;
+    }
 
     let template = FileContentTemplate {
         name,
         path: path.to_str().unwrap_or(name),
         media_type,
         mime_type: Some(mime_type),
+        renderer,
+        raw: query.raw.unwrap_or(false),
+        read_whole,
+        charset: charset.map(|charset| charset.name()),
+        detected_charset: is_text.then(|| detected_charset.name()),
+        encodings: COMMON_ENCODINGS
+            .iter()
+            .map(|common| {
+                (
+                    common.name(),
+                    Some(common.name()) == charset.map(|c| c.name()),
+                )
+            })
+            .collect(),
+        manager_api: &configs.manager_api(),
     }
     .render()
     .unwrap();
@@ -150,3 +275,647 @@ pub async fn file_content(path: Path<PathBuf>) -> impl Responder {
         .insert_header(ContentType::plaintext())
         .body(template)
 }
+
+/// Number of lines loaded each time the text preview is scrolled to its end
+const LINES_PER_PAGE: usize = 500;
+
+#[derive(Debug, Deserialize)]
+pub struct FileManagerLinesQuery {
+    pub from: Option<usize>,
+    pub charset: Option<String>,
+}
+
+#[get("/api/v1/lines/{path:.*}")]
+pub async fn lines_template(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    line_indices: Data<LineIndexCache>,
+    path: Path<String>,
+    query: Query<FileManagerLinesQuery>,
+) -> impl Responder {
+    let relative_path = match storage_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let file_path = match local_path(&storage, &relative_path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    let encoding =
+        match resolve_line_encoding(storage.as_ref(), &relative_path, query.charset.as_deref()) {
+            Ok(encoding) => encoding,
+            Err(message) => return HttpResponse::BadRequest().body(message),
+        };
+
+    let from = query.from.unwrap_or(0);
+    let line_range = match web::block(move || {
+        line_indices
+            .get(&file_path)
+            .and_then(|index| read_lines(&file_path, &index, from, from + LINES_PER_PAGE, encoding))
+    })
+    .await
+    {
+        Ok(Ok(line_range)) => line_range,
+        Ok(Err(err)) => {
+            return HttpResponse::BadRequest()
+                .body(format!("Failed to read lines of {}: {}", &path, err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let next_line = from + line_range.lines.len();
+    let lines = line_range
+        .lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| (from + i + 1, escape_html(line)))
+        .collect();
+
+    let template = LinePageTemplate {
+        path: &path,
+        lines,
+        next_line: (next_line < line_range.total_lines.unwrap_or(0)).then_some(next_line),
+        manager_api: &configs.manager_api(),
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CharsetQuery {
+    pub charset: Option<String>,
+}
+
+#[get("/api/v1/highlight/{path:.*}")]
+pub async fn highlight_template(
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+    query: Query<CharsetQuery>,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    let header = read_header(storage.as_ref(), &file_path).unwrap_or_default();
+    let syntax = match find_syntax(&file_path, &header) {
+        Some(syntax) => syntax,
+        None => return HttpResponse::BadRequest().body(format!("No known syntax for {}", &path)),
+    };
+
+    let encoding =
+        match resolve_storage_encoding(storage.as_ref(), &file_path, query.charset.as_deref()) {
+            Ok(encoding) => encoding,
+            Err(message) => return HttpResponse::BadRequest().body(message),
+        };
+
+    // Files past the limit are refused rather than tying up a thread highlighting them
+    let storage = storage.into_inner();
+    let (line_count, highlighted_content) = match web::block(move || {
+        storage::read_to_end(storage.as_ref(), &file_path, MAX_HIGHLIGHT_SIZE).map(|content| {
+            let content = decode(&content, encoding);
+            (
+                content.lines().count().max(1),
+                highlight_to_html(&content, syntax),
+            )
+        })
+    })
+    .await
+    {
+        Ok(Ok(highlighted)) => highlighted,
+        Ok(Err(err)) => {
+            return HttpResponse::BadRequest().body(format!("Failed to read {}: {}", &path, err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let template = SourcePreviewTemplate {
+        line_count,
+        highlighted_content,
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+/// Number of rows shown per page of a table preview
+const TABLE_ROWS_PER_PAGE: usize = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct RenderQuery {
+    pub page: Option<usize>,
+    pub sort: Option<usize>,
+    pub descending: Option<bool>,
+    pub pretty: Option<bool>,
+    pub charset: Option<String>,
+}
+
+#[get("/api/v1/render/{path:.*}")]
+pub async fn render_template(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+    query: Query<RenderQuery>,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    let renderer = match Renderer::from_path(&file_path) {
+        Some(renderer) => renderer,
+        None => return HttpResponse::BadRequest().body(format!("No renderer for {}", &path)),
+    };
+
+    match storage.stat(&file_path) {
+        Ok(metadata) if metadata.len <= MAX_RENDER_SIZE => {}
+        Ok(_) => {
+            return HttpResponse::BadRequest().body(format!("{} is too large to render", &path))
+        }
+        Err(err) => {
+            return HttpResponse::BadRequest()
+                .body(format!("Failed to get metadata for {}: {}", &path, err))
+        }
+    }
+
+    let encoding =
+        match resolve_storage_encoding(storage.as_ref(), &file_path, query.charset.as_deref()) {
+            Ok(encoding) => encoding,
+            Err(message) => return HttpResponse::BadRequest().body(message),
+        };
+
+    let manager_api = configs.manager_api();
+    let template = match renderer {
+        Renderer::Markdown => {
+            read_to_end(storage.as_ref(), &file_path, MAX_RENDER_SIZE).map(|content| {
+                let base_path = std::path::Path::new(path.as_str())
+                    .parent()
+                    .map(|parent| parent.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+
+                RichPreviewTemplate::Markdown {
+                    html: render_markdown(&decode(&content, encoding), &base_path),
+                }
+            })
+        }
+        Renderer::Table { delimiter } => {
+            let sort = query.sort.map(|column| TableSort {
+                column,
+                descending: query.descending.unwrap_or(false),
+            });
+            let page = query.page.unwrap_or(0);
+            // Tables are sorted by a library reading them from the disk
+            let file_path = match local_path(&storage, &file_path) {
+                Ok(file_path) => file_path,
+                Err(response) => return response,
+            };
+
+            read_table(
+                &file_path,
+                delimiter,
+                encoding,
+                sort,
+                page,
+                TABLE_ROWS_PER_PAGE,
+            )
+            .map(|table| {
+                let page_count = table.total_rows.div_ceil(TABLE_ROWS_PER_PAGE).max(1);
+                let headers = table
+                    .headers
+                    .iter()
+                    .enumerate()
+                    .map(|(column, header)| {
+                        let sorted_descending = sort
+                            .filter(|sort| sort.column == column)
+                            .map(|sort| sort.descending);
+
+                        TableHeaderTemplate {
+                            name: escape_html(header),
+                            indicator: match sorted_descending {
+                                Some(true) => "▼",
+                                Some(false) => "▲",
+                                None => "",
+                            },
+                            next_descending: sorted_descending == Some(false),
+                        }
+                    })
+                    .collect();
+
+                RichPreviewTemplate::Table {
+                    path: &path,
+                    headers,
+                    rows: table
+                        .rows
+                        .iter()
+                        .map(|row| row.iter().map(|cell| escape_html(cell)).collect())
+                        .collect(),
+                    page: page + 1,
+                    page_count,
+                    previous_page: page.checked_sub(1),
+                    next_page: (page + 1 < page_count).then_some(page + 1),
+                    sort,
+                    manager_api: &manager_api,
+                }
+            })
+        }
+        Renderer::Tree(format) => read_to_end(storage.as_ref(), &file_path, MAX_RENDER_SIZE)
+            .and_then(|content| parse_tree(&decode(&content, encoding), format))
+            .map(|value| {
+                let pretty = query.pretty.unwrap_or(false);
+                let html = if pretty {
+                    escape_html(&pretty_print(&value, format))
+                } else {
+                    render_tree(&value)
+                };
+
+                RichPreviewTemplate::Tree {
+                    path: &path,
+                    html,
+                    pretty,
+                    manager_api: &manager_api,
+                }
+            }),
+    };
+
+    match template {
+        Ok(template) => HttpResponse::Ok()
+            .insert_header(ContentType::html())
+            .body(template.render().unwrap()),
+        Err(err) => HttpResponse::BadRequest().body(format!("Failed to render {}: {}", &path, err)),
+    }
+}
+
+#[get("/api/v1/gallery/{path:.*}")]
+pub async fn gallery_template(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+) -> impl Responder {
+    let root_dir_path = match storage_path(&path) {
+        Ok(root_dir_path) => root_dir_path,
+        Err(response) => return response,
+    };
+
+    if !storage
+        .stat(&root_dir_path)
+        .is_ok_and(|metadata| metadata.is_dir)
+    {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a directory", root_dir_path));
+    }
+
+    // The root is named after its directory on the local disk, when there is one
+    let local_dir_path = storage.local_path(&root_dir_path);
+    let name = match local_dir_path
+        .as_deref()
+        .unwrap_or(&root_dir_path)
+        .file_name()
+    {
+        Some(file_name) => file_name.to_str().unwrap_or("Unknown Filename").to_owned(),
+        None => "Unknown Filename".to_owned(),
+    };
+
+    let mut base_dir = Directory {
+        name,
+        path: root_dir_path,
+        entries: Vec::new(),
+        layer: None,
+    };
+
+    if let Err(err) = get_directory_structure(storage.as_ref(), &mut base_dir) {
+        return HttpResponse::BadRequest().body(err.to_string());
+    }
+
+    base_dir.sort_entries();
+
+    let path = path.trim_matches('/');
+    let parent_path = (!path.is_empty()).then(|| {
+        std::path::Path::new(path)
+            .parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+
+    let template = GalleryTemplate {
+        name: &base_dir.name,
+        parent_path,
+        entries: base_dir
+            .entries
+            .iter()
+            .map(GalleryEntryTemplate::from)
+            .collect(),
+        thumbnail_size: DEFAULT_THUMBNAIL_SIZE,
+        manager_api: &configs.manager_api(),
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+#[get("/api/v1/metadata/{path:.*}")]
+pub async fn metadata_template(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+) -> impl Responder {
+    let file_path = match storage_path(&path).and_then(|path| local_path(&storage, &path)) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !file_path.is_file() {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", file_path));
+    }
+
+    match web::block(move || {
+        let mime_type = detect_type(&file_path, &configs.mime_types).mime_type;
+        extract_metadata(&file_path, &mime_type)
+    })
+    .await
+    {
+        Ok(Ok(metadata)) => HttpResponse::Ok()
+            .insert_header(ContentType::html())
+            .body(MetadataTemplate::from(&metadata).render().unwrap()),
+        Ok(Err(err)) => HttpResponse::BadRequest()
+            .body(format!("Failed to read metadata of {}: {}", &path, err)),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Number of rows of a hex view page
+const HEX_ROWS_PER_PAGE: usize = 32;
+
+#[derive(Debug, Deserialize)]
+pub struct HexViewQuery {
+    pub offset: Option<String>,
+    /// Byte pattern searched for, starting at `from`
+    pub pattern: Option<String>,
+    pub from: Option<String>,
+}
+
+#[get("/api/v1/hex/{path:.*}")]
+pub async fn hex_template(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+    query: Query<HexViewQuery>,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !is_storage_file(&storage, &file_path) {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", file_path));
+    }
+
+    let page_size = (HEX_ROWS_PER_PAGE * HEX_BYTES_PER_ROW) as u64;
+    let mut message = None;
+
+    let mut offset = match query.offset.as_deref().filter(|offset| !offset.is_empty()) {
+        Some(offset) => parse_offset(offset).unwrap_or_else(|err| {
+            message = Some(err);
+            0
+        }),
+        None => 0,
+    };
+
+    // Bytes of the match highlighted in the page
+    let mut highlight = None;
+    let pattern = query.pattern.clone().unwrap_or_default();
+    if !pattern.trim().is_empty() {
+        let from = query
+            .from
+            .as_deref()
+            .map(parse_offset)
+            .unwrap_or(Ok(offset));
+
+        match (parse_pattern(&pattern), from) {
+            (Ok(bytes), Ok(from)) => {
+                let search_storage = storage.clone();
+                let search_path = file_path.clone();
+                let length = bytes.len() as u64;
+                match web::block(move || {
+                    find_pattern(search_storage.as_ref(), &search_path, &bytes, from)
+                })
+                .await
+                {
+                    Ok(Ok(Some(position))) => {
+                        offset = position;
+                        highlight = Some(position..position + length);
+                    }
+                    Ok(Ok(None)) => {
+                        message = Some(format!("Pattern not found after offset {from:#x}"));
+                        offset = from;
+                    }
+                    Ok(Err(err)) => message = Some(format!("Failed to search: {}", err)),
+                    Err(err) => message = Some(err.to_string()),
+                }
+            }
+            (Err(err), _) | (_, Err(err)) => message = Some(err),
+        }
+    }
+
+    let page_offset = offset - offset % HEX_BYTES_PER_ROW as u64;
+    let range = match read_range(storage.as_ref(), &file_path, page_offset, page_size) {
+        Ok(range) => range,
+        Err(err) => {
+            return HttpResponse::BadRequest().body(format!("Failed to read {}: {}", &path, err))
+        }
+    };
+
+    let rows = range
+        .bytes
+        .chunks(HEX_BYTES_PER_ROW)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let row_offset = range.offset + (i * HEX_BYTES_PER_ROW) as u64;
+            HexRowTemplate::new(row_offset, bytes, highlight.as_ref())
+        })
+        .collect();
+
+    let end = range.offset + range.bytes.len() as u64;
+    let description = read_header(storage.as_ref(), &file_path)
+        .ok()
+        .and_then(|header| describe(&header));
+
+    let template = HexViewTemplate {
+        path: &path,
+        description,
+        file_size: range.file_size,
+        rows,
+        offset: format!("{:#x}", range.offset),
+        pattern: escape_html(&pattern).replace('"', "&quot;"),
+        search_from: highlight
+            .as_ref()
+            .map(|highlight| highlight.start + 1)
+            .unwrap_or(range.offset),
+        previous_offset: (range.offset > 0).then(|| range.offset.saturating_sub(page_size)),
+        next_offset: (end < range.file_size).then_some(end),
+        message: message.map(|message| escape_html(&message)),
+        manager_api: &configs.manager_api(),
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffViewQuery {
+    pub a: String,
+    pub b: String,
+    pub context: Option<usize>,
+}
+
+#[get("/api/v1/diff")]
+pub async fn diff_template(
+    storage: Data<dyn Storage>,
+    query: Query<DiffViewQuery>,
+) -> impl Responder {
+    let query = query.into_inner();
+    let mut file_paths = Vec::with_capacity(2);
+    for path in [&query.a, &query.b] {
+        let file_path = match storage_path(path) {
+            Ok(file_path) => file_path,
+            Err(response) => return response,
+        };
+
+        if !is_storage_file(&storage, &file_path) {
+            return HttpResponse::BadRequest().body(format!("{:?} is not a file", file_path));
+        }
+        file_paths.push(file_path);
+    }
+
+    let (a_path, b_path) = (file_paths.remove(0), file_paths.remove(0));
+    let texts = web::block(move || {
+        Ok::<_, std::io::Error>((
+            read_text(storage.as_ref(), &a_path)?,
+            read_text(storage.as_ref(), &b_path)?,
+        ))
+    });
+    let (old, new) = match texts.await {
+        Ok(Ok(texts)) => texts,
+        Ok(Err(err)) => {
+            return HttpResponse::BadRequest().body(format!("Failed to compare files: {}", err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let hunks = diff_hunks(&old, &new, query.context.unwrap_or(DEFAULT_CONTEXT))
+        .into_iter()
+        .map(|hunk| hunk.into_split().into())
+        .collect();
+
+    let template = DiffTemplate {
+        a: escape_html(&query.a).replace('"', "&quot;"),
+        b: escape_html(&query.b).replace('"', "&quot;"),
+        hunks,
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+#[get("/api/v1/trash")]
+pub async fn trash_template(configs: Data<ServerConfigs>, trash: Data<Trash>) -> impl Responder {
+    render_trash(&configs, trash, None).await
+}
+
+#[post("/api/v1/trash/{id}/restore")]
+pub async fn restore_trash_template(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    trash: Data<Trash>,
+    id: Path<String>,
+) -> impl Responder {
+    // Failures are shown in the trash page, which isn't swapped in for error statuses
+    if storage.is_read_only() {
+        let message = "Failed to restore: the served files are read-only".to_owned();
+        return render_trash(&configs, trash, Some(message)).await;
+    }
+
+    let restoring_trash = trash.clone();
+    let restored = web::block(move || restoring_trash.restore(storage.as_ref(), &id));
+    let message = match restored.await {
+        Ok(Ok(entry)) => format!("Restored {}", entry.original_path),
+        Ok(Err(err)) => format!("Failed to restore: {}", err),
+        Err(err) => err.to_string(),
+    };
+
+    render_trash(&configs, trash, Some(message)).await
+}
+
+#[delete("/api/v1/trash/{id}")]
+pub async fn purge_trash_entry_template(
+    configs: Data<ServerConfigs>,
+    trash: Data<Trash>,
+    id: Path<String>,
+) -> impl Responder {
+    let purging_trash = trash.clone();
+    let message = match web::block(move || purging_trash.purge(&id)).await {
+        Ok(Ok(entry)) => format!("Permanently deleted {}", entry.original_path),
+        Ok(Err(err)) => format!("Failed to delete: {}", err),
+        Err(err) => err.to_string(),
+    };
+
+    render_trash(&configs, trash, Some(message)).await
+}
+
+#[delete("/api/v1/trash")]
+pub async fn purge_trash_template(
+    configs: Data<ServerConfigs>,
+    trash: Data<Trash>,
+) -> impl Responder {
+    let purging_trash = trash.clone();
+    let message = match web::block(move || purging_trash.purge_all()).await {
+        Ok(Ok(purged)) => format!("Permanently deleted {} entries", purged),
+        Ok(Err(err)) => format!("Failed to empty the trash: {}", err),
+        Err(err) => err.to_string(),
+    };
+
+    render_trash(&configs, trash, Some(message)).await
+}
+
+/// Renders the entries in the trash along with the result of the action taken on them
+async fn render_trash(
+    configs: &ServerConfigs,
+    trash: Data<Trash>,
+    message: Option<String>,
+) -> HttpResponse {
+    let entries = match web::block(move || trash.list()).await {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(err)) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to list the trash: {}", err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let template = TrashTemplate {
+        entries: entries.into_iter().map(TrashEntryTemplate::from).collect(),
+        message: message.map(|message| escape_html(&message)),
+        manager_api: &configs.manager_api(),
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
@@ -4,8 +4,22 @@ pub mod handlers;
 pub mod templates;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
-    cfg.service(handlers::home_page)
-        .service(handlers::favicon)
-        .service(handlers::directory_structure_template)
-        .service(handlers::file_content);
+    cfg.service(handlers::home_page).service(handlers::favicon);
+}
+
+/// Routes of the manager besides its page, mounted under `ServerConfigs::manager_scope`
+pub fn api_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(handlers::directory_structure_template)
+        .service(handlers::file_content)
+        .service(handlers::lines_template)
+        .service(handlers::highlight_template)
+        .service(handlers::render_template)
+        .service(handlers::gallery_template)
+        .service(handlers::metadata_template)
+        .service(handlers::hex_template)
+        .service(handlers::diff_template)
+        .service(handlers::trash_template)
+        .service(handlers::restore_trash_template)
+        .service(handlers::purge_trash_entry_template)
+        .service(handlers::purge_trash_template);
 }
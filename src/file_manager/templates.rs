@@ -1,10 +1,23 @@
+use std::ops::Range;
+
 use askama::Template;
-use file_server_core::{Directory, DirectoryEntry, MediaType};
+use file_server_core::{
+    diff::{DiffHunk, DiffLine, LineKind, SplitRow},
+    escape_html,
+    hex::HEX_BYTES_PER_ROW,
+    images::is_supported_image,
+    metadata::MediaMetadata,
+    render::{Renderer, TableSort},
+    trash::TrashEntry,
+    Directory, DirectoryEntry, MediaType,
+};
 
 #[derive(Debug, Template)]
 #[template(path = "index.html", escape = "none")]
-pub struct HomePageTemplate {
+pub struct HomePageTemplate<'a> {
     pub css_content: String,
+    /// Path of the manager's API, e.g. `/manager/api/v1`
+    pub manager_api: &'a str,
 }
 
 #[derive(Debug, Template)]
@@ -20,43 +33,59 @@ pub struct DirectoryTemplate<'a> {
     // TODO: Make optional to represent unvisited state (Or use enum?)
     pub entries: Vec<DirectoryEntryTemplate<'a>>,
     pub path: String,
+    /// Overlay layer of the directory
+    pub layer: Option<&'a str>,
     pub expanded: bool,
+    pub manager_api: &'a str,
 }
 
 #[derive(Debug, Template)]
 #[template(path = "directory-entry.html", escape = "none")]
 pub enum DirectoryEntryTemplate<'a> {
     Directory(DirectoryTemplate<'a>),
-    File { name: &'a str, path: String },
+    File {
+        name: &'a str,
+        path: String,
+        /// Overlay layer of the file
+        layer: Option<&'a str>,
+        manager_api: &'a str,
+    },
 }
 
-impl<'a> From<&'a Directory> for DirectoryTemplate<'a> {
-    fn from(value: &'a Directory) -> Self {
+impl<'a> DirectoryTemplate<'a> {
+    pub fn new(value: &'a Directory, manager_api: &'a str) -> Self {
         let entries = value
             .entries
             .iter()
-            .map(DirectoryEntryTemplate::from)
+            .map(|entry| DirectoryEntryTemplate::new(entry, manager_api))
             .collect();
 
         Self {
             name: &value.name,
             entries,
             path: value.path.to_str().unwrap_or("").to_owned(),
+            layer: value.layer.as_deref(),
             expanded: false,
+            manager_api,
         }
     }
 }
 
-impl<'a> From<&'a DirectoryEntry> for DirectoryEntryTemplate<'a> {
-    fn from(value: &'a DirectoryEntry) -> Self {
+impl<'a> DirectoryEntryTemplate<'a> {
+    pub fn new(value: &'a DirectoryEntry, manager_api: &'a str) -> Self {
         match value {
             DirectoryEntry::Directory(directory) => {
-                let directory = DirectoryTemplate::from(directory);
+                let directory = DirectoryTemplate::new(directory, manager_api);
                 Self::Directory(directory)
             }
-            DirectoryEntry::File { name, path } => {
+            DirectoryEntry::File { name, path, layer } => {
                 let path = path.to_str().unwrap_or("").to_owned();
-                Self::File { name, path }
+                Self::File {
+                    name,
+                    path,
+                    layer: layer.as_deref(),
+                    manager_api,
+                }
             }
         }
     }
@@ -69,4 +98,425 @@ pub struct FileContentTemplate<'a> {
     pub path: &'a str,
     pub media_type: MediaType,
     pub mime_type: Option<&'a str>,
+    pub renderer: Option<Renderer>,
+    /// Show the raw preview even though a renderer is available
+    pub raw: bool,
+    /// Text that can't be read by line (archive members, UTF-16) is previewed whole
+    pub read_whole: bool,
+    /// Charset chosen instead of the detected one
+    pub charset: Option<&'static str>,
+    /// Detected charset, only for text
+    pub detected_charset: Option<&'static str>,
+    /// Charsets that can be chosen, and whether they are chosen
+    pub encodings: Vec<(&'static str, bool)>,
+    pub manager_api: &'a str,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "line-page.html", escape = "none")]
+pub struct LinePageTemplate<'a> {
+    pub path: &'a str,
+    /// Pairs of one based line numbers and escaped line content
+    pub lines: Vec<(usize, String)>,
+    pub next_line: Option<usize>,
+    pub manager_api: &'a str,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "source-preview.html", escape = "none")]
+pub struct SourcePreviewTemplate {
+    pub line_count: usize,
+    pub highlighted_content: String,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "rich-preview.html", escape = "none")]
+pub enum RichPreviewTemplate<'a> {
+    Markdown {
+        html: String,
+    },
+    Table {
+        path: &'a str,
+        headers: Vec<TableHeaderTemplate>,
+        /// Escaped body cells
+        rows: Vec<Vec<String>>,
+        /// One based number of the current page
+        page: usize,
+        page_count: usize,
+        previous_page: Option<usize>,
+        next_page: Option<usize>,
+        sort: Option<TableSort>,
+        manager_api: &'a str,
+    },
+    Tree {
+        path: &'a str,
+        html: String,
+        pretty: bool,
+        manager_api: &'a str,
+    },
+}
+
+#[derive(Debug)]
+pub struct TableHeaderTemplate {
+    /// Escaped header cell
+    pub name: String,
+    pub indicator: &'static str,
+    /// Sort order applied when the header is clicked
+    pub next_descending: bool,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "gallery.html", escape = "none")]
+pub struct GalleryTemplate<'a> {
+    pub name: &'a str,
+    pub parent_path: Option<String>,
+    pub entries: Vec<GalleryEntryTemplate<'a>>,
+    pub thumbnail_size: u32,
+    pub manager_api: &'a str,
+}
+
+#[derive(Debug)]
+pub enum GalleryEntryTemplate<'a> {
+    Directory { name: &'a str, path: String },
+    Image { name: &'a str, path: String },
+    File { name: &'a str, path: String },
+}
+
+impl<'a> From<&'a DirectoryEntry> for GalleryEntryTemplate<'a> {
+    fn from(value: &'a DirectoryEntry) -> Self {
+        match value {
+            DirectoryEntry::Directory(directory) => Self::Directory {
+                name: &directory.name,
+                path: directory.path.to_str().unwrap_or("").to_owned(),
+            },
+            DirectoryEntry::File { name, path, .. } if is_supported_image(path) => Self::Image {
+                name,
+                path: path.to_str().unwrap_or("").to_owned(),
+            },
+            DirectoryEntry::File { name, path, .. } => Self::File {
+                name,
+                path: path.to_str().unwrap_or("").to_owned(),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Template)]
+#[template(path = "metadata.html", escape = "none")]
+pub struct MetadataTemplate {
+    pub sections: Vec<MetadataSectionTemplate>,
+}
+
+#[derive(Debug)]
+pub struct MetadataSectionTemplate {
+    pub title: &'static str,
+    /// Pairs of escaped labels and values
+    pub rows: Vec<(String, String)>,
+}
+
+impl MetadataSectionTemplate {
+    fn new(title: &'static str) -> Self {
+        Self {
+            title,
+            rows: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, label: &str, value: Option<impl ToString>) {
+        if let Some(value) = value {
+            self.rows
+                .push((escape_html(label), escape_html(&value.to_string())));
+        }
+    }
+}
+
+impl From<&MediaMetadata> for MetadataTemplate {
+    fn from(value: &MediaMetadata) -> Self {
+        let mut sections = Vec::new();
+
+        if let Some(image) = &value.image {
+            let mut section = MetadataSectionTemplate::new("Image");
+            section.push(
+                "Dimensions",
+                image
+                    .width
+                    .zip(image.height)
+                    .map(|(width, height)| format!("{width} × {height}")),
+            );
+            section.push("Camera make", image.camera_make.as_ref());
+            section.push("Camera model", image.camera_model.as_ref());
+            section.push("Taken at", image.taken_at.as_ref());
+            section.push("Orientation", image.orientation);
+            section.push(
+                "GPS",
+                image.gps.as_ref().map(|gps| match gps.altitude {
+                    Some(altitude) => {
+                        format!(
+                            "{:.6}, {:.6} ({altitude:.1} m)",
+                            gps.latitude, gps.longitude
+                        )
+                    }
+                    None => format!("{:.6}, {:.6}", gps.latitude, gps.longitude),
+                }),
+            );
+            sections.push(section);
+
+            if !image.exif.is_empty() {
+                let mut section = MetadataSectionTemplate::new("EXIF");
+                for (tag, value) in &image.exif {
+                    section.push(tag, Some(value));
+                }
+                sections.push(section);
+            }
+        }
+
+        if let Some(audio) = &value.audio {
+            let mut section = MetadataSectionTemplate::new("Audio");
+            section.push("Codec", audio.codec.as_ref());
+            section.push("Duration", audio.duration_seconds.map(format_duration));
+            section.push(
+                "Sample rate",
+                audio.sample_rate.map(|rate| format!("{rate} Hz")),
+            );
+            section.push("Channels", audio.channels);
+            section.push("Bits per sample", audio.bits_per_sample);
+            sections.push(section);
+
+            if !audio.tags.is_empty() {
+                let mut section = MetadataSectionTemplate::new("Tags");
+                for (key, value) in &audio.tags {
+                    section.push(key, Some(value));
+                }
+                sections.push(section);
+            }
+        }
+
+        if let Some(video) = &value.video {
+            let mut section = MetadataSectionTemplate::new("Video");
+            section.push("Container", Some(&video.container));
+            section.push("Duration", video.duration_seconds.map(format_duration));
+            for (i, track) in video.tracks.iter().enumerate() {
+                let dimensions = track
+                    .width
+                    .zip(track.height)
+                    .map(|(width, height)| format!(", {width} × {height}"))
+                    .unwrap_or_default();
+                section.push(
+                    &format!("Track {}", i + 1),
+                    Some(format!("{} ({}{dimensions})", track.kind, track.codec)),
+                );
+            }
+            sections.push(section);
+        }
+
+        Self { sections }
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.round() as u64;
+    let (hours, minutes, seconds) = (total / 3600, total / 60 % 60, total % 60);
+
+    if hours > 0 {
+        format!("{hours}:{minutes:02}:{seconds:02}")
+    } else {
+        format!("{minutes}:{seconds:02}")
+    }
+}
+
+#[derive(Debug, Template)]
+#[template(path = "hex-view.html", escape = "none")]
+pub struct HexViewTemplate<'a> {
+    pub path: &'a str,
+    /// Type of the file identified by its magic number
+    pub description: Option<&'static str>,
+    pub file_size: u64,
+    pub rows: Vec<HexRowTemplate>,
+    /// Offset of the first row, in hex
+    pub offset: String,
+    /// Escaped search pattern
+    pub pattern: String,
+    /// Offset the next search starts at
+    pub search_from: u64,
+    pub previous_offset: Option<u64>,
+    pub next_offset: Option<u64>,
+    /// Escaped error or search result message
+    pub message: Option<String>,
+    pub manager_api: &'a str,
+}
+
+#[derive(Debug)]
+pub struct HexRowTemplate {
+    pub offset: String,
+    /// Hex pairs with highlighted bytes wrapped in `<mark>`
+    pub hex: String,
+    /// Escaped printable ASCII, `.` for other bytes
+    pub ascii: String,
+}
+
+impl HexRowTemplate {
+    pub fn new(offset: u64, bytes: &[u8], highlight: Option<&Range<u64>>) -> Self {
+        let mut hex = String::new();
+        let mut ascii = String::new();
+
+        for i in 0..HEX_BYTES_PER_ROW {
+            if i == HEX_BYTES_PER_ROW / 2 {
+                hex.push(' ');
+            }
+
+            let Some(&byte) = bytes.get(i) else {
+                // Pad short rows so the ASCII column stays aligned
+                hex.push_str("   ");
+                continue;
+            };
+
+            let is_highlighted =
+                highlight.is_some_and(|highlight| highlight.contains(&(offset + i as u64)));
+            let character = if byte.is_ascii_graphic() || byte == b' ' {
+                escape_html(&(byte as char).to_string())
+            } else {
+                ".".to_owned()
+            };
+
+            if is_highlighted {
+                hex.push_str(&format!("<mark>{byte:02x}</mark> "));
+                ascii.push_str(&format!("<mark>{character}</mark>"));
+            } else {
+                hex.push_str(&format!("{byte:02x} "));
+                ascii.push_str(&character);
+            }
+        }
+
+        Self {
+            offset: format!("{offset:08x}"),
+            hex,
+            ascii,
+        }
+    }
+}
+
+#[derive(Debug, Template)]
+#[template(path = "diff.html", escape = "none")]
+pub struct DiffTemplate {
+    /// Escaped paths of the compared files
+    pub a: String,
+    pub b: String,
+    pub hunks: Vec<DiffHunkTemplate>,
+}
+
+#[derive(Debug)]
+pub struct DiffHunkTemplate {
+    /// `@@ -old +new @@` line of the hunk
+    pub header: String,
+    pub rows: Vec<(DiffCellTemplate, DiffCellTemplate)>,
+}
+
+#[derive(Debug)]
+pub struct DiffCellTemplate {
+    pub number: String,
+    pub class: &'static str,
+    /// Escaped line content
+    pub content: String,
+}
+
+impl From<DiffHunk<SplitRow>> for DiffHunkTemplate {
+    fn from(hunk: DiffHunk<SplitRow>) -> Self {
+        let cell = |line: Option<DiffLine>, number: fn(&DiffLine) -> Option<usize>| match line {
+            Some(line) => DiffCellTemplate {
+                number: number(&line).map(|n| n.to_string()).unwrap_or_default(),
+                class: match line.kind {
+                    LineKind::Context => "diff-context",
+                    LineKind::Delete => "diff-delete",
+                    LineKind::Insert => "diff-insert",
+                },
+                content: escape_html(&line.content),
+            },
+            None => DiffCellTemplate {
+                number: String::new(),
+                class: "diff-empty",
+                content: String::new(),
+            },
+        };
+
+        Self {
+            header: format!(
+                "@@ -{},{} +{},{} @@",
+                hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines
+            ),
+            rows: hunk
+                .lines
+                .into_iter()
+                .map(|row| {
+                    (
+                        cell(row.left, |line| line.old_line),
+                        cell(row.right, |line| line.new_line),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Template)]
+#[template(path = "trash.html", escape = "none")]
+pub struct TrashTemplate<'a> {
+    pub entries: Vec<TrashEntryTemplate>,
+    /// Escaped result of the last action
+    pub message: Option<String>,
+    pub manager_api: &'a str,
+}
+
+#[derive(Debug)]
+pub struct TrashEntryTemplate {
+    pub id: String,
+    /// Escaped path the entry was deleted from
+    pub original_path: String,
+    pub deleted_at: String,
+    /// Escaped name of the deleting user
+    pub deleted_by: String,
+    pub size: String,
+}
+
+impl From<TrashEntry> for TrashEntryTemplate {
+    fn from(entry: TrashEntry) -> Self {
+        Self {
+            id: entry.id,
+            original_path: escape_html(&entry.original_path),
+            deleted_at: format_timestamp(entry.deleted_at),
+            deleted_by: escape_html(&entry.deleted_by),
+            size: match entry.size {
+                Some(size) => format!("{size} B"),
+                None => "Directory".to_owned(),
+            },
+        }
+    }
+}
+
+/// Formats seconds since the epoch as a UTC date and time
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // Converts days since the epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
 }
@@ -1,19 +1,118 @@
 use actix_files::NamedFile;
 use actix_web::{
-    get,
-    http::header::ContentType,
-    web::{Data, Path, Query},
-    HttpResponse, Responder,
+    body::SizedStream,
+    delete, get,
+    http::{
+        header::{self, ContentType, EntityTag, Header, HttpDate},
+        Method, StatusCode,
+    },
+    patch, post, put, route,
+    web::{self, Data, Path, Query},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
 };
+use askama::Template;
+use file_server_core::archive::{find_archive, get_archive_structure, read_member, stream_member};
+use file_server_core::charset::{
+    decode, detect_encoding, encoding_for_label, resolve_line_encoding,
+};
+use file_server_core::detect::{
+    detect_type, detect_type_by_name, detect_type_with_header, SNIFF_SIZE,
+};
+use file_server_core::diff::{diff_hunks, read_text, unified_diff, DiffHunk, DEFAULT_CONTEXT};
+use file_server_core::follow::follow_file;
+use file_server_core::hex::{
+    find_pattern, parse_offset, parse_pattern, read_range, MAX_RANGE_SIZE,
+};
+use file_server_core::images::{
+    is_supported_image, thumbnail, transform_image, Fit, ImageLimits, ImageTransform, OutputFormat,
+    DEFAULT_THUMBNAIL_SIZE,
+};
+use file_server_core::lines::{read_lines, read_tail, LineIndexCache};
+use file_server_core::metadata::extract_metadata;
+use file_server_core::render::MAX_RENDER_SIZE;
+use file_server_core::storage::{entity_tag, read_header, Storage, StorageMetadata};
+use file_server_core::trash::{Trash, ANONYMOUS_USER, USER_HEADERS};
+use file_server_core::uploads::{
+    metadata_value, parse_metadata, Upload, UploadError, UploadStore, CHECKSUM_ALGORITHMS,
+    TUS_EXTENSIONS, TUS_VERSION,
+};
+use file_server_core::versions::{Version, VersionStore};
 use file_server_core::*;
 use log::info;
-use mime_guess;
-use serde::Deserialize;
+use percent_encoding::utf8_percent_encode;
+use serde::{Deserialize, Serialize};
 
+use encoding_rs::Encoding;
+use futures_util::StreamExt;
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::Read;
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, UNIX_EPOCH};
 
 use crate::configs::ServerConfigs;
+use crate::file_manager::templates::format_timestamp;
+use crate::file_server::templates::{
+    AutoindexColumn, AutoindexColumnTemplate, AutoindexEntryTemplate, AutoindexTemplate,
+};
+
+/// Normalizes a requested path into a path of the storage
+pub(crate) fn storage_path(path: &str) -> Result<PathBuf, HttpResponse> {
+    normalize_path(path).map_err(|err| HttpResponse::BadRequest().body(err.to_string()))
+}
+
+/// Refuses requests that would write to read-only storage, such as an archive
+pub(crate) fn check_writable(storage: &dyn Storage) -> Result<(), HttpResponse> {
+    match storage.is_read_only() {
+        true => Err(HttpResponse::Forbidden().body("The served files are read-only")),
+        false => Ok(()),
+    }
+}
+
+/// Path of `path` on the local disk, for the features that need one
+pub(crate) fn local_path(
+    storage: &Data<dyn Storage>,
+    path: &std::path::Path,
+) -> Result<PathBuf, HttpResponse> {
+    storage.local_path(path).ok_or_else(|| {
+        HttpResponse::NotImplemented().body(format!(
+            "{} is not on the local disk, which this feature requires",
+            path.display()
+        ))
+    })
+}
+
+pub(crate) fn is_storage_file(storage: &Data<dyn Storage>, path: &std::path::Path) -> bool {
+    storage.stat(path).is_ok_and(|metadata| !metadata.is_dir)
+}
+
+/// Archive containing `path` and the path inside it, for storages on the local disk
+pub(crate) fn find_storage_archive(
+    storage: &Data<dyn Storage>,
+    path: &std::path::Path,
+) -> Option<(PathBuf, PathBuf)> {
+    storage
+        .local_path(path)
+        .and_then(|local_path| find_archive(&local_path))
+}
+
+/// Lists the members of an archive into `directory`, whose path is relative to the storage
+pub(crate) fn list_storage_archive(
+    storage: &Data<dyn Storage>,
+    directory: &mut Directory,
+    archive_path: &std::path::Path,
+    recursive: bool,
+) -> std::io::Result<()> {
+    let relative_path = directory.path.clone();
+    let local_path = storage
+        .local_path(&relative_path)
+        .ok_or_else(|| std::io::Error::new(ErrorKind::Unsupported, "Not on the local disk"))?;
+    directory.path = local_path.clone();
+    get_archive_structure(directory, archive_path, recursive)?;
+    directory.rebase_path(&local_path, &relative_path);
+    Ok(())
+}
 
 #[get("/health-check")]
 async fn health_check() -> impl Responder {
@@ -24,28 +123,128 @@ async fn health_check() -> impl Responder {
 struct FileRequest {
     #[serde(rename = "force-display")]
     force_display: Option<bool>,
+    /// Overrides the detected charset of text
+    charset: Option<String>,
+    w: Option<u32>,
+    h: Option<u32>,
+    fit: Option<Fit>,
+    format: Option<OutputFormat>,
+    quality: Option<u8>,
+    rotate: Option<u16>,
+    #[serde(rename = "auto-orient")]
+    auto_orient: Option<bool>,
+    /// Column the listing of a directory is sorted by
+    sort: Option<AutoindexColumn>,
+    descending: Option<bool>,
+}
+
+impl FileRequest {
+    /// The requested image transformation, if any transformation parameter is present
+    fn image_transform(&self) -> Option<ImageTransform> {
+        let has_transform = self.w.is_some()
+            || self.h.is_some()
+            || self.format.is_some()
+            || self.quality.is_some()
+            || self.rotate.is_some()
+            || self.auto_orient.is_some();
+
+        has_transform.then(|| ImageTransform {
+            auto_orient: self.auto_orient.unwrap_or(false),
+            rotate: self.rotate.unwrap_or(0),
+            width: self.w,
+            height: self.h,
+            fit: self.fit.unwrap_or_default(),
+            format: self.format,
+            quality: self.quality,
+        })
+    }
 }
 
 #[get("/api/v1/files/{path:.*}")]
 async fn serve_static_file(
+    req: HttpRequest,
     configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    image_limits: Data<ImageLimits>,
     path: Path<String>,
     query: Query<FileRequest>,
 ) -> impl Responder {
     // TODO: Add request ID for debugging purposes
-    let mut file_path = configs.base_dir.clone();
-    file_path.push(path.as_str());
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if let Some(transform) = query.image_transform() {
+        let file_path = match local_path(&storage, &file_path) {
+            Ok(file_path) => file_path,
+            Err(response) => return response,
+        };
+        if !file_path.is_file() || !is_supported_image(&file_path) {
+            return HttpResponse::BadRequest().body(format!("{} is not a supported image", &path));
+        }
+        if let Err(message) = transform.validate() {
+            return HttpResponse::BadRequest().body(message);
+        }
+
+        let max_pixels = image_limits.max_pixels;
+        let cache_dir = configs.cache_dir.join("transformed");
+
+        return process_image(&req, &image_limits, &path, move || {
+            transform_image(&file_path, &transform, max_pixels, &cache_dir)
+        })
+        .await;
+    }
+
+    let encoding = match query.charset.as_deref().filter(|label| !label.is_empty()) {
+        Some(label) => match encoding_for_label(label) {
+            Some(encoding) => Some(encoding),
+            None => return HttpResponse::BadRequest().body(format!("Unknown charset {}", label)),
+        },
+        None => None,
+    };
+
+    if let Some((archive_path, member_path)) = find_storage_archive(&storage, &file_path) {
+        if !member_path.as_os_str().is_empty() {
+            let force_display = query.force_display.unwrap_or(false);
+            return serve_archive_member(
+                archive_path,
+                member_path,
+                force_display,
+                encoding,
+                &path,
+                &configs.mime_types,
+            )
+            .await;
+        }
+    }
+
+    let file_bytes = match storage.stat(&file_path) {
+        Ok(metadata) => {
+            if metadata.is_dir {
+                return serve_directory(&req, &configs, &storage, file_path, &query).await;
+            }
+
+            if !query.force_display.unwrap_or(false) {
+                match requested_range(&req, metadata.len) {
+                    Ok(Some(range)) => {
+                        return serve_range(&storage, &file_path, range, metadata.len, &configs)
+                    }
+                    Ok(None) => {}
+                    Err(response) => return response,
+                }
+            }
 
-    let file_bytes = match NamedFile::open_async(&file_path).await {
-        Ok(file) => {
-            if file.metadata().is_dir() {
-                return HttpResponse::BadRequest().body(format!("{:?} is a directory", &file_path));
+            let mut file_bytes = Vec::new();
+            if let Err(err) = storage
+                .open(&file_path, None)
+                .and_then(|mut reader| reader.read_to_end(&mut file_bytes))
+            {
+                return HttpResponse::InternalServerError()
+                    .body(format!("Failed to read file {}: {}", path, err));
             }
 
-            file.file()
-                .bytes()
-                .map(|byte| byte.unwrap())
-                .collect::<Vec<_>>()
+            file_bytes
         }
         Err(_) => {
             let message = format!("Failed to get file with path: {}", path);
@@ -56,52 +255,393 @@ async fn serve_static_file(
     };
 
     let mut response_builder = HttpResponse::Ok();
+    let header = &file_bytes[..file_bytes.len().min(SNIFF_SIZE)];
 
     // Determine if `text/plain` is used to force browser to display the file contents
     match query.force_display {
         Some(force_display) if force_display => {
             response_builder.insert_header(ContentType::plaintext());
-            let file_content = String::from_utf8_lossy(&file_bytes);
-            let mut sanitized_file_content = String::with_capacity(file_content.len() * 2);
-            file_content.chars().for_each(|c| match c {
-                '<' => sanitized_file_content.push_str("&lt;"),
-                '>' => sanitized_file_content.push_str("&gt;"),
-                '&' => sanitized_file_content.push_str("&amp;"),
-                c => sanitized_file_content.push(c),
-            });
+            let encoding = encoding.unwrap_or_else(|| detect_encoding(header));
+            let file_content = decode(&file_bytes, encoding);
 
-            response_builder.body(sanitized_file_content)
+            response_builder.body(escape_html(&file_content))
         }
         _ => {
-            // get file mimetype from file name
-            let mime_type = match mime_guess::from_path(file_path).first() {
-                Some(mime) => mime.to_string(),
-                None => "text/plain".to_string(),
-            };
-            response_builder.insert_header(("Content-Type", mime_type.as_str()));
+            let mut detected_type =
+                detect_type_with_header(&file_path, Some(header), &configs.mime_types);
+            if let Some(encoding) = encoding.filter(|_| detected_type.is_text()) {
+                detected_type.charset = Some(encoding.name());
+            }
+
+            response_builder.insert_header(("Content-Type", detected_type.content_type()));
+            response_builder.insert_header((header::ACCEPT_RANGES, "bytes"));
             response_builder.body(file_bytes)
         }
     }
 }
 
+/// Lists a directory requested by a browser, or serves its `index.html` when enabled
+async fn serve_directory(
+    req: &HttpRequest,
+    configs: &ServerConfigs,
+    storage: &Data<dyn Storage>,
+    dir_path: PathBuf,
+    query: &FileRequest,
+) -> HttpResponse {
+    let accepts_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.contains("text/html"));
+    if !accepts_html {
+        return HttpResponse::BadRequest().body(format!("{:?} is a directory", &dir_path));
+    }
+
+    // Relative links of the listing resolve against the directory only with a trailing slash
+    if !dir_path.as_os_str().is_empty() && !req.path().ends_with('/') {
+        let location = match req.query_string() {
+            "" => format!("{}/", req.path()),
+            query_string => format!("{}/?{}", req.path(), query_string),
+        };
+        return HttpResponse::MovedPermanently()
+            .insert_header((header::LOCATION, location))
+            .finish();
+    }
+
+    let index_path = dir_path.join("index.html");
+    if configs.index_files {
+        if let Ok(metadata) = storage.stat(&index_path) {
+            if !metadata.is_dir {
+                return serve_storage_file(
+                    req,
+                    storage,
+                    &index_path,
+                    metadata,
+                    &configs.mime_types,
+                )
+                .await;
+            }
+        }
+    }
+
+    let storage = storage.clone().into_inner();
+    let listed_path = dir_path.clone();
+    let mut entries = match web::block(move || storage.list(&listed_path)).await {
+        Ok(Ok(entries)) => entries,
+        Ok(Err(err)) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to list {:?}: {}", &dir_path, err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    entries.retain(|entry| !(dir_path.as_os_str().is_empty() && entry.name == DATA_DIR_NAME));
+
+    let sort = query.sort.unwrap_or_default();
+    let descending = query.descending.unwrap_or(false);
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            AutoindexColumn::Name => a.name.cmp(&b.name),
+            AutoindexColumn::Size => a.metadata.len.cmp(&b.metadata.len),
+            AutoindexColumn::Modified => a.metadata.modified.cmp(&b.metadata.modified),
+        };
+        let order = if descending { order.reverse() } else { order };
+        // Directories come first whatever the order
+        b.metadata.is_dir.cmp(&a.metadata.is_dir).then(order)
+    });
+
+    let columns = AutoindexColumn::ALL
+        .iter()
+        .map(|column| AutoindexColumnTemplate {
+            key: column.key(),
+            name: column.title(),
+            indicator: match (*column == sort, descending) {
+                (true, false) => "▲",
+                (true, true) => "▼",
+                (false, _) => "",
+            },
+            next_descending: *column == sort && !descending,
+        })
+        .collect();
+
+    let entries = entries
+        .into_iter()
+        .map(|entry| {
+            let suffix = if entry.metadata.is_dir { "/" } else { "" };
+            AutoindexEntryTemplate {
+                name: escape_html(&format!("{}{}", entry.name, suffix)),
+                href: format!(
+                    "{}{}",
+                    utf8_percent_encode(&entry.name, PATH_SEGMENT),
+                    suffix
+                ),
+                size: if entry.metadata.is_dir {
+                    "-".to_owned()
+                } else {
+                    entry.metadata.len.to_string()
+                },
+                modified: entry
+                    .metadata
+                    .modified
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|duration| format_timestamp(duration.as_secs()))
+                    .unwrap_or_default(),
+            }
+        })
+        .collect();
+
+    let template = AutoindexTemplate {
+        path: escape_html(&dir_path.to_string_lossy())
+            + if dir_path.as_os_str().is_empty() {
+                ""
+            } else {
+                "/"
+            },
+        has_parent: !dir_path.as_os_str().is_empty(),
+        columns,
+        entries,
+    };
+
+    match template.render() {
+        Ok(html) => HttpResponse::Ok()
+            .insert_header(ContentType::html())
+            .body(html),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Single byte range requested with a `Range` header. Several ranges are answered with the
+/// whole file.
+pub(crate) fn requested_range(
+    req: &HttpRequest,
+    len: u64,
+) -> Result<Option<Range<u64>>, HttpResponse> {
+    let Some(value) = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return Ok(None);
+    };
+
+    match value.parse::<header::Range>() {
+        Ok(header::Range::Bytes(specs)) if specs.len() == 1 => {
+            match specs[0].to_satisfiable_range(len) {
+                Some((start, end)) => Ok(Some(start..end + 1)),
+                None => Err(HttpResponse::RangeNotSatisfiable()
+                    .insert_header((header::CONTENT_RANGE, format!("bytes */{len}")))
+                    .finish()),
+            }
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Streams the bytes of `range` of a file, `len` being the size of the whole file
+pub(crate) fn serve_range(
+    storage: &Data<dyn Storage>,
+    file_path: &std::path::Path,
+    range: Range<u64>,
+    len: u64,
+    configs: &ServerConfigs,
+) -> HttpResponse {
+    let header = read_header(storage.as_ref(), file_path).ok();
+    let detected_type = detect_type_with_header(file_path, header.as_deref(), &configs.mime_types);
+    let range_len = range.end - range.start;
+    let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+
+    match storage.open(file_path, Some(range)) {
+        Ok(reader) => HttpResponse::PartialContent()
+            .insert_header(("Content-Type", detected_type.content_type()))
+            .insert_header((header::CONTENT_RANGE, content_range))
+            .insert_header((header::ACCEPT_RANGES, "bytes"))
+            .body(SizedStream::new(
+                range_len,
+                FileStream::new(reader, range_len),
+            )),
+        Err(err) => HttpResponse::InternalServerError()
+            .body(format!("Failed to read {:?}: {}", file_path, err)),
+    }
+}
+
+/// Streams a file of the storage with its validators, answering conditional requests and a
+/// `Range` header like static files
+pub(crate) async fn serve_storage_file(
+    req: &HttpRequest,
+    storage: &Data<dyn Storage>,
+    file_path: &std::path::Path,
+    metadata: StorageMetadata,
+    mime_types: &HashMap<String, String>,
+) -> HttpResponse {
+    let etag = EntityTag::new_strong(entity_tag(&metadata));
+    if let Some(status) = precondition_status(req, &etag, metadata.modified) {
+        return HttpResponseBuilder::new(status)
+            .insert_header(header::ETag(etag))
+            .finish();
+    }
+
+    // A range of another version of the file than the one `If-Range` names is useless
+    let if_range = req
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|value| value.to_str().ok());
+    let range = match if_range.is_none_or(|if_range| if_range == etag.to_string()) {
+        true => match requested_range(req, metadata.len) {
+            Ok(range) => range,
+            Err(response) => return response,
+        },
+        false => None,
+    };
+
+    let opening_storage = storage.clone().into_inner();
+    let opening_path = file_path.to_owned();
+    let opening_range = range.clone();
+    let opened = web::block(move || {
+        let header = read_header(opening_storage.as_ref(), &opening_path).ok();
+        let reader = opening_storage.open(&opening_path, opening_range)?;
+        Ok::<_, std::io::Error>((header, reader))
+    });
+    let (header, reader) = match opened.await {
+        Ok(Ok(opened)) => opened,
+        Ok(Err(err)) => {
+            return HttpResponse::InternalServerError()
+                .body(format!("Failed to read {:?}: {}", file_path, err))
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let detected_type = detect_type_with_header(file_path, header.as_deref(), mime_types);
+    let mut response = match &range {
+        Some(range) => {
+            let mut response = HttpResponse::PartialContent();
+            response.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, metadata.len),
+            ));
+            response
+        }
+        None => HttpResponse::Ok(),
+    };
+    response
+        .insert_header(("Content-Type", detected_type.content_type()))
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header(header::ETag(etag));
+    if let Some(modified) = metadata.modified {
+        response.insert_header(header::LastModified(modified.into()));
+    }
+
+    let len = range.map_or(metadata.len, |range| range.end - range.start);
+    response.body(SizedStream::new(len, FileStream::new(reader, len)))
+}
+
+/// Status answering the conditional headers of a request for a file, `None` when the file is
+/// to be sent
+fn precondition_status(
+    req: &HttpRequest,
+    etag: &EntityTag,
+    modified: Option<std::time::SystemTime>,
+) -> Option<StatusCode> {
+    let headers = req.headers();
+    // Dates of headers are precise to the second
+    let modified_secs = modified
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_secs());
+    let date_secs = |date: HttpDate| {
+        std::time::SystemTime::from(date)
+            .duration_since(UNIX_EPOCH)
+            .map(|date| date.as_secs())
+            .ok()
+    };
+
+    if headers.contains_key(header::IF_MATCH) {
+        let matches = match header::IfMatch::parse(req) {
+            Ok(header::IfMatch::Any) => true,
+            Ok(header::IfMatch::Items(tags)) => tags.iter().any(|tag| tag.strong_eq(etag)),
+            Err(_) => false,
+        };
+        if !matches {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    } else if let Ok(header::IfUnmodifiedSince(since)) = header::IfUnmodifiedSince::parse(req) {
+        if modified_secs.is_some_and(|modified| Some(modified) > date_secs(since)) {
+            return Some(StatusCode::PRECONDITION_FAILED);
+        }
+    }
+
+    let is_read = matches!(*req.method(), Method::GET | Method::HEAD);
+    if headers.contains_key(header::IF_NONE_MATCH) {
+        let matches = match header::IfNoneMatch::parse(req) {
+            Ok(header::IfNoneMatch::Any) => true,
+            Ok(header::IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+            Err(_) => false,
+        };
+        if matches {
+            return Some(match is_read {
+                true => StatusCode::NOT_MODIFIED,
+                false => StatusCode::PRECONDITION_FAILED,
+            });
+        }
+    } else if let Ok(header::IfModifiedSince(since)) = header::IfModifiedSince::parse(req) {
+        let is_modified = modified_secs.is_none_or(|modified| Some(modified) > date_secs(since));
+        if is_read && !is_modified {
+            return Some(StatusCode::NOT_MODIFIED);
+        }
+    }
+
+    None
+}
+
 #[get("/api/v1/stream/{path:.*}")]
-async fn serve_file_stream(configs: Data<ServerConfigs>, path: Path<String>) -> impl Responder {
-    let mut file_path = configs.base_dir.clone();
-    file_path.push(path.as_str());
+async fn serve_file_stream(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
 
-    let file = match File::open(&file_path) {
-        Ok(file) if !file.metadata().unwrap().is_file() => {
-            return HttpResponse::BadRequest()
-                .body(format!("{:?} is a directory", &file_path));
+    if let Some((archive_path, member_path)) = find_storage_archive(&storage, &file_path) {
+        if !member_path.as_os_str().is_empty() {
+            return serve_archive_member(
+                archive_path,
+                member_path,
+                false,
+                None,
+                &path,
+                &configs.mime_types,
+            )
+            .await;
+        }
+    }
+
+    let (reader, len) = match storage.stat(&file_path) {
+        Ok(metadata) if metadata.is_dir => {
+            return HttpResponse::BadRequest().body(format!("{:?} is a directory", &file_path));
+        }
+        Ok(metadata) => match requested_range(&req, metadata.len) {
+            Ok(Some(range)) => {
+                return serve_range(&storage, &file_path, range, metadata.len, &configs)
+            }
+            Err(response) => return response,
+            Ok(None) => match storage.open(&file_path, None) {
+                Ok(reader) => (reader, metadata.len),
+                Err(_) => {
+                    return HttpResponse::InternalServerError()
+                        .body(format!("Failed to open file {}", &path))
+                }
+            },
         },
         Err(_) => {
             return HttpResponse::InternalServerError()
                 .body(format!("Failed to open file {}", &path))
         }
-        Ok(file) => file,
     };
 
-    return HttpResponse::Ok().streaming(FileStream::new(file));
+    HttpResponse::Ok()
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .streaming(FileStream::new(reader, len))
 }
 
 #[derive(Debug, Deserialize)]
@@ -111,26 +651,41 @@ pub struct DirectoryStructureQuery {
 
 #[get("/api/v1/directory-structure/{path:.*}")]
 pub async fn dir_structure(
-    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
     path: Path<String>,
     query: Query<DirectoryStructureQuery>,
 ) -> impl Responder {
-    let mut root_dir_path = configs.base_dir.clone();
-    root_dir_path.push(path.as_str());
-
-    let metadata = match fs::metadata(&root_dir_path) {
-        Ok(metadata) => metadata,
-        Err(_) => {
-            return HttpResponse::BadRequest()
-                .body(format!("Failed to get metadata for: {:?}", root_dir_path))
-        }
+    let root_dir_path = match storage_path(&path) {
+        Ok(root_dir_path) => root_dir_path,
+        Err(response) => return response,
     };
 
-    if !metadata.is_dir() {
-        return HttpResponse::BadRequest().body(format!("{:?} is not a directory", root_dir_path));
-    };
+    // Archives and directories inside of them are listed like directories on disk
+    let archive_path =
+        find_storage_archive(&storage, &root_dir_path).map(|(archive_path, _)| archive_path);
+
+    if archive_path.is_none() {
+        let metadata = match storage.stat(&root_dir_path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                return HttpResponse::BadRequest()
+                    .body(format!("Failed to get metadata for: {:?}", root_dir_path))
+            }
+        };
 
-    let name = match root_dir_path.file_name() {
+        if !metadata.is_dir {
+            return HttpResponse::BadRequest()
+                .body(format!("{:?} is not a directory", root_dir_path));
+        };
+    }
+
+    // The root is named after its directory on the local disk, when there is one
+    let local_dir_path = storage.local_path(&root_dir_path);
+    let name = match local_dir_path
+        .as_deref()
+        .unwrap_or(&root_dir_path)
+        .file_name()
+    {
         Some(file_name) => file_name.to_str().unwrap_or("Unknown Filename").to_owned(),
         None => "Unknown Filename".to_owned(),
     };
@@ -139,20 +694,1004 @@ pub async fn dir_structure(
         name,
         path: root_dir_path,
         entries: Vec::new(),
+        layer: None,
     };
 
-    let get_dir_structure_result = match query.recursive {
-        Some(recursive) if recursive => get_directory_structure_recursive(&mut base_dir),
-        _ => get_directory_structure(&mut base_dir),
+    let get_dir_structure_result = match (&archive_path, query.recursive) {
+        (Some(archive_path), recursive) => list_storage_archive(
+            &storage,
+            &mut base_dir,
+            archive_path,
+            recursive.unwrap_or(false),
+        ),
+        (None, Some(recursive)) if recursive => {
+            get_directory_structure_recursive(storage.as_ref(), &mut base_dir)
+        }
+        _ => get_directory_structure(storage.as_ref(), &mut base_dir),
     };
 
     match get_dir_structure_result {
-        Ok(_) => {
-            base_dir.sanitize_path(&configs.base_dir.to_string_lossy());
-            return HttpResponse::Ok()
+        Ok(_) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&base_dir).unwrap()),
+        Err(err) => HttpResponse::BadRequest().body(err.to_string()),
+    }
+}
+
+/// Upper bound on the number of lines returned by a single request
+const MAX_LINES_PER_REQUEST: usize = 10_000;
+
+#[derive(Debug, Deserialize)]
+pub struct LinesQuery {
+    pub from: Option<usize>,
+    pub to: Option<usize>,
+    pub tail: Option<usize>,
+    /// Overrides the detected charset
+    pub charset: Option<String>,
+}
+
+#[get("/api/v1/lines/{path:.*}")]
+pub async fn file_lines(
+    storage: Data<dyn Storage>,
+    line_indices: Data<LineIndexCache>,
+    path: Path<String>,
+    query: Query<LinesQuery>,
+) -> impl Responder {
+    let relative_path = match storage_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let file_path = match local_path(&storage, &relative_path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !file_path.is_file() {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", &file_path));
+    }
+
+    let encoding =
+        match resolve_line_encoding(storage.as_ref(), &relative_path, query.charset.as_deref()) {
+            Ok(encoding) => encoding,
+            Err(message) => return HttpResponse::BadRequest().body(message),
+        };
+
+    let (tail, from) = (query.tail, query.from.unwrap_or(0));
+    let to = query
+        .to
+        .unwrap_or(usize::MAX)
+        .min(from.saturating_add(MAX_LINES_PER_REQUEST));
+    let line_range = match web::block(move || match tail {
+        Some(tail) => read_tail(&file_path, tail.min(MAX_LINES_PER_REQUEST), encoding),
+        None => line_indices
+            .get(&file_path)
+            .and_then(|index| read_lines(&file_path, &index, from, to, encoding)),
+    })
+    .await
+    {
+        Ok(line_range) => line_range,
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    match line_range {
+        Ok(line_range) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&line_range).unwrap()),
+        Err(err) => HttpResponse::InternalServerError()
+            .body(format!("Failed to read lines of {}: {}", &path, err)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FollowQuery {
+    /// Number of existing lines sent before following, defaults to 10 like `tail -f`
+    pub lines: Option<usize>,
+    /// Overrides the detected charset
+    pub charset: Option<String>,
+}
+
+#[get("/api/v1/follow/{path:.*}")]
+pub async fn follow(
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+    query: Query<FollowQuery>,
+) -> impl Responder {
+    let relative_path = match storage_path(&path) {
+        Ok(relative_path) => relative_path,
+        Err(response) => return response,
+    };
+    let file_path = match local_path(&storage, &relative_path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !file_path.is_file() {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", &file_path));
+    }
+
+    let encoding =
+        match resolve_line_encoding(storage.as_ref(), &relative_path, query.charset.as_deref()) {
+            Ok(encoding) => encoding,
+            Err(message) => return HttpResponse::BadRequest().body(message),
+        };
+
+    let tail = query.lines.unwrap_or(10).min(MAX_LINES_PER_REQUEST);
+    match web::block(move || follow_file(file_path, tail, encoding)).await {
+        Ok(Ok(stream)) => HttpResponse::Ok()
+            .insert_header(("Content-Type", "text/event-stream"))
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(stream),
+        Ok(Err(err)) => {
+            HttpResponse::InternalServerError().body(format!("Failed to follow {}: {}", &path, err))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThumbnailQuery {
+    pub size: Option<u32>,
+}
+
+#[get("/api/v1/thumb/{path:.*}")]
+pub async fn serve_thumbnail(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    image_limits: Data<ImageLimits>,
+    path: Path<String>,
+    query: Query<ThumbnailQuery>,
+) -> impl Responder {
+    let file_path = match storage_path(&path).and_then(|path| local_path(&storage, &path)) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !file_path.is_file() || !is_supported_image(&file_path) {
+        return HttpResponse::BadRequest().body(format!("{} is not a supported image", &path));
+    }
+
+    let size = query.size.unwrap_or(DEFAULT_THUMBNAIL_SIZE);
+    let max_pixels = image_limits.max_pixels;
+    let cache_dir = configs.cache_dir.join("thumbnails");
+
+    process_image(&req, &image_limits, &path, move || {
+        thumbnail(&file_path, size, max_pixels, &cache_dir)
+    })
+    .await
+}
+
+#[get("/api/v1/metadata/{path:.*}")]
+pub async fn file_metadata(
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+) -> impl Responder {
+    let file_path = match storage_path(&path).and_then(|path| local_path(&storage, &path)) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !file_path.is_file() {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", &file_path));
+    }
+
+    match web::block(move || {
+        let mime_type = detect_type(&file_path, &configs.mime_types).mime_type;
+        extract_metadata(&file_path, &mime_type)
+    })
+    .await
+    {
+        Ok(Ok(metadata)) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&metadata).unwrap()),
+        Ok(Err(err)) if err.kind() == ErrorKind::InvalidData => HttpResponse::BadRequest()
+            .body(format!("Failed to read metadata of {}: {}", &path, err)),
+        Ok(Err(err)) => HttpResponse::InternalServerError()
+            .body(format!("Failed to read metadata of {}: {}", &path, err)),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BytesQuery {
+    /// Decimal, or hex with a `0x` prefix
+    pub offset: Option<String>,
+    pub length: Option<u64>,
+}
+
+#[get("/api/v1/bytes/{path:.*}")]
+pub async fn serve_byte_range(
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+    query: Query<BytesQuery>,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !is_storage_file(&storage, &file_path) {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", &file_path));
+    }
+
+    let offset = match query.offset.as_deref().map(parse_offset).unwrap_or(Ok(0)) {
+        Ok(offset) => offset,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let length = query.length.unwrap_or(MAX_RANGE_SIZE);
+
+    match read_range(storage.as_ref(), &file_path, offset, length) {
+        Ok(range) => {
+            let mut response_builder = HttpResponse::Ok();
+            response_builder.insert_header(("Content-Type", "application/octet-stream"));
+            if !range.bytes.is_empty() {
+                let end = range.offset + range.bytes.len() as u64 - 1;
+                response_builder.insert_header((
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", range.offset, end, range.file_size),
+                ));
+            }
+
+            response_builder.body(range.bytes)
+        }
+        Err(err) => HttpResponse::InternalServerError()
+            .body(format!("Failed to read bytes of {}: {}", &path, err)),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FindBytesQuery {
+    /// Hex bytes (`de ad be ef`) or text in double quotes
+    pub pattern: String,
+    pub from: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FindBytesResult {
+    offset: Option<u64>,
+}
+
+#[get("/api/v1/find-bytes/{path:.*}")]
+pub async fn find_bytes(
+    storage: Data<dyn Storage>,
+    path: Path<String>,
+    query: Query<FindBytesQuery>,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+
+    if !is_storage_file(&storage, &file_path) {
+        return HttpResponse::BadRequest().body(format!("{:?} is not a file", &file_path));
+    }
+
+    let pattern = match parse_pattern(&query.pattern) {
+        Ok(pattern) => pattern,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+    let from = match query.from.as_deref().map(parse_offset).unwrap_or(Ok(0)) {
+        Ok(from) => from,
+        Err(message) => return HttpResponse::BadRequest().body(message),
+    };
+
+    match web::block(move || find_pattern(storage.as_ref(), &file_path, &pattern, from)).await {
+        Ok(Ok(offset)) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&FindBytesResult { offset }).unwrap()),
+        Ok(Err(err)) => {
+            HttpResponse::InternalServerError().body(format!("Failed to search {}: {}", &path, err))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffMode {
+    #[default]
+    Unified,
+    Split,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiffQuery {
+    pub a: String,
+    pub b: String,
+    #[serde(default)]
+    pub mode: DiffMode,
+    /// Respond with JSON hunks instead of the unified diff text
+    pub format: Option<String>,
+    pub context: Option<usize>,
+}
+
+/// Compares two text files line by line. The unified mode responds with the output of
+/// `diff -u` or its hunks as JSON, the split mode with hunks laid out side by side.
+#[get("/api/v1/diff")]
+pub async fn diff_files(storage: Data<dyn Storage>, query: Query<DiffQuery>) -> impl Responder {
+    let query = query.into_inner();
+    let context = query.context.unwrap_or(DEFAULT_CONTEXT);
+    let json = match query.format.as_deref() {
+        None | Some("text") => query.mode == DiffMode::Split,
+        Some("json") => true,
+        Some(format) => {
+            return HttpResponse::BadRequest().body(format!("Unsupported diff format {format}"))
+        }
+    };
+
+    let (old, new) = match read_diff_texts(&storage, &query.a, &query.b).await {
+        Ok(texts) => texts,
+        Err(response) => return response,
+    };
+
+    if !json {
+        let a = format!("a/{}", query.a.trim_start_matches('/'));
+        let b = format!("b/{}", query.b.trim_start_matches('/'));
+        return HttpResponse::Ok()
+            .insert_header(ContentType::plaintext())
+            .body(unified_diff(&a, &b, &old, &new, context));
+    }
+
+    let hunks = diff_hunks(&old, &new, context);
+    let body = match query.mode {
+        DiffMode::Unified => serde_json::to_string(&hunks),
+        DiffMode::Split => serde_json::to_string(
+            &hunks
+                .into_iter()
+                .map(DiffHunk::into_split)
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .body(body.unwrap())
+}
+
+/// Reads the two files of a diff as text, responding with an error when either can't be
+async fn read_diff_texts(
+    storage: &Data<dyn Storage>,
+    a: &str,
+    b: &str,
+) -> Result<(String, String), HttpResponse> {
+    let mut texts = Vec::with_capacity(2);
+
+    for path in [a, b] {
+        let file_path = storage_path(path)?;
+
+        if !is_storage_file(storage, &file_path) {
+            return Err(HttpResponse::BadRequest().body(format!("{:?} is not a file", &file_path)));
+        }
+
+        let storage = storage.clone();
+        match web::block(move || read_text(storage.as_ref(), &file_path)).await {
+            Ok(Ok(text)) => texts.push(text),
+            Ok(Err(err)) => {
+                let message = format!("Failed to compare {}: {}", path, err);
+                return Err(match err.kind() {
+                    ErrorKind::InvalidInput | ErrorKind::InvalidData => {
+                        HttpResponse::BadRequest().body(message)
+                    }
+                    _ => HttpResponse::InternalServerError().body(message),
+                });
+            }
+            Err(err) => return Err(HttpResponse::InternalServerError().body(err.to_string())),
+        }
+    }
+
+    let new = texts.pop().unwrap_or_default();
+    let old = texts.pop().unwrap_or_default();
+    Ok((old, new))
+}
+
+#[derive(Debug, Serialize)]
+struct WriteResult {
+    /// Version the previous content was stored as, when a file was overwritten
+    previous_version: Option<Version>,
+}
+
+/// Creates or overwrites a file with the request body. The body is written to a staging file
+/// first and then moved into place, the previous content is kept as a version.
+#[put("/api/v1/files/{path:.*}")]
+pub async fn write_file(
+    storage: Data<dyn Storage>,
+    versions: Data<VersionStore>,
+    path: Path<String>,
+    payload: web::Payload,
+) -> impl Responder {
+    let file_path = match storage_path(&path) {
+        Ok(file_path) if file_path.as_os_str().is_empty() => {
+            return HttpResponse::BadRequest().body(format!("{} is not a file", &path))
+        }
+        Ok(file_path) => file_path,
+        Err(response) => return response,
+    };
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    let existed = match storage.stat(&file_path) {
+        Ok(metadata) if metadata.is_dir => {
+            return HttpResponse::BadRequest().body(format!("{:?} is a directory", &file_path))
+        }
+        Ok(_) => true,
+        Err(_) => false,
+    };
+
+    let staging_versions = versions.clone();
+    let (staging_path, staging_file) =
+        match web::block(move || staging_versions.create_staging_file()).await {
+            Ok(Ok(staging)) => staging,
+            Ok(Err(err)) => {
+                return HttpResponse::InternalServerError()
+                    .body(format!("Failed to create staging file: {}", err))
+            }
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+
+    if let Err(err) = write_payload(payload, staging_file).await {
+        let _ = fs::remove_file(&staging_path);
+        return HttpResponse::InternalServerError()
+            .body(format!("Failed to write {}: {}", &path, err));
+    }
+
+    let replace_path = staging_path.clone();
+    let relative_path = path.clone();
+    let replaced =
+        web::block(move || versions.replace(storage.as_ref(), &relative_path, &replace_path)).await;
+    match replaced {
+        Ok(Ok(previous_version)) => {
+            info!("Wrote {}", &path);
+            let body = serde_json::to_string(&WriteResult { previous_version }).unwrap();
+            let mut response_builder = match existed {
+                true => HttpResponse::Ok(),
+                false => HttpResponse::Created(),
+            };
+            response_builder
                 .insert_header(ContentType::json())
-                .body(serde_json::to_string(&base_dir).unwrap());
+                .body(body)
         }
-        Err(err) => HttpResponse::BadRequest().body(err.to_string()),
+        Ok(Err(err)) if err.kind() == ErrorKind::InvalidInput => {
+            let _ = fs::remove_file(&staging_path);
+            HttpResponse::BadRequest().body(format!("Failed to write {}: {}", &path, err))
+        }
+        Ok(Err(err)) => {
+            let _ = fs::remove_file(&staging_path);
+            HttpResponse::InternalServerError().body(format!("Failed to write {}: {}", &path, err))
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VersionQuery {
+    pub version: Option<String>,
+}
+
+/// Lists the previous versions of a file, or responds with the content of the one given by
+/// `version`
+#[get("/api/v1/versions/{path:.*}")]
+pub async fn file_versions(
+    configs: Data<ServerConfigs>,
+    versions: Data<VersionStore>,
+    path: Path<String>,
+    query: Query<VersionQuery>,
+) -> impl Responder {
+    let relative_path = path.clone();
+    let Some(version) = query.into_inner().version else {
+        return match web::block(move || versions.list(&relative_path)).await {
+            Ok(Ok(versions)) => HttpResponse::Ok()
+                .insert_header(ContentType::json())
+                .body(serde_json::to_string(&versions).unwrap()),
+            Ok(Err(err)) => version_error_response(&path, err),
+            Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+        };
+    };
+
+    let content = web::block(move || fs::read(versions.version_path(&relative_path, &version)?));
+    match content.await {
+        Ok(Ok(content)) => {
+            let header = &content[..content.len().min(SNIFF_SIZE)];
+            let detected_type = detect_type_with_header(
+                std::path::Path::new(path.as_str()),
+                Some(header),
+                &configs.mime_types,
+            );
+
+            HttpResponse::Ok()
+                .insert_header(("Content-Type", detected_type.content_type()))
+                .body(content)
+        }
+        Ok(Err(err)) => version_error_response(&path, err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Restores the previous version of a file given by `version`, keeping the replaced content as
+/// a new version
+#[post("/api/v1/versions/{path:.*}")]
+pub async fn restore_version(
+    storage: Data<dyn Storage>,
+    versions: Data<VersionStore>,
+    path: Path<String>,
+    query: Query<VersionQuery>,
+) -> impl Responder {
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    let Some(version) = query.into_inner().version else {
+        return HttpResponse::BadRequest().body("The version to restore is required");
+    };
+
+    let relative_path = path.clone();
+    let restored = web::block(move || versions.restore(storage.as_ref(), &relative_path, &version));
+    match restored.await {
+        Ok(Ok(previous_version)) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&WriteResult { previous_version }).unwrap()),
+        Ok(Err(err)) => version_error_response(&path, err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+fn version_error_response(path: &str, err: std::io::Error) -> HttpResponse {
+    let message = format!("Failed to get versions of {}: {}", path, err);
+    info!("{}", message);
+
+    match err.kind() {
+        ErrorKind::PermissionDenied => HttpResponse::Forbidden().body(message),
+        ErrorKind::NotFound => HttpResponse::NotFound().body(message),
+        ErrorKind::InvalidInput => HttpResponse::BadRequest().body(message),
+        _ => HttpResponse::InternalServerError().body(message),
+    }
+}
+
+/// Moves a file or directory to the trash, from where it can be restored until it expires
+#[delete("/api/v1/files/{path:.*}")]
+pub async fn delete_file(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    trash: Data<Trash>,
+    path: Path<String>,
+) -> impl Responder {
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    let user = request_user(&req);
+    let relative_path = path.clone();
+    match web::block(move || trash.delete(storage.as_ref(), &relative_path, &user)).await {
+        Ok(Ok(entry)) => {
+            info!("{} moved {} to the trash", &entry.deleted_by, &path);
+            HttpResponse::Ok()
+                .insert_header(ContentType::json())
+                .body(serde_json::to_string(&entry).unwrap())
+        }
+        Ok(Err(err)) => trash_error_response(&path, err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[get("/api/v1/trash")]
+pub async fn list_trash(trash: Data<Trash>) -> impl Responder {
+    match web::block(move || trash.list()).await {
+        Ok(Ok(entries)) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&entries).unwrap()),
+        Ok(Err(err)) => trash_error_response("the trash", err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[post("/api/v1/trash/{id}/restore")]
+pub async fn restore_trash_entry(
+    storage: Data<dyn Storage>,
+    trash: Data<Trash>,
+    id: Path<String>,
+) -> impl Responder {
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    let entry_id = id.clone();
+    match web::block(move || trash.restore(storage.as_ref(), &entry_id)).await {
+        Ok(Ok(entry)) => {
+            info!("Restored {} from the trash", &entry.original_path);
+            HttpResponse::Ok()
+                .insert_header(ContentType::json())
+                .body(serde_json::to_string(&entry).unwrap())
+        }
+        Ok(Err(err)) => trash_error_response(&id, err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[delete("/api/v1/trash/{id}")]
+pub async fn purge_trash_entry(trash: Data<Trash>, id: Path<String>) -> impl Responder {
+    let entry_id = id.clone();
+    match web::block(move || trash.purge(&entry_id)).await {
+        Ok(Ok(entry)) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&entry).unwrap()),
+        Ok(Err(err)) => trash_error_response(&id, err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PurgeResult {
+    purged: usize,
+}
+
+#[delete("/api/v1/trash")]
+pub async fn purge_trash(trash: Data<Trash>) -> impl Responder {
+    match web::block(move || trash.purge_all()).await {
+        Ok(Ok(purged)) => HttpResponse::Ok()
+            .insert_header(ContentType::json())
+            .body(serde_json::to_string(&PurgeResult { purged }).unwrap()),
+        Ok(Err(err)) => trash_error_response("the trash", err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// User that made a request as named by an authenticating reverse proxy
+pub(crate) fn request_user(req: &HttpRequest) -> String {
+    USER_HEADERS
+        .iter()
+        .find_map(|header| req.headers().get(*header)?.to_str().ok())
+        .map(str::trim)
+        .filter(|user| !user.is_empty())
+        .unwrap_or(ANONYMOUS_USER)
+        .to_owned()
+}
+
+fn trash_error_response(target: &str, err: std::io::Error) -> HttpResponse {
+    let message = format!("Failed to update {}: {}", target, err);
+    info!("{}", message);
+
+    match err.kind() {
+        ErrorKind::PermissionDenied => HttpResponse::Forbidden().body(message),
+        ErrorKind::NotFound => HttpResponse::NotFound().body(message),
+        ErrorKind::AlreadyExists => HttpResponse::Conflict().body(message),
+        ErrorKind::InvalidInput => HttpResponse::BadRequest().body(message),
+        _ => HttpResponse::InternalServerError().body(message),
+    }
+}
+
+/// Advertises the tus protocol version and extensions supported by the upload endpoints
+#[route("/api/v1/uploads", method = "OPTIONS")]
+pub async fn upload_options() -> impl Responder {
+    HttpResponse::NoContent()
+        .insert_header(("Tus-Resumable", TUS_VERSION))
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Checksum-Algorithm", CHECKSUM_ALGORITHMS))
+        .finish()
+}
+
+/// Creates a tus upload. The destination in the storage is given by the `path` (or `filename`)
+/// key of the `Upload-Metadata` header.
+#[post("/api/v1/uploads")]
+pub async fn create_upload(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    uploads: Data<UploadStore>,
+    versions: Data<VersionStore>,
+) -> impl Responder {
+    if let Err(response) = check_tus_version(&req) {
+        return response;
+    }
+    if storage.is_read_only() {
+        return tus_response(StatusCode::FORBIDDEN).body("The served files are read-only");
+    }
+
+    let Some(length) = header_value(&req, "Upload-Length").and_then(|length| length.parse().ok())
+    else {
+        return tus_response(StatusCode::BAD_REQUEST).body("A valid Upload-Length is required");
+    };
+    let metadata = match parse_metadata(header_value(&req, "Upload-Metadata").unwrap_or("")) {
+        Ok(metadata) => metadata,
+        Err(err) => return upload_error_response(err),
+    };
+    let Some(path) =
+        metadata_value(&metadata, "path").or_else(|| metadata_value(&metadata, "filename"))
+    else {
+        return tus_response(StatusCode::BAD_REQUEST)
+            .body("The path or filename of the upload is required in Upload-Metadata");
+    };
+
+    let created = web::block(move || {
+        let mut upload = uploads.create(&path, length, metadata)?;
+        // Empty files are complete as soon as they are created
+        if upload.is_complete() {
+            uploads.complete(&mut upload, storage.as_ref(), &versions)?;
+        }
+        Ok(upload)
+    });
+
+    match created.await {
+        Ok(Ok(upload)) => {
+            info!("Created upload {} for {}", &upload.id, &upload.path);
+            tus_response(StatusCode::CREATED)
+                .insert_header(("Location", format!("/api/v1/uploads/{}", upload.id)))
+                .insert_header(("Upload-Offset", upload.offset))
+                .insert_header(("Upload-Expires", upload_expires(&upload)))
+                .finish()
+        }
+        Ok(Err(err)) => upload_error_response(err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[route("/api/v1/uploads/{id}", method = "HEAD")]
+pub async fn upload_offset(
+    req: HttpRequest,
+    uploads: Data<UploadStore>,
+    id: Path<String>,
+) -> impl Responder {
+    if let Err(response) = check_tus_version(&req) {
+        return response;
+    }
+
+    match web::block(move || uploads.get(&id)).await {
+        Ok(Ok(upload)) => tus_response(StatusCode::OK)
+            .insert_header(("Upload-Offset", upload.offset))
+            .insert_header(("Upload-Length", upload.length))
+            .insert_header(("Upload-Expires", upload_expires(&upload)))
+            .insert_header(("Cache-Control", "no-store"))
+            .finish(),
+        Ok(Err(err)) => upload_error_response(err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Appends the request body to an upload at `Upload-Offset`, moving the file into the storage
+/// once all of it is received
+#[patch("/api/v1/uploads/{id}")]
+pub async fn append_upload(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    uploads: Data<UploadStore>,
+    versions: Data<VersionStore>,
+    id: Path<String>,
+    mut payload: web::Payload,
+) -> impl Responder {
+    if let Err(response) = check_tus_version(&req) {
+        return response;
+    }
+    if header_value(&req, "Content-Type") != Some("application/offset+octet-stream") {
+        return tus_response(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .body("Content-Type must be application/offset+octet-stream");
+    }
+    let Some(offset) = header_value(&req, "Upload-Offset").and_then(|offset| offset.parse().ok())
+    else {
+        return tus_response(StatusCode::BAD_REQUEST).body("A valid Upload-Offset is required");
+    };
+
+    let checksum = header_value(&req, "Upload-Checksum").map(str::to_owned);
+    let appending_uploads = uploads.clone();
+    let started =
+        web::block(move || appending_uploads.append(&id, offset, checksum.as_deref())).await;
+    let mut writer = match started {
+        Ok(Ok(writer)) => writer,
+        Ok(Err(err)) => return upload_error_response(err),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                // Bytes received before the connection broke are kept to resume from
+                let _ = web::block(move || writer.abort()).await;
+                return tus_response(StatusCode::BAD_REQUEST).body(err.to_string());
+            }
+        };
+
+        writer = match web::block(move || writer.write(&chunk).map(|_| writer)).await {
+            Ok(Ok(writer)) => writer,
+            Ok(Err(err)) => return upload_error_response(err),
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+    }
+
+    let finished = web::block(move || {
+        let mut upload = writer.finish()?;
+        if upload.is_complete() {
+            uploads.complete(&mut upload, storage.as_ref(), &versions)?;
+            info!("Completed upload {} to {}", &upload.id, &upload.path);
+        }
+        Ok(upload)
+    });
+
+    match finished.await {
+        Ok(Ok(upload)) => tus_response(StatusCode::NO_CONTENT)
+            .insert_header(("Upload-Offset", upload.offset))
+            .insert_header(("Upload-Expires", upload_expires(&upload)))
+            .finish(),
+        Ok(Err(err)) => upload_error_response(err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Terminates an upload, discarding what was received
+#[delete("/api/v1/uploads/{id}")]
+pub async fn terminate_upload(
+    req: HttpRequest,
+    uploads: Data<UploadStore>,
+    id: Path<String>,
+) -> impl Responder {
+    if let Err(response) = check_tus_version(&req) {
+        return response;
+    }
+
+    match web::block(move || uploads.terminate(&id)).await {
+        Ok(Ok(())) => tus_response(StatusCode::NO_CONTENT).finish(),
+        Ok(Err(err)) => upload_error_response(err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Responds with `412 Precondition Failed` to clients speaking another version of tus
+fn check_tus_version(req: &HttpRequest) -> Result<(), HttpResponse> {
+    match header_value(req, "Tus-Resumable") {
+        Some(TUS_VERSION) => Ok(()),
+        _ => Err(tus_response(StatusCode::PRECONDITION_FAILED)
+            .insert_header(("Tus-Version", TUS_VERSION))
+            .body(format!("Tus-Resumable {} is required", TUS_VERSION))),
+    }
+}
+
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut response_builder = HttpResponse::build(status);
+    response_builder.insert_header(("Tus-Resumable", TUS_VERSION));
+    response_builder
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name)?.to_str().ok()
+}
+
+fn upload_expires(upload: &Upload) -> String {
+    HttpDate::from(UNIX_EPOCH + Duration::from_secs(upload.expires_at)).to_string()
+}
+
+fn upload_error_response(err: UploadError) -> HttpResponse {
+    let status = match &err {
+        UploadError::NotFound => StatusCode::NOT_FOUND,
+        UploadError::OffsetMismatch { .. } => StatusCode::CONFLICT,
+        UploadError::Locked => StatusCode::LOCKED,
+        UploadError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        // Defined by the checksum extension of tus
+        UploadError::ChecksumMismatch => StatusCode::from_u16(460).unwrap(),
+        UploadError::Invalid(_) => StatusCode::BAD_REQUEST,
+        UploadError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+
+    let message = format!("Failed to upload: {}", err);
+    info!("{}", message);
+    tus_response(status).body(message)
+}
+
+/// Writes a request body to a file chunk by chunk, returning the number of bytes written
+pub(crate) async fn write_payload(payload: web::Payload, file: File) -> std::io::Result<u64> {
+    write_limited_payload(payload, file, u64::MAX).await
+}
+
+/// Same as [`write_payload`], failing with `FileTooLarge` once more than `max_size` bytes were
+/// received
+pub(crate) async fn write_limited_payload(
+    mut payload: web::Payload,
+    mut file: File,
+    max_size: u64,
+) -> std::io::Result<u64> {
+    let mut written = 0;
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(std::io::Error::other)?;
+        written += chunk.len() as u64;
+        if written > max_size {
+            return Err(std::io::Error::new(
+                ErrorKind::FileTooLarge,
+                format!("Uploads are limited to {max_size} bytes"),
+            ));
+        }
+
+        file = web::block(move || file.write_all(&chunk).map(|_| file))
+            .await
+            .map_err(std::io::Error::other)??;
+    }
+
+    web::block(move || file.sync_all())
+        .await
+        .map_err(std::io::Error::other)??;
+    Ok(written)
+}
+
+/// Streams a member of an archive, or responds with its escaped content when `force_display`
+/// is set, the same way as files on disk
+async fn serve_archive_member(
+    archive_path: PathBuf,
+    member_path: PathBuf,
+    force_display: bool,
+    encoding: Option<&'static Encoding>,
+    path: &str,
+    mime_types: &HashMap<String, String>,
+) -> HttpResponse {
+    let mut detected_type = detect_type_by_name(&member_path, mime_types);
+    if let Some(encoding) = encoding.filter(|_| detected_type.is_text()) {
+        detected_type.charset = Some(encoding.name());
+    }
+
+    if force_display {
+        return match web::block(move || read_member(&archive_path, &member_path, MAX_RENDER_SIZE))
+            .await
+        {
+            Ok(Ok(content)) => HttpResponse::Ok()
+                .insert_header(ContentType::plaintext())
+                .body(escape_html(&decode(
+                    &content,
+                    encoding.unwrap_or_else(|| detect_encoding(&content)),
+                ))),
+            Ok(Err(err)) => archive_error_response(path, err),
+            Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+        };
+    }
+
+    match stream_member(archive_path, member_path).await {
+        Ok((size, stream)) => HttpResponse::Ok()
+            .insert_header(("Content-Type", detected_type.content_type()))
+            .no_chunking(size)
+            .streaming(stream),
+        Err(err) => archive_error_response(path, err),
+    }
+}
+
+fn archive_error_response(path: &str, err: std::io::Error) -> HttpResponse {
+    let message = format!("Failed to read {} from archive: {}", path, err);
+    info!("{}", message);
+
+    match err.kind() {
+        ErrorKind::NotFound => HttpResponse::NotFound().body(message),
+        ErrorKind::InvalidInput | ErrorKind::InvalidData => {
+            HttpResponse::BadRequest().body(message)
+        }
+        _ => HttpResponse::InternalServerError().body(message),
+    }
+}
+
+/// Runs a CPU heavy image operation off the worker threads, within the concurrency limit,
+/// and responds with the resulting image
+async fn process_image(
+    req: &HttpRequest,
+    image_limits: &ImageLimits,
+    path: &str,
+    operation: impl FnOnce() -> std::io::Result<(PathBuf, OutputFormat)> + Send + 'static,
+) -> HttpResponse {
+    let _permit = match image_limits.concurrency.acquire().await {
+        Ok(permit) => permit,
+        Err(err) => return HttpResponse::ServiceUnavailable().body(err.to_string()),
+    };
+
+    let (image_path, format) = match web::block(operation).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(err)) => {
+            info!("Failed to process image {}: {}", path, err);
+            let message = format!("Failed to process image {}: {}", path, err);
+
+            return match err.kind() {
+                ErrorKind::InvalidInput | ErrorKind::InvalidData => {
+                    HttpResponse::BadRequest().body(message)
+                }
+                _ => HttpResponse::InternalServerError().body(message),
+            };
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    match NamedFile::open_async(&image_path).await {
+        Ok(file) => file
+            .set_content_type(format.mime_type().parse().unwrap())
+            .into_response(req),
+        Err(err) => HttpResponse::InternalServerError()
+            .body(format!("Failed to open processed image {}: {}", path, err)),
     }
 }
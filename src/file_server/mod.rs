@@ -1,10 +1,31 @@
 use actix_web::web;
 
 pub mod handlers;
+pub mod templates;
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg.service(handlers::health_check)
         .service(handlers::serve_static_file)
         .service(handlers::dir_structure)
-        .service(handlers::serve_file_stream);
+        .service(handlers::serve_file_stream)
+        .service(handlers::file_lines)
+        .service(handlers::follow)
+        .service(handlers::serve_thumbnail)
+        .service(handlers::file_metadata)
+        .service(handlers::serve_byte_range)
+        .service(handlers::find_bytes)
+        .service(handlers::diff_files)
+        .service(handlers::write_file)
+        .service(handlers::file_versions)
+        .service(handlers::restore_version)
+        .service(handlers::delete_file)
+        .service(handlers::list_trash)
+        .service(handlers::restore_trash_entry)
+        .service(handlers::purge_trash_entry)
+        .service(handlers::purge_trash)
+        .service(handlers::upload_options)
+        .service(handlers::create_upload)
+        .service(handlers::upload_offset)
+        .service(handlers::append_upload)
+        .service(handlers::terminate_upload);
 }
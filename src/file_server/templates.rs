@@ -0,0 +1,62 @@
+use askama::Template;
+use serde::Deserialize;
+
+/// Columns an autoindex page can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoindexColumn {
+    #[default]
+    Name,
+    Size,
+    Modified,
+}
+
+impl AutoindexColumn {
+    pub const ALL: [Self; 3] = [Self::Name, Self::Size, Self::Modified];
+
+    pub fn key(&self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Size => "size",
+            Self::Modified => "modified",
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            Self::Name => "Name",
+            Self::Size => "Size",
+            Self::Modified => "Last modified",
+        }
+    }
+}
+
+/// Listing of a directory requested by a browser, like the autoindex of nginx
+#[derive(Debug, Template)]
+#[template(path = "autoindex.html", escape = "none")]
+pub struct AutoindexTemplate {
+    /// Escaped path of the directory, with a trailing slash
+    pub path: String,
+    pub has_parent: bool,
+    pub columns: Vec<AutoindexColumnTemplate>,
+    pub entries: Vec<AutoindexEntryTemplate>,
+}
+
+#[derive(Debug)]
+pub struct AutoindexColumnTemplate {
+    pub key: &'static str,
+    pub name: &'static str,
+    pub indicator: &'static str,
+    /// Order of the link, which reverses the current one
+    pub next_descending: bool,
+}
+
+#[derive(Debug)]
+pub struct AutoindexEntryTemplate {
+    /// Escaped name, with a trailing slash for directories
+    pub name: String,
+    /// URL-encoded link relative to the directory
+    pub href: String,
+    pub size: String,
+    pub modified: String,
+}
@@ -0,0 +1,606 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Component, Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
+};
+
+use actix_web::web::Bytes;
+use flate2::read::{DeflateDecoder, GzDecoder};
+use futures_util::{stream, Stream};
+use tokio::sync::{mpsc, oneshot};
+use zip::CompressionMethod;
+
+use crate::{
+    storage::{Storage, StorageEntry, StorageMetadata},
+    Directory, DirectoryEntry,
+};
+
+const CHUNK_SIZE: usize = 1024 * 64; // 64KB
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.to_lowercase();
+
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether the file is an archive that can be browsed like a directory
+pub fn is_archive(path: &Path) -> bool {
+    ArchiveKind::from_path(path).is_some() && path.is_file()
+}
+
+/// Splits a path pointing into an archive (e.g. `dist/build.zip/bin/app`) into the path of the
+/// archive on disk and the path of the member inside of it, which is empty for the archive itself.
+///
+/// Returns `None` when the path exists on disk or no archive is part of it.
+pub fn find_archive(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    for ancestor in path.ancestors() {
+        if ancestor.is_file() {
+            return ArchiveKind::from_path(ancestor).map(|_| {
+                (
+                    ancestor.to_owned(),
+                    path.strip_prefix(ancestor).unwrap().to_owned(),
+                )
+            });
+        }
+        if ancestor.exists() {
+            return None;
+        }
+    }
+
+    None
+}
+
+/// Member names of archives are untrusted, only their normal components are kept.
+/// Returns `None` for names escaping the archive root.
+fn normalize_member_path(name: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(component) => normalized.push(component),
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+            Component::ParentDir => return None,
+        }
+    }
+
+    Some(normalized)
+}
+
+#[derive(Debug, Default)]
+struct MemberTree {
+    is_dir: bool,
+    children: BTreeMap<String, MemberTree>,
+}
+
+impl MemberTree {
+    fn insert(&mut self, path: &Path, is_dir: bool) {
+        let mut node = self;
+        let mut components = path.components().peekable();
+
+        while let Some(component) = components.next() {
+            let name = component.as_os_str().to_string_lossy().into_owned();
+            node.is_dir = true;
+            node = node.children.entry(name).or_default();
+            if components.peek().is_none() {
+                node.is_dir |= is_dir;
+            }
+        }
+    }
+
+    fn get(&self, path: &Path) -> Option<&MemberTree> {
+        path.components().try_fold(self, |node, component| {
+            node.children
+                .get(component.as_os_str().to_string_lossy().as_ref())
+        })
+    }
+
+    fn push_entries(&self, directory: &mut Directory, recursive: bool) {
+        for (name, node) in &self.children {
+            let path = directory.path.join(name);
+
+            let entry = if node.is_dir {
+                let mut child = Directory {
+                    name: name.to_owned(),
+                    entries: Vec::new(),
+                    path,
+                    layer: None,
+                };
+                if recursive {
+                    node.push_entries(&mut child, recursive);
+                }
+                DirectoryEntry::Directory(child)
+            } else {
+                DirectoryEntry::File {
+                    name: name.to_owned(),
+                    path,
+                    layer: None,
+                }
+            };
+
+            directory.entries.push(entry);
+        }
+    }
+}
+
+fn read_member_tree(archive_path: &Path) -> io::Result<MemberTree> {
+    let kind = ArchiveKind::from_path(archive_path).ok_or_else(not_an_archive)?;
+    let mut tree = MemberTree {
+        is_dir: true,
+        ..Default::default()
+    };
+
+    match kind {
+        ArchiveKind::Zip => {
+            let archive = zip::ZipArchive::new(BufReader::new(File::open(archive_path)?))
+                .map_err(to_io_error)?;
+            for name in archive.file_names() {
+                if let Some(path) = normalize_member_path(Path::new(name)) {
+                    tree.insert(&path, name.ends_with('/'));
+                }
+            }
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = open_tar(archive_path, kind)?;
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if let Some(path) = normalize_member_path(&entry.path()?) {
+                    tree.insert(&path, entry.header().entry_type().is_dir());
+                }
+            }
+        }
+    }
+
+    Ok(tree)
+}
+
+/// Lists the members of an archive under `directory.path`, which must point into the archive
+/// at `archive_path`. Entry paths continue the archive path, so they can be requested again.
+pub fn get_archive_structure(
+    directory: &mut Directory,
+    archive_path: &Path,
+    recursive: bool,
+) -> io::Result<()> {
+    let inner_path = directory
+        .path
+        .strip_prefix(archive_path)
+        .map_err(|_| not_an_archive())?
+        .to_owned();
+
+    let tree = read_member_tree(archive_path)?;
+    match tree.get(&inner_path) {
+        Some(node) if node.is_dir => {
+            node.push_entries(directory, recursive);
+            Ok(())
+        }
+        Some(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{:?} is not a directory", directory.path),
+        )),
+        None => Err(member_not_found(&inner_path)),
+    }
+}
+
+/// Streams a single member of an archive without extracting the others. The archive is read
+/// on a blocking thread, resolving to the size of the member once it has been found.
+pub async fn stream_member(
+    archive_path: PathBuf,
+    member_path: PathBuf,
+) -> io::Result<(u64, impl Stream<Item = Result<Bytes, io::Error>>)> {
+    let (size_sender, size_receiver) = oneshot::channel();
+    let (chunk_sender, chunk_receiver) = mpsc::channel(4);
+
+    actix_web::rt::task::spawn_blocking(move || {
+        let mut size_sender = Some(size_sender);
+        let result = with_member(&archive_path, &member_path, |size, reader| {
+            let size_sent = size_sender
+                .take()
+                .is_some_and(|sender| sender.send(Ok(size)).is_ok());
+            if !size_sent {
+                return Ok(());
+            }
+
+            let mut buffer = vec![0; CHUNK_SIZE];
+            loop {
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => Ok(Bytes::copy_from_slice(&buffer[..n])),
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+
+                // Stop reading once the client is gone
+                if chunk_sender.blocking_send(chunk).is_err() || failed {
+                    return Ok(());
+                }
+            }
+        });
+
+        if let (Err(err), Some(sender)) = (result, size_sender) {
+            let _ = sender.send(Err(err));
+        }
+    });
+
+    let size = size_receiver.await.map_err(io::Error::other)??;
+
+    let chunks = stream::unfold(chunk_receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    Ok((size, chunks))
+}
+
+/// Reads a whole member into memory, failing when it is larger than `max_size`
+pub fn read_member(archive_path: &Path, member_path: &Path, max_size: u64) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    with_member(archive_path, member_path, |size, reader| {
+        if size > max_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{member_path:?} is larger than {max_size} bytes"),
+            ));
+        }
+
+        reader.read_to_end(&mut content).map(|_| ())
+    })?;
+
+    Ok(content)
+}
+
+/// Finds a file member and hands its size and a reader over its content to `read`
+fn with_member(
+    archive_path: &Path,
+    member_path: &Path,
+    read: impl FnOnce(u64, &mut dyn Read) -> io::Result<()>,
+) -> io::Result<()> {
+    let kind = ArchiveKind::from_path(archive_path).ok_or_else(not_an_archive)?;
+    let member_path =
+        normalize_member_path(member_path).ok_or_else(|| member_not_found(member_path))?;
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(archive_path)?))
+                .map_err(to_io_error)?;
+
+            // Names are matched after normalization, so look up the stored name first
+            let name = archive
+                .file_names()
+                .find(|name| {
+                    !name.ends_with('/')
+                        && normalize_member_path(Path::new(name)).as_ref() == Some(&member_path)
+                })
+                .ok_or_else(|| member_not_found(&member_path))?
+                .to_owned();
+
+            let mut file = archive.by_name(&name).map_err(to_io_error)?;
+            read(file.size(), &mut file)
+        }
+        ArchiveKind::Tar | ArchiveKind::TarGz => {
+            let mut archive = open_tar(archive_path, kind)?;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let is_member = entry.header().entry_type().is_file()
+                    && normalize_member_path(&entry.path()?).as_ref() == Some(&member_path);
+
+                if is_member {
+                    return read(entry.size(), &mut entry);
+                }
+            }
+
+            Err(member_not_found(&member_path))
+        }
+    }
+}
+
+/// Files of a tar or zip archive served without extracting it. Members are indexed when the
+/// storage is opened, the archive is only read again for their content. It can't be written to.
+#[derive(Debug)]
+pub struct ArchiveStorage {
+    archive_path: PathBuf,
+    kind: ArchiveKind,
+    /// Every member by normalized path, the root being the empty path
+    members: BTreeMap<PathBuf, ArchiveMember>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ArchiveMember {
+    metadata: StorageMetadata,
+    /// Where the content of a file is, `None` for directories
+    content: Option<MemberContent>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MemberContent {
+    /// Stored as is at `offset`, in the decompressed stream for compressed tarballs
+    Stored { offset: u64 },
+    /// Zip member deflated into `compressed_size` bytes at `offset`
+    Deflated { offset: u64, compressed_size: u64 },
+    /// Compressed with a method that isn't supported
+    Unsupported,
+}
+
+impl ArchiveStorage {
+    pub fn open(archive_path: &Path) -> io::Result<Self> {
+        let kind = ArchiveKind::from_path(archive_path).ok_or_else(not_an_archive)?;
+        // Members without a time of their own are as old as the archive
+        let archive_modified = archive_path.metadata()?.modified().ok();
+        let mut storage = Self {
+            archive_path: archive_path.to_owned(),
+            kind,
+            members: BTreeMap::new(),
+        };
+        storage.insert_dir(Path::new(""), archive_modified);
+
+        match kind {
+            ArchiveKind::Zip => {
+                let mut archive = zip::ZipArchive::new(BufReader::new(File::open(archive_path)?))
+                    .map_err(to_io_error)?;
+                for index in 0..archive.len() {
+                    let file = archive.by_index_raw(index).map_err(to_io_error)?;
+                    let Some(path) = normalize_member_path(Path::new(file.name())) else {
+                        continue;
+                    };
+
+                    if file.is_dir() {
+                        storage.insert_dir(&path, archive_modified);
+                        continue;
+                    }
+                    let content = match file.compression() {
+                        CompressionMethod::Stored => MemberContent::Stored {
+                            offset: file.data_start(),
+                        },
+                        CompressionMethod::Deflated => MemberContent::Deflated {
+                            offset: file.data_start(),
+                            compressed_size: file.compressed_size(),
+                        },
+                        _ => MemberContent::Unsupported,
+                    };
+                    storage.insert_file(&path, file.size(), archive_modified, content);
+                }
+            }
+            ArchiveKind::Tar | ArchiveKind::TarGz => {
+                let mut archive = open_tar(archive_path, kind)?;
+                for entry in archive.entries()? {
+                    let entry = entry?;
+                    let Some(path) = normalize_member_path(&entry.path()?) else {
+                        continue;
+                    };
+                    let modified = entry
+                        .header()
+                        .mtime()
+                        .ok()
+                        .map(|mtime| UNIX_EPOCH + Duration::from_secs(mtime));
+
+                    let entry_type = entry.header().entry_type();
+                    if entry_type.is_dir() {
+                        storage.insert_dir(&path, modified);
+                    } else if entry_type.is_file() {
+                        let content = MemberContent::Stored {
+                            offset: entry.raw_file_position(),
+                        };
+                        storage.insert_file(&path, entry.size(), modified, content);
+                    }
+                }
+            }
+        }
+
+        Ok(storage)
+    }
+
+    /// Number of files and directories in the archive
+    pub fn len(&self) -> usize {
+        self.members.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert_dir(&mut self, path: &Path, modified: Option<std::time::SystemTime>) {
+        for ancestor in path.ancestors() {
+            self.members
+                .entry(ancestor.to_owned())
+                .or_insert(ArchiveMember {
+                    metadata: StorageMetadata {
+                        is_dir: true,
+                        len: 0,
+                        modified,
+                    },
+                    content: None,
+                });
+        }
+    }
+
+    fn insert_file(
+        &mut self,
+        path: &Path,
+        len: u64,
+        modified: Option<std::time::SystemTime>,
+        content: MemberContent,
+    ) {
+        if let Some(parent) = path.parent() {
+            self.insert_dir(parent, modified);
+        }
+        // The last of duplicated members wins, like when extracting the archive
+        self.members.insert(
+            path.to_owned(),
+            ArchiveMember {
+                metadata: StorageMetadata {
+                    is_dir: false,
+                    len,
+                    modified,
+                },
+                content: Some(content),
+            },
+        );
+    }
+
+    fn member(&self, path: &Path) -> io::Result<&ArchiveMember> {
+        self.members.get(path).ok_or_else(|| member_not_found(path))
+    }
+
+    fn read_only(&self) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{:?} is served read-only", self.archive_path),
+        )
+    }
+}
+
+impl Storage for ArchiveStorage {
+    fn list(&self, path: &Path) -> io::Result<Vec<StorageEntry>> {
+        if !self.member(path)?.metadata.is_dir {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a directory", path),
+            ));
+        }
+
+        Ok(self
+            .members
+            .iter()
+            .filter(|(member_path, _)| {
+                !member_path.as_os_str().is_empty() && member_path.parent() == Some(path)
+            })
+            .filter_map(|(member_path, member)| {
+                Some(StorageEntry {
+                    name: member_path.file_name()?.to_str()?.to_owned(),
+                    metadata: member.metadata,
+                    layer: None,
+                })
+            })
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<StorageMetadata> {
+        self.member(path).map(|member| member.metadata)
+    }
+
+    fn open(&self, path: &Path, range: Option<Range<u64>>) -> io::Result<Box<dyn Read + Send>> {
+        let member = self.member(path)?;
+        let Some(content) = member.content else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is a directory", path),
+            ));
+        };
+
+        let len = member.metadata.len;
+        let range = range.unwrap_or(0..len);
+        let (start, end) = (range.start.min(len), range.end.min(len));
+        let mut file = File::open(&self.archive_path)?;
+
+        // Compressed content can't be read at random, it is decompressed up to the range
+        let mut reader: Box<dyn Read + Send> = match (content, self.kind) {
+            (MemberContent::Stored { offset }, ArchiveKind::TarGz) => {
+                let mut reader = GzDecoder::new(BufReader::new(file));
+                skip(&mut reader, offset + start)?;
+                Box::new(reader)
+            }
+            (MemberContent::Stored { offset }, _) => {
+                file.seek(SeekFrom::Start(offset + start))?;
+                Box::new(BufReader::new(file))
+            }
+            (
+                MemberContent::Deflated {
+                    offset,
+                    compressed_size,
+                },
+                _,
+            ) => {
+                file.seek(SeekFrom::Start(offset))?;
+                let mut reader = DeflateDecoder::new(BufReader::new(file.take(compressed_size)));
+                skip(&mut reader, start)?;
+                Box::new(reader)
+            }
+            (MemberContent::Unsupported, _) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{:?} is compressed with an unsupported method", path),
+                ))
+            }
+        };
+
+        reader = Box::new(reader.take(end.saturating_sub(start)));
+        Ok(reader)
+    }
+
+    fn write(&self, _path: &Path, _content: &mut dyn Read) -> io::Result<u64> {
+        Err(self.read_only())
+    }
+
+    fn create_dir(&self, _path: &Path) -> io::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn remove(&self, _path: &Path) -> io::Result<()> {
+        Err(self.read_only())
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+}
+
+/// Reads and drops the first `count` bytes of `reader`
+fn skip(reader: &mut impl Read, count: u64) -> io::Result<()> {
+    let skipped = io::copy(&mut reader.take(count), &mut io::sink())?;
+    if skipped < count {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "The archive ended before the member",
+        ));
+    }
+
+    Ok(())
+}
+
+fn open_tar(archive_path: &Path, kind: ArchiveKind) -> io::Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(archive_path)?);
+    let reader: Box<dyn Read> = match kind {
+        ArchiveKind::TarGz => Box::new(GzDecoder::new(file)),
+        _ => Box::new(file),
+    };
+
+    Ok(tar::Archive::new(reader))
+}
+
+fn not_an_archive() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "Not a supported archive")
+}
+
+fn member_not_found(member_path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No member {member_path:?} in archive"),
+    )
+}
+
+fn to_io_error(err: zip::result::ZipError) -> io::Error {
+    match err {
+        zip::result::ZipError::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::Path;
+
+use encoding_rs::{
+    DecoderResult, Encoding, BIG5, EUC_JP, EUC_KR, GBK, SHIFT_JIS, UTF_16BE, UTF_16LE, UTF_8,
+    WINDOWS_1252,
+};
+
+use crate::storage::{self, Storage};
+
+/// Encodings offered when overriding the detected one
+pub const COMMON_ENCODINGS: [&Encoding; 10] = [
+    UTF_8,
+    UTF_16LE,
+    UTF_16BE,
+    WINDOWS_1252,
+    SHIFT_JIS,
+    EUC_JP,
+    GBK,
+    BIG5,
+    EUC_KR,
+    encoding_rs::ISO_8859_15,
+];
+
+/// Legacy encodings tried when text is not UTF-8, in order of preference on ties
+const LEGACY_CANDIDATES: [&Encoding; 6] = [WINDOWS_1252, SHIFT_JIS, EUC_JP, GBK, BIG5, EUC_KR];
+
+/// Looks up an encoding by any of its WHATWG labels (e.g. `latin1`, `sjis`, `utf-8`)
+pub fn encoding_for_label(label: &str) -> Option<&'static Encoding> {
+    Encoding::for_label(label.trim().as_bytes())
+}
+
+/// Detects the encoding of text from a sample of its content.
+///
+/// A byte order mark decides, then valid UTF-8 wins. Otherwise the legacy encodings that can
+/// decode the sample are scored by how plausible the decoded characters are, e.g. kana for
+/// Shift-JIS versus accented letters for Latin-1.
+pub fn detect_encoding(sample: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+
+    match std::str::from_utf8(sample) {
+        Ok(_) => return UTF_8,
+        // The sample may end in the middle of a multi-byte character
+        Err(err) if err.error_len().is_none() => return UTF_8,
+        Err(_) => {}
+    }
+
+    let mut best = (WINDOWS_1252, i64::MIN);
+    for encoding in LEGACY_CANDIDATES {
+        if let Some(score) = score_encoding(encoding, sample) {
+            if score > best.1 {
+                best = (encoding, score);
+            }
+        }
+    }
+
+    best.0
+}
+
+/// Like [`resolve_storage_encoding`] for reading a file line by line, which splits lines on
+/// `\n` bytes and so only works for ASCII compatible encodings
+pub fn resolve_line_encoding(
+    storage: &dyn Storage,
+    path: &Path,
+    label: Option<&str>,
+) -> Result<&'static Encoding, String> {
+    let encoding = resolve_storage_encoding(storage, path, label)?;
+    if !encoding.is_ascii_compatible() {
+        return Err(format!(
+            "{} text can't be read line by line",
+            encoding.name()
+        ));
+    }
+
+    Ok(encoding)
+}
+
+/// Uses the encoding named by `label` when given, otherwise detects the encoding of the file
+/// at `path` in `storage` from its leading bytes, UTF-8 when it can't be read
+pub fn resolve_storage_encoding(
+    storage: &dyn Storage,
+    path: &Path,
+    label: Option<&str>,
+) -> Result<&'static Encoding, String> {
+    match label.filter(|label| !label.trim().is_empty()) {
+        Some(label) => encoding_for_label(label).ok_or_else(|| format!("Unknown charset {label}")),
+        None => Ok(storage::read_header(storage, path)
+            .map(|header| detect_encoding(&header))
+            .unwrap_or(UTF_8)),
+    }
+}
+
+/// Decodes text to UTF-8, replacing malformed sequences and removing a byte order mark
+pub fn decode(bytes: &[u8], encoding: &'static Encoding) -> String {
+    encoding.decode_with_bom_removal(bytes).0.into_owned()
+}
+
+/// Scores how plausible the sample is in the encoding, `None` when it can't be decoded
+fn score_encoding(encoding: &'static Encoding, sample: &[u8]) -> Option<i64> {
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut decoded =
+        String::with_capacity(decoder.max_utf8_buffer_length_without_replacement(sample.len())?);
+
+    // Not the last chunk, so a character cut off at the end of the sample is not malformed
+    let (result, _) = decoder.decode_to_string_without_replacement(sample, &mut decoded, false);
+    if matches!(result, DecoderResult::Malformed(_, _)) {
+        return None;
+    }
+
+    let score = decoded
+        .chars()
+        .map(|c| match c {
+            // Hiragana and katakana
+            '\u{3040}'..='\u{30FF}' => 3,
+            // CJK ideographs and hangul syllables
+            '\u{4E00}'..='\u{9FFF}' | '\u{AC00}'..='\u{D7A3}' => 2,
+            // Accented latin letters, except × and ÷
+            '\u{C0}'..='\u{FF}' if c != '\u{D7}' && c != '\u{F7}' => 1,
+            // Half-width katakana and rarely used Latin-1 symbols appear in misdecoded text
+            '\u{FF61}'..='\u{FF9F}' | '\u{80}'..='\u{BF}' => -1,
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => -5,
+            _ => 0,
+        })
+        .sum();
+
+    Some(score)
+}
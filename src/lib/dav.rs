@@ -0,0 +1,454 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{escape_html, storage::Storage};
+
+pub const DAV_NAMESPACE: &str = "DAV:";
+
+/// Longest lock a client can request, locks are refreshed by clients that need them longer
+pub const MAX_LOCK_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    Zero,
+    One,
+    Infinity,
+}
+
+impl Depth {
+    /// Parses a `Depth` header, `default` applies when it is missing
+    pub fn parse(header: Option<&str>, default: Depth) -> Result<Self, String> {
+        match header.map(str::trim) {
+            None => Ok(default),
+            Some("0") => Ok(Self::Zero),
+            Some("1") => Ok(Self::One),
+            Some(depth) if depth.eq_ignore_ascii_case("infinity") => Ok(Self::Infinity),
+            Some(depth) => Err(format!("{depth} is not a valid Depth")),
+        }
+    }
+}
+
+/// Write lock taken with the `LOCK` method
+#[derive(Debug, Clone)]
+pub struct Lock {
+    /// `opaquelocktoken:` URI identifying the lock
+    pub token: String,
+    /// Locked path relative to `base_dir`
+    pub path: PathBuf,
+    pub exclusive: bool,
+    /// Whether the members of a locked collection are locked too
+    pub deep: bool,
+    /// `owner` element sent by the client, as XML
+    pub owner: Option<String>,
+    pub timeout: Duration,
+    expires: Instant,
+}
+
+impl Lock {
+    /// Whether the lock applies to `path`
+    pub fn covers(&self, path: &Path) -> bool {
+        self.path == path || (self.deep && path.starts_with(&self.path))
+    }
+
+    /// `activelock` element describing the lock in `lockdiscovery` properties
+    pub fn to_xml(&self, href: &str) -> String {
+        format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope>{}</D:lockscope>\
+             <D:depth>{}</D:depth>{}<D:timeout>Second-{}</D:timeout>\
+             <D:locktoken><D:href>{}</D:href></D:locktoken>\
+             <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            if self.exclusive {
+                "<D:exclusive/>"
+            } else {
+                "<D:shared/>"
+            },
+            if self.deep { "infinity" } else { "0" },
+            self.owner.as_deref().unwrap_or_default(),
+            self.timeout.as_secs(),
+            self.token,
+            escape_xml(href),
+        )
+    }
+}
+
+/// Locks held on paths of `base_dir`. They are only kept in memory, so they are released
+/// when the server restarts.
+#[derive(Debug, Default)]
+pub struct LockTable {
+    locks: Mutex<HashMap<String, Lock>>,
+}
+
+impl LockTable {
+    /// Locks `path`, failing when a conflicting lock is held on it, on one of its ancestors or,
+    /// for deep locks, on one of its members
+    pub fn lock(
+        &self,
+        path: &Path,
+        exclusive: bool,
+        deep: bool,
+        owner: Option<String>,
+        timeout: Duration,
+    ) -> Option<Lock> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| lock.expires > Instant::now());
+
+        let conflicts = locks.values().any(|lock| {
+            let overlaps = lock.covers(path) || (deep && lock.path.starts_with(path));
+            overlaps && (exclusive || lock.exclusive)
+        });
+        if conflicts {
+            return None;
+        }
+
+        let timeout = timeout.min(MAX_LOCK_TIMEOUT);
+        let lock = Lock {
+            token: format!("opaquelocktoken:{}", uuid()),
+            path: path.to_owned(),
+            exclusive,
+            deep,
+            owner,
+            timeout,
+            expires: Instant::now() + timeout,
+        };
+
+        locks.insert(lock.token.clone(), lock.clone());
+        Some(lock)
+    }
+
+    /// Extends a lock on `path` held with one of `tokens`
+    pub fn refresh(&self, path: &Path, tokens: &[String], timeout: Duration) -> Option<Lock> {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| lock.expires > Instant::now());
+
+        let timeout = timeout.min(MAX_LOCK_TIMEOUT);
+        let token = tokens
+            .iter()
+            .find(|token| locks.get(*token).is_some_and(|lock| lock.covers(path)))?;
+        let lock = locks.get_mut(token)?;
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + timeout;
+
+        Some(lock.clone())
+    }
+
+    /// Releases the lock identified by `token` if it applies to `path`
+    pub fn unlock(&self, path: &Path, token: &str) -> bool {
+        let mut locks = self.locks.lock().unwrap();
+        match locks.get(token) {
+            Some(lock) if lock.covers(path) => locks.remove(token).is_some(),
+            _ => false,
+        }
+    }
+
+    /// Locks that apply to `path`
+    pub fn locks_on(&self, path: &Path) -> Vec<Lock> {
+        let locks = self.locks.lock().unwrap();
+        locks
+            .values()
+            .filter(|lock| lock.expires > Instant::now() && lock.covers(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Whether `path` can be modified with the submitted `tokens`. Modifying a collection
+    /// (`deep`) also requires the tokens of locks held on its members.
+    pub fn can_modify(&self, path: &Path, deep: bool, tokens: &[String]) -> bool {
+        let locks = self.locks.lock().unwrap();
+        locks.values().all(|lock| {
+            let applies = lock.covers(path) || (deep && lock.path.starts_with(path));
+            !applies || lock.expires <= Instant::now() || tokens.contains(&lock.token)
+        })
+    }
+
+    /// Drops the locks on `path` and its members once they are deleted or moved
+    pub fn remove_all(&self, path: &Path) {
+        let mut locks = self.locks.lock().unwrap();
+        locks.retain(|_, lock| !lock.path.starts_with(path));
+    }
+}
+
+/// Properties requested by a `PROPFIND` body
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PropfindRequest {
+    AllProp,
+    PropName,
+    /// Namespace and local name of each requested property
+    Prop(Vec<(String, String)>),
+}
+
+/// Parses the body of a `PROPFIND` request, an empty body asks for all properties
+pub fn parse_propfind(body: &str) -> Result<PropfindRequest, String> {
+    if body.trim().is_empty() {
+        return Ok(PropfindRequest::AllProp);
+    }
+
+    let document = roxmltree::Document::parse(body).map_err(|err| err.to_string())?;
+    let root = document.root_element();
+    if !is_dav_element(&root, "propfind") {
+        return Err("Expected a propfind element".to_owned());
+    }
+
+    for child in root.children().filter(|child| child.is_element()) {
+        if is_dav_element(&child, "allprop") {
+            return Ok(PropfindRequest::AllProp);
+        }
+        if is_dav_element(&child, "propname") {
+            return Ok(PropfindRequest::PropName);
+        }
+        if is_dav_element(&child, "prop") {
+            let properties = child
+                .children()
+                .filter(|property| property.is_element())
+                .map(|property| {
+                    let name = property.tag_name();
+                    (
+                        name.namespace().unwrap_or_default().to_owned(),
+                        name.name().to_owned(),
+                    )
+                })
+                .collect();
+            return Ok(PropfindRequest::Prop(properties));
+        }
+    }
+
+    Err("Expected allprop, propname or prop".to_owned())
+}
+
+/// Namespace and local name of the properties set or removed by a `PROPPATCH` body
+pub fn parse_proppatch(body: &str) -> Result<Vec<(String, String)>, String> {
+    let document = roxmltree::Document::parse(body).map_err(|err| err.to_string())?;
+    let root = document.root_element();
+    if !is_dav_element(&root, "propertyupdate") {
+        return Err("Expected a propertyupdate element".to_owned());
+    }
+
+    Ok(root
+        .descendants()
+        .filter(|node| is_dav_element(node, "prop"))
+        .flat_map(|prop| prop.children().filter(|property| property.is_element()))
+        .map(|property| {
+            let name = property.tag_name();
+            (
+                name.namespace().unwrap_or_default().to_owned(),
+                name.name().to_owned(),
+            )
+        })
+        .collect())
+}
+
+/// Lock requested by a `LOCK` body
+#[derive(Debug, Clone)]
+pub struct LockInfo {
+    pub exclusive: bool,
+    /// `owner` element, re-serialized with the DAV namespace prefix
+    pub owner: Option<String>,
+}
+
+pub fn parse_lockinfo(body: &str) -> Result<LockInfo, String> {
+    let document = roxmltree::Document::parse(body).map_err(|err| err.to_string())?;
+    let root = document.root_element();
+    if !is_dav_element(&root, "lockinfo") {
+        return Err("Expected a lockinfo element".to_owned());
+    }
+
+    let exclusive = !root
+        .descendants()
+        .any(|node| is_dav_element(&node, "shared"));
+    let owner = root
+        .children()
+        .find(|node| is_dav_element(node, "owner"))
+        .map(|owner| format!("<D:owner>{}</D:owner>", serialize_children(&owner)));
+
+    Ok(LockInfo { exclusive, owner })
+}
+
+/// Parses a `Timeout` header such as `Second-600` or `Infinite, Second-4100000000`
+pub fn parse_timeout(header: Option<&str>) -> Duration {
+    header
+        .into_iter()
+        .flat_map(|header| header.split(','))
+        .find_map(|timeout| match timeout.trim() {
+            "Infinite" => Some(MAX_LOCK_TIMEOUT),
+            timeout => timeout
+                .strip_prefix("Second-")?
+                .parse()
+                .ok()
+                .map(Duration::from_secs),
+        })
+        .unwrap_or(MAX_LOCK_TIMEOUT)
+}
+
+/// Lock tokens submitted in an `If` or `Lock-Token` header
+pub fn submitted_tokens(header: &str) -> Vec<String> {
+    header
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(token, _)| token.trim().to_owned())
+        .filter(|token| token.starts_with("opaquelocktoken:"))
+        .collect()
+}
+
+/// Builds a `207 Multi-Status` body
+#[derive(Debug, Default)]
+pub struct Multistatus {
+    responses: String,
+}
+
+impl Multistatus {
+    /// Adds the properties of a resource, grouped by status, e.g. `HTTP/1.1 200 OK`
+    pub fn add_propstat(&mut self, href: &str, propstats: &[(&str, String)]) {
+        self.responses.push_str("<D:response><D:href>");
+        self.responses.push_str(&escape_xml(href));
+        self.responses.push_str("</D:href>");
+        for (status, properties) in propstats {
+            self.responses.push_str(&format!(
+                "<D:propstat><D:prop>{properties}</D:prop><D:status>{status}</D:status></D:propstat>"
+            ));
+        }
+        self.responses.push_str("</D:response>");
+    }
+
+    /// Adds the status of a resource, e.g. for members that could not be deleted
+    pub fn add_status(&mut self, href: &str, status: &str) {
+        self.responses.push_str(&format!(
+            "<D:response><D:href>{}</D:href><D:status>{status}</D:status></D:response>",
+            escape_xml(href)
+        ));
+    }
+
+    pub fn finish(self) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+             <D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>",
+            self.responses
+        )
+    }
+}
+
+/// Body of a successful `LOCK` response
+pub fn lock_discovery(lock: &Lock, href: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>",
+        lock.to_xml(href)
+    )
+}
+
+/// Escapes text and attribute values in XML
+pub fn escape_xml(content: &str) -> String {
+    escape_html(content)
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Copies a file or a directory of `storage` with all of its members
+pub fn copy_recursive(storage: &dyn Storage, from: &Path, to: &Path) -> io::Result<()> {
+    if !storage.stat(from)?.is_dir {
+        return storage
+            .write(to, &mut storage.open(from, None)?)
+            .map(|_| ());
+    }
+
+    storage.create_dir(to)?;
+    for entry in storage.list(from)? {
+        copy_recursive(storage, &from.join(&entry.name), &to.join(&entry.name))?;
+    }
+
+    Ok(())
+}
+
+/// Creates a collection, failing like `MKCOL` when it exists or its parent doesn't
+pub fn create_collection(storage: &dyn Storage, path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !storage.stat(parent).is_ok_and(|metadata| metadata.is_dir) => Err(
+            io::Error::new(ErrorKind::NotFound, "The parent collection does not exist"),
+        ),
+        _ => storage.create_dir(path),
+    }
+}
+
+fn is_dav_element(node: &roxmltree::Node, name: &str) -> bool {
+    node.is_element()
+        && node.tag_name().name() == name
+        && node.tag_name().namespace() == Some(DAV_NAMESPACE)
+}
+
+/// Serializes the content of an element, keeping the text and the names of child elements
+/// which is all clients put in `owner`
+fn serialize_children(node: &roxmltree::Node) -> String {
+    node.children()
+        .map(|child| {
+            if child.is_text() {
+                escape_xml(child.text().unwrap_or_default())
+            } else if child.is_element() {
+                let name = child.tag_name();
+                let tag = match name.namespace() {
+                    Some(DAV_NAMESPACE) => format!("D:{}", name.name()),
+                    _ => name.name().to_owned(),
+                };
+                format!("<{tag}>{}</{tag}>", serialize_children(&child))
+            } else {
+                String::new()
+            }
+        })
+        .collect()
+}
+
+/// Random version 4 UUID
+fn uuid() -> String {
+    let bytes = rand::random::<u128>().to_be_bytes();
+    let mut uuid = String::with_capacity(36);
+
+    for (i, byte) in bytes.iter().enumerate() {
+        let byte = match i {
+            6 => byte & 0x0F | 0x40,
+            8 => byte & 0x3F | 0x80,
+            _ => *byte,
+        };
+        if matches!(i, 4 | 6 | 8 | 10) {
+            uuid.push('-');
+        }
+        uuid.push_str(&format!("{byte:02x}"));
+    }
+
+    uuid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        tests::{read_str, write_str},
+        MemoryStorage,
+    };
+
+    #[test]
+    fn copies_collections_with_their_members() {
+        let storage = MemoryStorage::default();
+        storage.create_dir(Path::new("a")).unwrap();
+        storage.create_dir(Path::new("a/b")).unwrap();
+        write_str(&storage, "a/b/c.txt", "c");
+
+        copy_recursive(&storage, Path::new("a"), Path::new("copy")).unwrap();
+        assert_eq!(read_str(&storage, "copy/b/c.txt"), "c");
+        assert_eq!(read_str(&storage, "a/b/c.txt"), "c");
+    }
+
+    #[test]
+    fn collections_need_their_parent() {
+        let storage = MemoryStorage::default();
+        let err = create_collection(&storage, Path::new("a/b")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        create_collection(&storage, Path::new("a")).unwrap();
+        create_collection(&storage, Path::new("a/b")).unwrap();
+        let err = create_collection(&storage, Path::new("a")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    }
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+use encoding_rs::{Encoding, UTF_8};
+
+use crate::{charset::detect_encoding, MediaType};
+
+/// Number of leading bytes read to identify a file, enough to reach the tar header magic and
+/// to tell legacy text encodings apart
+pub const SNIFF_SIZE: usize = 1024 * 64; // 64KB
+
+#[derive(Debug)]
+pub struct MagicNumber {
+    pub mime_type: &'static str,
+    pub description: &'static str,
+    offset: usize,
+    magic: &'static [u8],
+    /// Further bytes that must follow at an offset (e.g. the `WEBP` of a RIFF container)
+    suffix: Option<(usize, &'static [u8])>,
+}
+
+const fn magic(
+    mime_type: &'static str,
+    description: &'static str,
+    offset: usize,
+    magic: &'static [u8],
+) -> MagicNumber {
+    MagicNumber {
+        mime_type,
+        description,
+        offset,
+        magic,
+        suffix: None,
+    }
+}
+
+const fn magic_with_suffix(
+    mime_type: &'static str,
+    description: &'static str,
+    magic: &'static [u8],
+    suffix: (usize, &'static [u8]),
+) -> MagicNumber {
+    MagicNumber {
+        mime_type,
+        description,
+        offset: 0,
+        magic,
+        suffix: Some(suffix),
+    }
+}
+
+/// Known signatures, more specific ones before the ones they share a prefix with
+const MAGIC_NUMBERS: &[MagicNumber] = &[
+    magic("image/png", "PNG image", 0, b"\x89PNG\r\n\x1a\n"),
+    magic("image/jpeg", "JPEG image", 0, b"\xFF\xD8\xFF"),
+    magic("image/gif", "GIF image", 0, b"GIF87a"),
+    magic("image/gif", "GIF image", 0, b"GIF89a"),
+    magic_with_suffix("image/webp", "WebP image", b"RIFF", (8, b"WEBP")),
+    magic("image/bmp", "BMP image", 0, b"BM"),
+    magic("image/x-icon", "Windows icon", 0, b"\x00\x00\x01\x00"),
+    magic("image/tiff", "TIFF image", 0, b"II*\x00"),
+    magic("image/tiff", "TIFF image", 0, b"MM\x00*"),
+    magic_with_suffix("audio/wav", "WAVE audio", b"RIFF", (8, b"WAVE")),
+    magic_with_suffix("video/x-msvideo", "AVI video", b"RIFF", (8, b"AVI ")),
+    magic("audio/mpeg", "MP3 audio with ID3 tags", 0, b"ID3"),
+    magic("audio/flac", "FLAC audio", 0, b"fLaC"),
+    magic("audio/ogg", "Ogg container", 0, b"OggS"),
+    magic("video/mp4", "ISO media (MP4, MOV, ...)", 4, b"ftyp"),
+    magic("video/webm", "Matroska/WebM video", 0, b"\x1A\x45\xDF\xA3"),
+    magic("application/pdf", "PDF document", 0, b"%PDF-"),
+    magic("application/zip", "ZIP archive", 0, b"PK\x03\x04"),
+    magic("application/zip", "Empty ZIP archive", 0, b"PK\x05\x06"),
+    magic("application/gzip", "gzip compressed data", 0, b"\x1F\x8B"),
+    magic("application/x-bzip2", "bzip2 compressed data", 0, b"BZh"),
+    magic("application/x-xz", "XZ compressed data", 0, b"\xFD7zXZ\x00"),
+    magic(
+        "application/zstd",
+        "Zstandard compressed data",
+        0,
+        b"\x28\xB5\x2F\xFD",
+    ),
+    magic(
+        "application/x-7z-compressed",
+        "7-Zip archive",
+        0,
+        b"7z\xBC\xAF\x27\x1C",
+    ),
+    magic("application/x-tar", "tar archive", 257, b"ustar"),
+    magic("application/x-elf", "ELF binary", 0, b"\x7FELF"),
+    magic(
+        "application/x-mach-binary",
+        "Mach-O binary",
+        0,
+        b"\xCF\xFA\xED\xFE",
+    ),
+    magic(
+        "application/x-mach-binary",
+        "Mach-O binary",
+        0,
+        b"\xCE\xFA\xED\xFE",
+    ),
+    magic(
+        "application/vnd.microsoft.portable-executable",
+        "Windows executable",
+        0,
+        b"MZ",
+    ),
+    magic("application/wasm", "WebAssembly module", 0, b"\x00asm"),
+    magic(
+        "application/vnd.sqlite3",
+        "SQLite database",
+        0,
+        b"SQLite format 3\x00",
+    ),
+    magic(
+        "application/java-vm",
+        "Java class file",
+        0,
+        b"\xCA\xFE\xBA\xBE",
+    ),
+    magic("font/woff", "WOFF font", 0, b"wOFF"),
+    magic("font/woff2", "WOFF2 font", 0, b"wOF2"),
+];
+
+impl MagicNumber {
+    fn matches(&self, header: &[u8]) -> bool {
+        let matches_at = |offset: usize, bytes: &[u8]| {
+            header
+                .get(offset..offset + bytes.len())
+                .is_some_and(|found| found == bytes)
+        };
+
+        matches_at(self.offset, self.magic)
+            && self
+                .suffix
+                .is_none_or(|(offset, bytes)| matches_at(offset, bytes))
+    }
+}
+
+/// Reads the leading bytes of a file used for sniffing
+pub fn read_header(path: &Path) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_SIZE);
+    File::open(path)?
+        .take(SNIFF_SIZE as u64)
+        .read_to_end(&mut header)?;
+
+    Ok(header)
+}
+
+/// Identifies the type of a file by the magic number at the start of its content
+pub fn sniff(header: &[u8]) -> Option<&'static MagicNumber> {
+    MAGIC_NUMBERS
+        .iter()
+        .find(|magic_number| magic_number.matches(header))
+}
+
+/// Human readable type of a file, refined for ELF files which might be core dumps
+pub fn describe(header: &[u8]) -> Option<&'static str> {
+    let magic_number = sniff(header)?;
+
+    if magic_number.mime_type == "application/x-elf" {
+        // e_type is a 16 bit integer in the byte order given by EI_DATA
+        let e_type = match (header.get(5), header.get(16..18)) {
+            (Some(1), Some(bytes)) => u16::from_le_bytes([bytes[0], bytes[1]]),
+            (Some(2), Some(bytes)) => u16::from_be_bytes([bytes[0], bytes[1]]),
+            _ => 0,
+        };
+
+        return Some(match e_type {
+            1 => "ELF relocatable object",
+            2 => "ELF executable",
+            3 => "ELF shared object",
+            4 => "ELF core dump",
+            _ => magic_number.description,
+        });
+    }
+
+    Some(magic_number.description)
+}
+
+/// Whether the content looks binary rather than text, judging by NUL bytes like most tools do.
+/// UTF-16 text is recognized by its byte order mark.
+pub fn looks_binary(header: &[u8]) -> bool {
+    let is_utf_16 = Encoding::for_bom(header).is_some_and(|(encoding, _)| encoding != UTF_8);
+    !is_utf_16 && memchr::memchr(0, header).is_some()
+}
+
+/// Type of a file's content as sent in the `Content-Type` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedType {
+    pub mime_type: String,
+    /// Only set for text
+    pub charset: Option<&'static str>,
+}
+
+impl DetectedType {
+    fn new(mime_type: &str) -> Self {
+        Self {
+            mime_type: mime_type.to_owned(),
+            charset: None,
+        }
+    }
+
+    pub fn media_type(&self) -> MediaType {
+        MediaType::from(self.mime_type.as_str())
+    }
+
+    pub fn is_text(&self) -> bool {
+        self.mime_type.starts_with("text/")
+            || TEXT_APPLICATION_TYPES.contains(&self.mime_type.as_str())
+    }
+
+    /// Value of the `Content-Type` header, with the charset for text
+    pub fn content_type(&self) -> String {
+        match self.charset {
+            Some(charset) => format!("{}; charset={}", self.mime_type, charset),
+            None => self.mime_type.clone(),
+        }
+    }
+}
+
+/// Application types that are text and get a charset
+const TEXT_APPLICATION_TYPES: [&str; 6] = [
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/toml",
+    "application/x-sh",
+    "application/x-yaml",
+];
+
+/// Looks up the override configured for the name or extension of a file.
+/// Keys are matched case-insensitively, extensions without the leading dot.
+fn find_override<'a>(path: &Path, overrides: &'a HashMap<String, String>) -> Option<&'a String> {
+    if overrides.is_empty() {
+        return None;
+    }
+
+    let name = path.file_name()?.to_str()?.to_lowercase();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+
+    overrides
+        .get(&name)
+        .or_else(|| overrides.get(extension.as_ref()?))
+}
+
+/// Detects the type of a file by its name alone, for content that can't be sniffed cheaply
+/// (e.g. archive members)
+pub fn detect_type_by_name(path: &Path, overrides: &HashMap<String, String>) -> DetectedType {
+    detect_type_with_header(path, None, overrides)
+}
+
+/// Detects the type of a file, combining the configured overrides, its extension and its content:
+///
+/// 1. A type configured for the file name or extension always wins
+/// 2. The extension is trusted unless it claims text while the content is binary
+/// 3. Files without a known extension are identified by their magic number, falling back to
+///    `text/plain` or `application/octet-stream` depending on whether the content looks binary
+pub fn detect_type(path: &Path, overrides: &HashMap<String, String>) -> DetectedType {
+    let header = read_header(path).ok();
+    detect_type_with_header(path, header.as_deref(), overrides)
+}
+
+pub fn detect_type_with_header(
+    path: &Path,
+    header: Option<&[u8]>,
+    overrides: &HashMap<String, String>,
+) -> DetectedType {
+    let mut detected = if let Some(mime_type) = find_override(path, overrides) {
+        DetectedType::new(mime_type)
+    } else {
+        let guess = mime_guess::from_path(path)
+            .first_raw()
+            .map(DetectedType::new);
+
+        match (guess, header) {
+            (Some(guess), Some(header)) if guess.is_text() && looks_binary(header) => {
+                DetectedType::new(
+                    sniff(header).map_or("application/octet-stream", |magic_number| {
+                        magic_number.mime_type
+                    }),
+                )
+            }
+            (Some(guess), _) => guess,
+            (None, Some(header)) => match sniff(header) {
+                Some(magic_number) => DetectedType::new(magic_number.mime_type),
+                None if looks_binary(header) => DetectedType::new("application/octet-stream"),
+                None => DetectedType::new("text/plain"),
+            },
+            (None, None) => DetectedType::new("application/octet-stream"),
+        }
+    };
+
+    if detected.is_text() {
+        detected.charset = header.map(|header| detect_encoding(header).name());
+    }
+
+    detected
+}
@@ -0,0 +1,178 @@
+use std::{
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use serde::Serialize;
+use similar::{ChangeTag, TextDiff};
+
+use crate::{
+    charset::{decode, detect_encoding},
+    detect::{looks_binary, SNIFF_SIZE},
+    storage::{self, Storage},
+};
+
+/// Files larger than this are not diffed, the diff takes quadratic time in the worst case
+pub const MAX_DIFF_SIZE: u64 = 1024 * 1024 * 4; // 4MB
+
+/// Number of unchanged lines shown around changes by default
+pub const DEFAULT_CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineKind {
+    Context,
+    Delete,
+    Insert,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLine {
+    pub kind: LineKind,
+    /// 1-based line number in the old file, `None` for inserted lines
+    pub old_line: Option<usize>,
+    /// 1-based line number in the new file, `None` for deleted lines
+    pub new_line: Option<usize>,
+    /// Content without the line ending
+    pub content: String,
+}
+
+/// Row of a side-by-side diff, a side is empty when the other has more changed lines
+#[derive(Debug, Clone, Serialize)]
+pub struct SplitRow {
+    pub left: Option<DiffLine>,
+    pub right: Option<DiffLine>,
+}
+
+/// Changed lines with their context, line numbers are 1-based like in unified diff headers
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffHunk<T> {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<T>,
+}
+
+/// Reads a file to diff as UTF-8 text, decoding it from its detected encoding.
+/// Binary files and files larger than [`MAX_DIFF_SIZE`] are rejected.
+pub fn read_text(storage: &dyn Storage, path: &Path) -> io::Result<String> {
+    if storage.stat(path)?.len > MAX_DIFF_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Files larger than {MAX_DIFF_SIZE} bytes can't be compared"),
+        ));
+    }
+
+    let bytes = storage::read_to_end(storage, path, MAX_DIFF_SIZE)?;
+    let header = &bytes[..bytes.len().min(SNIFF_SIZE)];
+    if looks_binary(header) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "Binary files can't be compared",
+        ));
+    }
+
+    Ok(decode(&bytes, detect_encoding(header)))
+}
+
+/// Diffs two texts line by line in the unified format of `diff -u`
+pub fn unified_diff(
+    old_name: &str,
+    new_name: &str,
+    old: &str,
+    new: &str,
+    context: usize,
+) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(context)
+        .header(old_name, new_name)
+        .to_string()
+}
+
+/// Diffs two texts line by line into hunks with `context` unchanged lines around changes
+pub fn diff_hunks(old: &str, new: &str, context: usize) -> Vec<DiffHunk<DiffLine>> {
+    let diff = TextDiff::from_lines(old, new);
+
+    diff.grouped_ops(context)
+        .iter()
+        .filter_map(|group| {
+            let (first, last) = (group.first()?, group.last()?);
+            let old_range = first.old_range().start..last.old_range().end;
+            let new_range = first.new_range().start..last.new_range().end;
+
+            let lines = group
+                .iter()
+                .flat_map(|op| diff.iter_changes(op))
+                .map(|change| DiffLine {
+                    kind: match change.tag() {
+                        ChangeTag::Equal => LineKind::Context,
+                        ChangeTag::Delete => LineKind::Delete,
+                        ChangeTag::Insert => LineKind::Insert,
+                    },
+                    old_line: change.old_index().map(|index| index + 1),
+                    new_line: change.new_index().map(|index| index + 1),
+                    content: change.value().trim_end_matches(['\n', '\r']).to_owned(),
+                })
+                .collect();
+
+            // Empty ranges start at the line before them, as in unified diff headers
+            Some(DiffHunk {
+                old_start: old_range.start + usize::from(!old_range.is_empty()),
+                old_lines: old_range.len(),
+                new_start: new_range.start + usize::from(!new_range.is_empty()),
+                new_lines: new_range.len(),
+                lines,
+            })
+        })
+        .collect()
+}
+
+impl DiffHunk<DiffLine> {
+    /// Lays the hunk out side by side, pairing deleted lines with the lines inserted after them
+    pub fn into_split(self) -> DiffHunk<SplitRow> {
+        let mut rows = Vec::with_capacity(self.lines.len());
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+
+        let flush = |rows: &mut Vec<SplitRow>, deleted: &mut Vec<_>, inserted: &mut Vec<_>| {
+            let count = deleted.len().max(inserted.len());
+            let mut deleted = deleted.drain(..);
+            let mut inserted = inserted.drain(..);
+            rows.extend((0..count).map(|_| SplitRow {
+                left: deleted.next(),
+                right: inserted.next(),
+            }));
+        };
+
+        for line in self.lines {
+            match line.kind {
+                LineKind::Delete => {
+                    // A new block of changes starts after the insertions of the previous one
+                    if !inserted.is_empty() {
+                        flush(&mut rows, &mut deleted, &mut inserted);
+                    }
+                    deleted.push(line);
+                }
+                LineKind::Insert => inserted.push(line),
+                LineKind::Context => {
+                    flush(&mut rows, &mut deleted, &mut inserted);
+                    rows.push(SplitRow {
+                        left: Some(line.clone()),
+                        right: Some(line),
+                    });
+                }
+            }
+        }
+        flush(&mut rows, &mut deleted, &mut inserted);
+
+        DiffHunk {
+            old_start: self.old_start,
+            old_lines: self.old_lines,
+            new_start: self.new_start,
+            new_lines: self.new_lines,
+            lines: rows,
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    fs,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::storage::{LocalStorage, Storage};
+
+/// Attempts at finding a free name before giving up on an upload
+const MAX_NAME_ATTEMPTS: usize = 1000;
+/// Longest file name kept from an upload, in bytes
+const MAX_NAME_LENGTH: usize = 200;
+
+/// Turns the name of an uploaded file into a plain file name, dropping any directories and
+/// characters that are unsafe in file names
+pub fn sanitize_file_name(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut sanitized: String = name
+        .chars()
+        .filter(|c| !c.is_control() && !matches!(c, ':' | '*' | '?' | '"' | '<' | '>' | '|'))
+        .collect();
+    // Hidden files would be easy to miss
+    sanitized = sanitized.trim().trim_start_matches('.').trim().to_owned();
+
+    if sanitized.len() > MAX_NAME_LENGTH {
+        let mut end = MAX_NAME_LENGTH;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+    }
+
+    if sanitized.is_empty() {
+        "upload".to_owned()
+    } else {
+        sanitized
+    }
+}
+
+/// Name of the `attempt`th copy of a file, e.g. `report (2).pdf`
+fn numbered_name(name: &str, attempt: usize) -> String {
+    if attempt == 0 {
+        return name.to_owned();
+    }
+
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{stem} ({attempt}).{extension}"),
+        _ => format!("{name} ({attempt})"),
+    }
+}
+
+/// Moves an uploaded file into the drop box directory `dir` under `name`, numbering the name
+/// when a file already has it. Returns the name the file was stored under.
+pub fn store_upload(dir: &Path, name: &str, uploaded: &Path) -> io::Result<String> {
+    let storage = LocalStorage::new(dir);
+    for attempt in 0..MAX_NAME_ATTEMPTS {
+        let stored_name = numbered_name(name, attempt);
+        match storage.move_in(uploaded, Path::new(&stored_name), false) {
+            Ok(()) => return Ok(stored_name),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::AlreadyExists,
+        format!("Too many files are named {name}"),
+    ))
+}
+
+/// Upload received by a drop box
+#[derive(Debug, Clone, Serialize)]
+pub struct DropBoxUpload {
+    pub drop_box: String,
+    /// Name sent by the uploader
+    pub original_name: String,
+    /// Name the file was stored under
+    pub stored_name: String,
+    pub size: u64,
+    /// Address of the uploader
+    pub remote: String,
+    /// Seconds since the epoch
+    pub received_at: u64,
+}
+
+impl DropBoxUpload {
+    pub fn new(
+        drop_box: &str,
+        original_name: &str,
+        stored_name: &str,
+        size: u64,
+        remote: &str,
+    ) -> Self {
+        Self {
+            drop_box: drop_box.to_owned(),
+            original_name: original_name.to_owned(),
+            stored_name: stored_name.to_owned(),
+            size,
+            remote: remote.to_owned(),
+            received_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// File recording every upload to drop boxes as a line of JSON, for whoever has to be notified
+#[derive(Debug)]
+pub struct DropBoxLog {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl DropBoxLog {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn record(&self, upload: &DropBoxUpload) -> io::Result<()> {
+        let mut line = serde_json::to_vec(upload).map_err(io::Error::other)?;
+        line.push(b'\n');
+
+        let _guard = self.lock.lock().unwrap();
+        fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?
+            .write_all(&line)
+    }
+}
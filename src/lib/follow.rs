@@ -0,0 +1,196 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::Duration,
+};
+
+use actix_web::{
+    rt::{task, time},
+    web::Bytes,
+};
+use futures_util::{stream, Stream, StreamExt};
+use log::debug;
+
+use encoding_rs::Encoding;
+
+use crate::lines::{decode_line, read_tail};
+
+/// How often a followed file is checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Number of idle polls between keep-alive comments (~15s)
+const KEEP_ALIVE_POLLS: usize = 30;
+/// Upper bound on the amount of appended data read per poll
+const MAX_READ_SIZE: u64 = 1024 * 512; // 512KB
+
+/// Streams the last `tail` lines of a file followed by everything appended to it afterwards,
+/// formatted as server-sent events.
+///
+/// Plain `message` events carry lines, `truncated` and `rotated` events are sent when the file
+/// shrinks or is replaced by a new file (inode change) and reading restarts from its beginning.
+///
+/// Reads the tail right away, so this is meant for blocking threads. The file is then polled on
+/// blocking threads as well.
+pub fn follow_file(
+    path: PathBuf,
+    tail: usize,
+    encoding: &'static Encoding,
+) -> std::io::Result<impl Stream<Item = Result<Bytes, std::io::Error>>> {
+    let file = File::open(&path)?;
+    let metadata = file.metadata()?;
+    let initial_lines = read_tail(&path, tail, encoding)?.lines;
+
+    let follower = Follower {
+        path,
+        file,
+        position: metadata.len(),
+        inode: inode(&metadata),
+        pending: Vec::new(),
+        idle_polls: 0,
+        encoding,
+    };
+
+    let initial_event = (!initial_lines.is_empty()).then(|| lines_event(&initial_lines));
+
+    Ok(stream::iter(initial_event.map(Ok)).chain(stream::unfold(
+        follower,
+        |mut follower| async move {
+            loop {
+                time::sleep(POLL_INTERVAL).await;
+
+                let polled = task::spawn_blocking(move || {
+                    let changes = follower.poll_changes();
+                    (follower, changes)
+                })
+                .await;
+                // The follower is lost when polling panicked, which ends the stream
+                let (returned, changes) = polled.ok()?;
+                follower = returned;
+
+                match changes {
+                    Ok(Some(event)) => return Some((Ok(event), follower)),
+                    Ok(None) => continue,
+                    Err(err) => {
+                        debug!("Failed to follow {:?}: {}", follower.path, err);
+                        return Some((Err(err), follower));
+                    }
+                }
+            }
+        },
+    )))
+}
+
+struct Follower {
+    path: PathBuf,
+    file: File,
+    position: u64,
+    inode: Option<u64>,
+    /// Trailing bytes of an incomplete line waiting for its newline
+    pending: Vec<u8>,
+    idle_polls: usize,
+    encoding: &'static Encoding,
+}
+
+impl Follower {
+    fn poll_changes(&mut self) -> std::io::Result<Option<Bytes>> {
+        // The path may briefly not exist while a log is being rotated, keep the old file meanwhile
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if inode(&metadata) != self.inode {
+                self.file = File::open(&self.path)?;
+                self.inode = inode(&self.file.metadata()?);
+                self.reset();
+                return Ok(Some(named_event("rotated")));
+            }
+        }
+
+        let len = self.file.metadata()?.len();
+        if len < self.position {
+            self.reset();
+            return Ok(Some(named_event("truncated")));
+        }
+
+        if len == self.position {
+            self.idle_polls += 1;
+            if self.idle_polls >= KEEP_ALIVE_POLLS {
+                self.idle_polls = 0;
+                return Ok(Some(Bytes::from_static(b": keep-alive\n\n")));
+            }
+            return Ok(None);
+        }
+
+        self.file.seek(SeekFrom::Start(self.position))?;
+        let read = (&mut self.file)
+            .take(MAX_READ_SIZE)
+            .read_to_end(&mut self.pending)?;
+        self.position += read as u64;
+
+        let complete = match self.pending.iter().rposition(|&byte| byte == b'\n') {
+            Some(last_newline) => self.pending.drain(..=last_newline).collect::<Vec<_>>(),
+            // Flush overly long lines instead of buffering them forever
+            None if self.pending.len() as u64 >= MAX_READ_SIZE => {
+                let mut complete = std::mem::take(&mut self.pending);
+                complete.push(b'\n');
+                complete
+            }
+            None => return Ok(None),
+        };
+
+        self.idle_polls = 0;
+        // `complete` ends with a newline, which must not produce an extra empty line
+        let lines = complete[..complete.len() - 1]
+            .split(|&byte| byte == b'\n')
+            .map(|line| decode_line(line, self.encoding))
+            .collect::<Vec<_>>();
+
+        Ok(Some(lines_event(&lines)))
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+        self.pending.clear();
+        self.idle_polls = 0;
+    }
+}
+
+/// Event carrying `lines`. A carriage return would end a data field, so it starts a new line.
+fn lines_event(lines: &[String]) -> Bytes {
+    let mut event = String::new();
+    for line in lines.iter().flat_map(|line| line.split('\r')) {
+        event.push_str("data: ");
+        event.push_str(line);
+        event.push('\n');
+    }
+    event.push('\n');
+
+    Bytes::from(event)
+}
+
+/// Events with an empty data field are dropped by browsers, so the name is repeated as data
+fn named_event(name: &str) -> Bytes {
+    Bytes::from(format!("event: {name}\ndata: {name}\n\n"))
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    Some(metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carriage_returns_start_new_data_lines() {
+        let lines = ["progress 10%\rprogress 20%".to_owned(), "done".to_owned()];
+        assert_eq!(
+            lines_event(&lines),
+            "data: progress 10%\ndata: progress 20%\ndata: done\n\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    io::{self, Read},
+    path::Path,
+};
+
+use memchr::memmem;
+
+use crate::storage::Storage;
+
+/// Upper bound on the number of bytes returned by a single ranged read
+pub const MAX_RANGE_SIZE: u64 = 1024 * 1024; // 1MB
+
+pub const HEX_BYTES_PER_ROW: usize = 16;
+
+/// Size of the chunks read while searching for a byte pattern
+const SEARCH_CHUNK_SIZE: usize = 1024 * 1024; // 1MB
+/// Longest byte pattern that can be searched for
+pub const MAX_PATTERN_SIZE: usize = 1024;
+
+#[derive(Debug)]
+pub struct ByteRange {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+    pub file_size: u64,
+}
+
+/// Reads up to `length` bytes starting at `offset`, fewer at the end of the file
+pub fn read_range(
+    storage: &dyn Storage,
+    path: &Path,
+    offset: u64,
+    length: u64,
+) -> io::Result<ByteRange> {
+    let file_size = storage.stat(path)?.len;
+    let offset = offset.min(file_size);
+
+    let mut bytes = Vec::new();
+    let end = offset.saturating_add(length.min(MAX_RANGE_SIZE));
+    storage
+        .open(path, Some(offset..end))?
+        .read_to_end(&mut bytes)?;
+
+    Ok(ByteRange {
+        offset,
+        bytes,
+        file_size,
+    })
+}
+
+/// Parses a search pattern, either hex digits with optional whitespace (`de ad be ef`) or
+/// text in double quotes (`"ELF"`)
+pub fn parse_pattern(pattern: &str) -> Result<Vec<u8>, String> {
+    let pattern = pattern.trim();
+
+    let bytes = if let Some(text) = pattern
+        .strip_prefix('"')
+        .and_then(|pattern| pattern.strip_suffix('"'))
+    {
+        text.as_bytes().to_vec()
+    } else {
+        let digits: Vec<char> = pattern
+            .trim_start_matches("0x")
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+
+        if !digits.len().is_multiple_of(2) {
+            return Err(format!("{pattern} has an odd number of hex digits"));
+        }
+
+        digits
+            .chunks(2)
+            .map(|pair| {
+                let pair: String = pair.iter().collect();
+                u8::from_str_radix(&pair, 16).map_err(|_| format!("{pair} is not a hex byte"))
+            })
+            .collect::<Result<Vec<u8>, String>>()?
+    };
+
+    if bytes.is_empty() || bytes.len() > MAX_PATTERN_SIZE {
+        return Err(format!(
+            "Patterns must be between 1 and {MAX_PATTERN_SIZE} bytes long"
+        ));
+    }
+
+    Ok(bytes)
+}
+
+/// Parses an offset given in decimal or, with a `0x` prefix, in hex
+pub fn parse_offset(offset: &str) -> Result<u64, String> {
+    let offset = offset.trim();
+    let parsed = match offset.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => offset.parse(),
+    };
+
+    parsed.map_err(|_| format!("{offset} is not a valid offset"))
+}
+
+/// Finds the first occurrence of `pattern` at or after `from`, reading the file in chunks
+pub fn find_pattern(
+    storage: &dyn Storage,
+    path: &Path,
+    pattern: &[u8],
+    from: u64,
+) -> io::Result<Option<u64>> {
+    let finder = memmem::Finder::new(pattern);
+    let mut file = storage.open(path, Some(from..u64::MAX))?;
+
+    // Chunks overlap by the pattern length so matches spanning two chunks are found
+    let overlap = pattern.len().saturating_sub(1);
+    let mut buffer = vec![0; SEARCH_CHUNK_SIZE + overlap];
+    let mut buffer_offset = from;
+    let mut filled = 0;
+
+    loop {
+        let read = file.read(&mut buffer[filled..])?;
+        if read == 0 {
+            return Ok(None);
+        }
+        filled += read;
+
+        if let Some(position) = finder.find(&buffer[..filled]) {
+            return Ok(Some(buffer_offset + position as u64));
+        }
+
+        let keep = overlap.min(filled);
+        buffer.copy_within(filled - keep..filled, 0);
+        buffer_offset += (filled - keep) as u64;
+        filled = keep;
+    }
+}
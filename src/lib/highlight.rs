@@ -0,0 +1,89 @@
+use std::{ffi::OsStr, path::Path, sync::OnceLock};
+
+use syntect::{
+    highlighting::ThemeSet,
+    html::{css_for_theme_with_class_style, ClassStyle, ClassedHTMLGenerator},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::LinesWithEndings,
+};
+
+/// Files larger than this are previewed as plain text since highlighting them is too slow
+pub const MAX_HIGHLIGHT_SIZE: u64 = 1024 * 1024; // 1MB
+
+const CLASS_STYLE: ClassStyle = ClassStyle::SpacedPrefixed { prefix: "hl-" };
+const LIGHT_THEME: &str = "InspiredGitHub";
+const DARK_THEME: &str = "base16-ocean.dark";
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+/// Finds the syntax of a file by its name or extension, falling back to the first line of
+/// `header`, the beginning of its content (e.g. a shebang). Returns `None` when only plain text
+/// would match. The file itself is never opened, `path` may be relative to any storage.
+pub fn find_syntax(path: &Path, header: &[u8]) -> Option<&'static SyntaxReference> {
+    let syntax_set = syntax_set();
+    let by_name = |name: Option<&OsStr>| {
+        syntax_set.find_syntax_by_extension(name?.to_str().filter(|name| !name.is_empty())?)
+    };
+    let syntax = by_name(path.file_name())
+        .or_else(|| by_name(path.extension()))
+        .or_else(|| {
+            let first_line = header.split(|&byte| byte == b'\n').next()?;
+            syntax_set.find_syntax_by_first_line(&String::from_utf8_lossy(first_line))
+        })?;
+
+    if syntax.name == syntax_set.find_syntax_plain_text().name {
+        return None;
+    }
+
+    Some(syntax)
+}
+
+/// Highlights `content` into HTML using `hl-` prefixed classes styled by [`highlight_css`]
+pub fn highlight_to_html(content: &str, syntax: &SyntaxReference) -> String {
+    let mut generator =
+        ClassedHTMLGenerator::new_with_class_style(syntax, syntax_set(), CLASS_STYLE);
+
+    for line in LinesWithEndings::from(content) {
+        // Only fails on malformed grammars, which the bundled ones are not
+        let _ = generator.parse_html_for_line_which_includes_newline(line);
+    }
+
+    generator.finalize()
+}
+
+/// Stylesheet for highlighted code, using the dark theme when the browser prefers it
+pub fn highlight_css() -> &'static str {
+    static HIGHLIGHT_CSS: OnceLock<String> = OnceLock::new();
+    HIGHLIGHT_CSS.get_or_init(|| {
+        let themes = ThemeSet::load_defaults();
+        let light_css = css_for_theme_with_class_style(&themes.themes[LIGHT_THEME], CLASS_STYLE)
+            .unwrap_or_default();
+        let dark_css = css_for_theme_with_class_style(&themes.themes[DARK_THEME], CLASS_STYLE)
+            .unwrap_or_default();
+
+        format!("{light_css}\n@media (prefers-color-scheme: dark) {{\n{dark_css}\n}}\n")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_syntax_without_opening_the_file() {
+        // None of these exist, the name or the given first line is all there is to go on
+        let find = |path: &str, header: &str| find_syntax(Path::new(path), header.as_bytes());
+
+        assert_eq!(find("src/main.rs", "").unwrap().name, "Rust");
+        assert_eq!(find("Makefile", "").unwrap().name, "Makefile");
+        assert_eq!(
+            find("bin/deploy", "#!/bin/bash\nset -e\n").unwrap().name,
+            "Bourne Again Shell (bash)"
+        );
+        assert!(find("notes.txt", "").is_none());
+        assert!(find("bin/deploy", "").is_none());
+    }
+}
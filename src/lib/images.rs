@@ -0,0 +1,333 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::{self, File},
+    hash::{Hash, Hasher},
+    io::{self, BufReader, BufWriter},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::UNIX_EPOCH,
+};
+
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    imageops::FilterType,
+    ColorType, DynamicImage, ImageFormat,
+};
+use serde::Deserialize;
+use tokio::sync::Semaphore;
+
+pub const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
+pub const MAX_THUMBNAIL_SIZE: u32 = 1024;
+pub const DEFAULT_JPEG_QUALITY: u8 = 85;
+
+const SUPPORTED_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "gif", "webp", "bmp"];
+
+/// Shared limits protecting the server from expensive image operations
+#[derive(Debug)]
+pub struct ImageLimits {
+    /// Largest number of pixels of a decoded or generated image
+    pub max_pixels: u64,
+    /// Permits for images being decoded and encoded at the same time
+    pub concurrency: Semaphore,
+}
+
+impl ImageLimits {
+    pub fn new(max_pixels: u64, concurrency: usize) -> Self {
+        Self {
+            max_pixels,
+            concurrency: Semaphore::new(concurrency.max(1)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale to fit inside the requested box, keeping the aspect ratio
+    #[default]
+    Contain,
+    /// Scale and crop to fill the requested box exactly
+    Cover,
+}
+
+#[derive(Debug, Clone, Copy, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+    /// Always lossless, `quality` is ignored
+    Webp,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+        }
+    }
+}
+
+/// Operations applied to an image, in the order of the fields
+#[derive(Debug, Default, Clone, Hash)]
+pub struct ImageTransform {
+    /// Rotate and flip according to the EXIF orientation tag
+    pub auto_orient: bool,
+    /// Clockwise rotation in degrees, a multiple of 90
+    pub rotate: u16,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub fit: Fit,
+    /// Defaults to PNG for images with transparency and JPEG otherwise
+    pub format: Option<OutputFormat>,
+    pub quality: Option<u8>,
+}
+
+impl ImageTransform {
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.rotate.is_multiple_of(90) || self.rotate >= 360 {
+            return Err(format!(
+                "rotate must be 0, 90, 180 or 270, got {}",
+                self.rotate
+            ));
+        }
+        if matches!(self.width, Some(0)) || matches!(self.height, Some(0)) {
+            return Err("w and h must be positive".to_owned());
+        }
+        if matches!(self.quality, Some(quality) if !(1..=100).contains(&quality)) {
+            return Err("quality must be between 1 and 100".to_owned());
+        }
+
+        Ok(())
+    }
+}
+
+/// Whether thumbnails can be generated for the file, judging by its extension
+pub fn is_supported_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| SUPPORTED_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// Returns the path of a thumbnail fitting in a `size`x`size` square, generating it first if
+/// no thumbnail exists in `cache_dir` for the current modification time of the image.
+pub fn thumbnail(
+    path: &Path,
+    size: u32,
+    max_pixels: u64,
+    cache_dir: &Path,
+) -> io::Result<(PathBuf, OutputFormat)> {
+    let size = size.clamp(1, MAX_THUMBNAIL_SIZE);
+    let transform = ImageTransform {
+        auto_orient: true,
+        width: Some(size),
+        height: Some(size),
+        ..Default::default()
+    };
+
+    transform_image(path, &transform, max_pixels, cache_dir)
+}
+
+/// Returns the path of the transformed image, generating it first if no result exists in
+/// `cache_dir` for the current modification time of the image.
+pub fn transform_image(
+    path: &Path,
+    transform: &ImageTransform,
+    max_pixels: u64,
+    cache_dir: &Path,
+) -> io::Result<(PathBuf, OutputFormat)> {
+    let cache_key = cache_key(path, transform)?;
+    for format in [OutputFormat::Jpeg, OutputFormat::Png, OutputFormat::Webp] {
+        let cached_path = cache_dir.join(format!("{cache_key}.{}", format.extension()));
+        if cached_path.is_file() {
+            return Ok((cached_path, format));
+        }
+    }
+
+    let (width, height) = image::image_dimensions(path).map_err(to_io_error)?;
+    check_pixels(width, height, max_pixels)?;
+
+    let mut image = decode(path)?;
+    if transform.auto_orient {
+        image = apply_orientation(image, read_orientation(path));
+    }
+    image = match transform.rotate {
+        90 => image.rotate90(),
+        180 => image.rotate180(),
+        270 => image.rotate270(),
+        _ => image,
+    };
+
+    if let Some((width, height)) = target_size(&image, transform.width, transform.height) {
+        check_pixels(width, height, max_pixels)?;
+        image = match transform.fit {
+            Fit::Contain => image.resize(width, height, FilterType::Lanczos3),
+            Fit::Cover => image.resize_to_fill(width, height, FilterType::Lanczos3),
+        };
+    }
+
+    let format = transform.format.unwrap_or(if image.color().has_alpha() {
+        OutputFormat::Png
+    } else {
+        OutputFormat::Jpeg
+    });
+    let quality = transform.quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+
+    let cached_path = cache_dir.join(format!("{cache_key}.{}", format.extension()));
+    write_atomically(&cached_path, |temp_path| {
+        encode(&image, format, quality, temp_path)
+    })?;
+
+    Ok((cached_path, format))
+}
+
+/// Completes a partially specified size using the aspect ratio of the image
+fn target_size(
+    image: &DynamicImage,
+    width: Option<u32>,
+    height: Option<u32>,
+) -> Option<(u32, u32)> {
+    let (source_width, source_height) = (image.width().max(1) as u64, image.height().max(1) as u64);
+
+    match (width, height) {
+        (Some(width), Some(height)) => Some((width, height)),
+        (Some(width), None) => Some((
+            width,
+            (width as u64 * source_height / source_width).max(1) as u32,
+        )),
+        (None, Some(height)) => Some((
+            (height as u64 * source_width / source_height).max(1) as u32,
+            height,
+        )),
+        (None, None) => None,
+    }
+}
+
+fn check_pixels(width: u32, height: u32, max_pixels: u64) -> io::Result<()> {
+    if width as u64 * height as u64 > max_pixels {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{width}x{height} exceeds the limit of {max_pixels} pixels"),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Reads the EXIF orientation tag, 1 (no transformation) when absent
+fn read_orientation(path: &Path) -> u32 {
+    let orientation = File::open(path).ok().and_then(|file| {
+        let exif = exif::Reader::new()
+            .read_from_container(&mut BufReader::new(file))
+            .ok()?;
+        exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?
+            .value
+            .get_uint(0)
+    });
+
+    orientation.unwrap_or(1)
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn encode(image: &DynamicImage, format: OutputFormat, quality: u8, path: &Path) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        // JPEG can't store alpha or 16 bit channels
+        OutputFormat::Jpeg => JpegEncoder::new_with_quality(&mut writer, quality)
+            .encode_image(&DynamicImage::ImageRgb8(image.to_rgb8())),
+        OutputFormat::Png => image.write_to(&mut writer, ImageFormat::Png),
+        OutputFormat::Webp => {
+            let image = image.to_rgba8();
+            WebPEncoder::new_lossless(&mut writer).encode(
+                image.as_raw(),
+                image.width(),
+                image.height(),
+                ColorType::Rgba8,
+            )
+        }
+    }
+    .map_err(to_io_error)
+}
+
+/// Writes through a temporary file so concurrent readers never see partially written files
+pub(crate) fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Requests generating the same file at once each write their own temporary file
+    static NEXT_TEMP_ID: AtomicU64 = AtomicU64::new(0);
+    let temp_id = NEXT_TEMP_ID.fetch_add(1, Ordering::Relaxed);
+
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        temp_id
+    ));
+
+    write(&temp_path)
+        .and_then(|_| fs::rename(&temp_path, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temp_path);
+        })
+}
+
+/// Key identifying a derived image, changing whenever the source file is modified
+fn cache_key(path: &Path, variant: impl Hash) -> io::Result<String> {
+    let metadata = fs::metadata(path)?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    modified.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    variant.hash(&mut hasher);
+
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+fn decode(path: &Path) -> io::Result<DynamicImage> {
+    image::io::Reader::open(path)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(to_io_error)
+}
+
+fn to_io_error(err: image::ImageError) -> io::Error {
+    match err {
+        image::ImageError::IoError(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
@@ -1,33 +1,129 @@
-use std::{fs, path::PathBuf};
+use std::{
+    io::{self, ErrorKind},
+    path::{Component, Path, PathBuf},
+};
+
+use archive::{get_archive_structure, is_archive};
+use percent_encoding::{AsciiSet, CONTROLS};
+use storage::Storage;
 
 pub use models::*;
 
+pub mod archive;
+pub mod charset;
+pub mod dav;
+pub mod detect;
+pub mod diff;
+pub mod drop_box;
+pub mod follow;
+pub mod hex;
+pub mod highlight;
+pub mod images;
+pub mod lines;
+pub mod metadata;
 pub mod models;
+pub mod overlay;
+pub mod pastes;
+pub mod render;
+pub mod s3;
+pub mod shares;
+pub mod sigv4;
+pub mod storage;
+pub mod trash;
+pub mod uploads;
+pub mod versions;
 
-pub fn get_directory_structure(root_directory: &mut Directory) -> std::io::Result<()> {
-    let root_entries = fs::read_dir(&root_directory.path)?.flatten();
+/// Name of the directory in `base_dir` where the server keeps its own data (e.g. file versions)
+/// by default. It is never listed or served.
+pub const DATA_DIR_NAME: &str = ".file-server";
 
-    for root_entry in root_entries {
-        let name = root_entry
-            .file_name()
-            .to_str()
-            .unwrap_or("Unknown Filename")
-            .to_owned();
-        let path = PathBuf::from(root_entry.path().to_str().unwrap_or("Unknown Path"));
+/// Characters escaped in a segment of a URL path. `/` and `:` are escaped too, so that names
+/// can't be read as another segment or a scheme in relative links.
+pub const PATH_SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b':')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
 
-        let is_directory = match root_entry.metadata() {
-            Ok(metadata) => metadata.is_dir(),
-            _ => false,
-        };
+/// Normalizes a path relative to `base_dir`, removing `.` components and leading slashes.
+///
+/// Paths that would escape `base_dir` (`..`) or point into the server's data directory are
+/// rejected, an empty path is `base_dir` itself.
+pub fn normalize_path(relative_path: &str) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+
+    for component in Path::new(relative_path).components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{relative_path} is outside of the served directory"),
+                ))
+            }
+        }
+    }
+
+    if normalized.starts_with(DATA_DIR_NAME) {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{relative_path} is not accessible"),
+        ));
+    }
+
+    Ok(normalized)
+}
+
+/// Joins a path relative to `base_dir`, confining it to `base_dir` like [`normalize_path`]
+pub fn confine_path(base_dir: &Path, relative_path: &str) -> io::Result<PathBuf> {
+    Ok(base_dir.join(normalize_path(relative_path)?))
+}
+
+/// Lists the entries of `root_directory`, whose path is relative to the root of `storage`
+pub fn get_directory_structure(
+    storage: &dyn Storage,
+    root_directory: &mut Directory,
+) -> std::io::Result<()> {
+    for root_entry in storage.list(&root_directory.path)? {
+        let name = root_entry.name;
+        if name == DATA_DIR_NAME {
+            continue;
+        }
+        let path = root_directory.path.join(&name);
+
+        // Archives are listed as directories, their members are listed when expanded
+        let is_directory = root_entry.metadata.is_dir
+            || storage
+                .local_path(&path)
+                .is_some_and(|local_path| is_archive(&local_path));
 
         let root_dir_entry = if is_directory {
             DirectoryEntry::Directory(Directory {
                 name,
                 entries: Vec::new(),
                 path,
+                layer: root_entry.layer,
             })
         } else {
-            DirectoryEntry::File { name, path }
+            DirectoryEntry::File {
+                name,
+                path,
+                layer: root_entry.layer,
+            }
         };
 
         root_directory.entries.push(root_dir_entry);
@@ -36,34 +132,50 @@ pub fn get_directory_structure(root_directory: &mut Directory) -> std::io::Resul
     Ok(())
 }
 
-pub fn get_directory_structure_recursive(root_directory: &mut Directory) -> std::io::Result<()> {
-    let root_entries = fs::read_dir(&root_directory.path)?.flatten();
+pub fn get_directory_structure_recursive(
+    storage: &dyn Storage,
+    root_directory: &mut Directory,
+) -> std::io::Result<()> {
+    for root_entry in storage.list(&root_directory.path)? {
+        let name = root_entry.name;
+        if name == DATA_DIR_NAME {
+            continue;
+        }
+        let path = root_directory.path.join(&name);
+        let archive_path = storage
+            .local_path(&path)
+            .filter(|local_path| is_archive(local_path));
 
-    for root_entry in root_entries {
-        let name = root_entry
-            .file_name()
-            .to_str()
-            .unwrap_or("Unknown Filename")
-            .to_owned();
-        let path = PathBuf::from(root_entry.path().to_str().unwrap_or("Unknown Path"));
-
-        let is_directory = match root_entry.metadata() {
-            Ok(metadata) => metadata.is_dir(),
-            _ => false,
-        };
-
-        let root_dir_entry = if is_directory {
+        let root_dir_entry = if root_entry.metadata.is_dir {
             let mut directory = Directory {
                 name,
                 entries: Vec::new(),
                 path,
+                layer: root_entry.layer,
             };
 
             // Recursively check for entries if is directory
-            let _ = get_directory_structure_recursive(&mut directory);
+            let _ = get_directory_structure_recursive(storage, &mut directory);
+            DirectoryEntry::Directory(directory)
+        } else if let Some(archive_path) = archive_path {
+            // Archives are read from the local disk, their members are listed under the path of
+            // the archive in the storage
+            let mut directory = Directory {
+                name,
+                entries: Vec::new(),
+                path: archive_path.clone(),
+                layer: root_entry.layer,
+            };
+
+            let _ = get_archive_structure(&mut directory, &archive_path, true);
+            directory.rebase_path(&archive_path, &path);
             DirectoryEntry::Directory(directory)
         } else {
-            DirectoryEntry::File { name, path }
+            DirectoryEntry::File {
+                name,
+                path,
+                layer: root_entry.layer,
+            }
         };
 
         root_directory.entries.push(root_dir_entry);
@@ -71,3 +183,21 @@ pub fn get_directory_structure_recursive(root_directory: &mut Directory) -> std:
 
     Ok(())
 }
+
+/// Compares secrets without leaking how much of them matches through timing
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// Escapes the characters that would otherwise be interpreted as HTML markup
+pub fn escape_html(content: &str) -> String {
+    let mut sanitized_content = String::with_capacity(content.len() * 2);
+    content.chars().for_each(|c| match c {
+        '<' => sanitized_content.push_str("&lt;"),
+        '>' => sanitized_content.push_str("&gt;"),
+        '&' => sanitized_content.push_str("&amp;"),
+        c => sanitized_content.push(c),
+    });
+
+    sanitized_content
+}
@@ -0,0 +1,470 @@
+use std::{
+    collections::HashMap,
+    fs::{File, Metadata},
+    io::{self, BufRead, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use encoding_rs::Encoding;
+use serde::Serialize;
+
+use crate::charset::decode;
+
+/// Number of lines between two checkpoints of a [`LineIndex`]
+pub const LINE_INDEX_INTERVAL: usize = 1000;
+
+/// Number of line indices a [`LineIndexCache`] keeps, the least recently used being dropped
+pub const MAX_CACHED_INDICES: usize = 256;
+
+/// Longest line returned in bytes, longer lines are cut off and end with [`TRUNCATED_MARKER`]
+pub const MAX_LINE_SIZE: usize = 1024 * 16; // 16KB
+pub const TRUNCATED_MARKER: &str = "…";
+
+const SCAN_BUFFER_SIZE: usize = 1024 * 64; // 64KB
+/// Bytes at the end of the indexed content compared to tell appends from other changes
+const TAIL_CHECK_SIZE: u64 = 1024 * 4; // 4KB
+
+/// Sparse index storing the byte offset of every `LINE_INDEX_INTERVAL`th line of a file.
+///
+/// Only appends are indexed incrementally: a file that grew while the end of its indexed
+/// content stayed the same is scanned from there, any other change causes a full rebuild.
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    checkpoints: Vec<u64>,
+    newlines: usize,
+    last_line_start: u64,
+    len: u64,
+    modified: Option<SystemTime>,
+    /// Last bytes of the indexed content
+    tail: Vec<u8>,
+}
+
+impl LineIndex {
+    pub fn build(path: &Path) -> std::io::Result<Self> {
+        let mut index = Self {
+            checkpoints: vec![0],
+            newlines: 0,
+            last_line_start: 0,
+            len: 0,
+            modified: None,
+            tail: Vec::new(),
+        };
+        index.update(path)?;
+
+        Ok(index)
+    }
+
+    /// Number of lines in the file, counting a trailing line without a newline
+    pub fn total_lines(&self) -> usize {
+        if self.len > self.last_line_start {
+            self.newlines + 1
+        } else {
+            self.newlines
+        }
+    }
+
+    pub fn is_stale(&self, metadata: &Metadata) -> bool {
+        metadata.len() != self.len || metadata.modified().ok() != self.modified
+    }
+
+    /// Brings the index up to date with the file on disk
+    pub fn update(&mut self, path: &Path) -> std::io::Result<()> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+
+        // Anything other than an append invalidates the existing checkpoints
+        if !self.is_append(&mut file, &metadata)? {
+            self.checkpoints = vec![0];
+            self.newlines = 0;
+            self.last_line_start = 0;
+            self.len = 0;
+        }
+
+        file.seek(SeekFrom::Start(self.len))?;
+        let mut buffer = vec![0; SCAN_BUFFER_SIZE];
+        let mut position = self.len;
+
+        loop {
+            let n = file.read(&mut buffer)?;
+            if n == 0 {
+                break;
+            }
+
+            for newline in memchr::memchr_iter(b'\n', &buffer[..n]) {
+                self.newlines += 1;
+                self.last_line_start = position + newline as u64 + 1;
+
+                if self.newlines.is_multiple_of(LINE_INDEX_INTERVAL) {
+                    self.checkpoints.push(self.last_line_start);
+                }
+            }
+
+            position += n as u64;
+        }
+
+        self.len = position;
+        self.modified = metadata.modified().ok();
+
+        let tail_start = self.len.saturating_sub(TAIL_CHECK_SIZE);
+        file.seek(SeekFrom::Start(tail_start))?;
+        self.tail.clear();
+        (&mut file)
+            .take(self.len - tail_start)
+            .read_to_end(&mut self.tail)?;
+
+        Ok(())
+    }
+
+    /// Whether the file only had content appended since it was indexed
+    fn is_append(&self, file: &mut File, metadata: &Metadata) -> std::io::Result<bool> {
+        if metadata.len() == self.len {
+            return Ok(metadata.modified().ok() == self.modified);
+        }
+        if metadata.len() < self.len {
+            return Ok(false);
+        }
+
+        let mut tail = Vec::with_capacity(self.tail.len());
+        file.seek(SeekFrom::Start(self.len - self.tail.len() as u64))?;
+        file.take(self.tail.len() as u64).read_to_end(&mut tail)?;
+
+        Ok(tail == self.tail)
+    }
+
+    /// Byte offset of the closest checkpoint at or before `line` and the number of lines to skip
+    fn seek_position(&self, line: usize) -> (u64, usize) {
+        let checkpoint = (line / LINE_INDEX_INTERVAL).min(self.checkpoints.len() - 1);
+        (
+            self.checkpoints[checkpoint],
+            line - checkpoint * LINE_INDEX_INTERVAL,
+        )
+    }
+}
+
+/// Keeps one [`LineIndex`] for each of the last [`MAX_CACHED_INDICES`] files read, so
+/// repeated range reads don't rescan the whole file
+#[derive(Debug, Default)]
+pub struct LineIndexCache {
+    slots: Mutex<IndexSlots>,
+}
+
+/// Index of a file, empty until first built
+type IndexSlot = Arc<Mutex<Option<LineIndex>>>;
+
+#[derive(Debug, Default)]
+struct IndexSlots {
+    /// Index of each file and when it was last used
+    indices: HashMap<PathBuf, (IndexSlot, u64)>,
+    clock: u64,
+}
+
+impl LineIndexCache {
+    /// Index of `path`, built or brought up to date first. Only the index of `path` is locked
+    /// while the file is scanned, which blocks, so this is meant for blocking threads.
+    pub fn get(&self, path: &Path) -> std::io::Result<LineIndex> {
+        let slot = self.slot(path);
+        let mut cached = slot.lock().unwrap();
+        let metadata = std::fs::metadata(path)?;
+
+        match cached.as_mut() {
+            Some(index) => {
+                if index.is_stale(&metadata) {
+                    index.update(path)?;
+                }
+                Ok(index.clone())
+            }
+            None => {
+                let index = LineIndex::build(path)?;
+                *cached = Some(index.clone());
+                Ok(index)
+            }
+        }
+    }
+
+    /// Slot of the index of `path`, dropping the least recently used one past the limit
+    fn slot(&self, path: &Path) -> IndexSlot {
+        let mut slots = self.slots.lock().unwrap();
+        slots.clock += 1;
+        let clock = slots.clock;
+
+        let (slot, last_used) = slots.indices.entry(path.to_owned()).or_default();
+        *last_used = clock;
+        let slot = slot.clone();
+
+        if slots.indices.len() > MAX_CACHED_INDICES {
+            let least_recent = slots
+                .indices
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(path, _)| path.clone());
+            if let Some(least_recent) = least_recent {
+                slots.indices.remove(&least_recent);
+            }
+        }
+
+        slot
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LineRange {
+    /// Line number of the first line, absent when unknown (e.g. for tails)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_lines: Option<usize>,
+    pub lines: Vec<String>,
+}
+
+/// Reads lines `from..to` (zero based, `to` exclusive) using `index` to skip ahead, decoding
+/// them from `encoding`, which must be ASCII compatible for lines to be split correctly
+pub fn read_lines(
+    path: &Path,
+    index: &LineIndex,
+    from: usize,
+    to: usize,
+    encoding: &'static Encoding,
+) -> std::io::Result<LineRange> {
+    let total_lines = index.total_lines();
+    let from = from.min(total_lines);
+    let to = to.clamp(from, total_lines);
+
+    let (offset, mut skip) = index.seek_position(from);
+    let mut file = File::open(path)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::with_capacity(SCAN_BUFFER_SIZE, file).take(index.len - offset);
+
+    let mut lines = Vec::with_capacity(to - from);
+    let mut line = Vec::new();
+    while lines.len() < to - from {
+        line.clear();
+        let (read, truncated) = read_limited_line(&mut reader, &mut line)?;
+        if read == 0 {
+            break;
+        }
+
+        if skip > 0 {
+            skip -= 1;
+            continue;
+        }
+
+        lines.push(decode_limited_line(&line, truncated, encoding));
+    }
+
+    Ok(LineRange {
+        from: Some(from),
+        total_lines: Some(total_lines),
+        lines,
+    })
+}
+
+/// Reads the last `count` lines by scanning backwards from the end of the file
+pub fn read_tail(
+    path: &Path,
+    count: usize,
+    encoding: &'static Encoding,
+) -> std::io::Result<LineRange> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    // A trailing newline terminates the last line instead of starting a new one
+    let mut end = len;
+    if len > 0 {
+        let mut last = [0; 1];
+        file.seek(SeekFrom::Start(len - 1))?;
+        file.read_exact(&mut last)?;
+        if last[0] == b'\n' {
+            end -= 1;
+        }
+    }
+
+    let mut start = end;
+    let mut newlines = 0;
+    let mut buffer = vec![0; SCAN_BUFFER_SIZE];
+    'scan: while start > 0 && count > 0 {
+        let chunk_start = start.saturating_sub(SCAN_BUFFER_SIZE as u64);
+        let chunk = &mut buffer[..(start - chunk_start) as usize];
+        file.seek(SeekFrom::Start(chunk_start))?;
+        file.read_exact(chunk)?;
+
+        for newline in memchr::memrchr_iter(b'\n', chunk) {
+            newlines += 1;
+            if newlines == count {
+                start = chunk_start + newline as u64 + 1;
+                break 'scan;
+            }
+        }
+
+        start = chunk_start;
+    }
+
+    let mut lines = Vec::new();
+    if count > 0 {
+        file.seek(SeekFrom::Start(start))?;
+        let mut reader = BufReader::with_capacity(SCAN_BUFFER_SIZE, file).take(len - start);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let (read, truncated) = read_limited_line(&mut reader, &mut line)?;
+            if read == 0 {
+                break;
+            }
+            lines.push(decode_limited_line(&line, truncated, encoding));
+        }
+    }
+
+    Ok(LineRange {
+        from: None,
+        total_lines: None,
+        lines,
+    })
+}
+
+/// Reads a line into `line` like `read_until`, keeping at most [`MAX_LINE_SIZE`] bytes of it.
+/// Returns the number of bytes read and whether the line was cut off.
+fn read_limited_line(reader: &mut impl BufRead, line: &mut Vec<u8>) -> io::Result<(usize, bool)> {
+    let mut read = 0;
+    let mut truncated = false;
+    loop {
+        let available = reader.fill_buf()?;
+        if available.is_empty() {
+            break;
+        }
+        let (used, content_len, complete) = match memchr::memchr(b'\n', available) {
+            Some(newline) => (newline + 1, newline, true),
+            None => (available.len(), available.len(), false),
+        };
+
+        let room = MAX_LINE_SIZE - line.len().min(MAX_LINE_SIZE);
+        truncated |= content_len > room;
+        line.extend_from_slice(&available[..used.min(room)]);
+        reader.consume(used);
+        read += used;
+        if complete {
+            break;
+        }
+    }
+
+    Ok((read, truncated))
+}
+
+fn decode_limited_line(line: &[u8], truncated: bool, encoding: &'static Encoding) -> String {
+    let mut decoded = decode_line(line, encoding);
+    if truncated {
+        decoded.push_str(TRUNCATED_MARKER);
+    }
+    decoded
+}
+
+pub(crate) fn decode_line(line: &[u8], encoding: &'static Encoding) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    decode(line, encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, OpenOptions},
+        io::Write,
+        time::Duration,
+    };
+
+    use encoding_rs::UTF_8;
+
+    use super::*;
+    use crate::storage::tests::temp_dir;
+
+    fn numbered_lines(range: std::ops::Range<usize>) -> String {
+        range.map(|number| format!("line {number}\n")).collect()
+    }
+
+    fn lines_at(path: &Path, index: &LineIndex, from: usize, to: usize) -> Vec<String> {
+        read_lines(path, index, from, to, UTF_8).unwrap().lines
+    }
+
+    #[test]
+    fn appends_are_indexed() {
+        let dir = temp_dir();
+        let path = dir.join("log");
+        fs::write(&path, numbered_lines(0..1500)).unwrap();
+        let mut index = LineIndex::build(&path).unwrap();
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(numbered_lines(1500..2500).as_bytes())
+            .unwrap();
+        file.write_all(b"partial").unwrap();
+        index.update(&path).unwrap();
+
+        assert_eq!(index.total_lines(), 2501);
+        assert_eq!(index.checkpoints.len(), 3);
+        assert_eq!(
+            lines_at(&path, &index, 1999, 2001),
+            ["line 1999", "line 2000"]
+        );
+        assert_eq!(lines_at(&path, &index, 2500, 2600), ["partial"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_files_are_reindexed() {
+        let dir = temp_dir();
+        let path = dir.join("log");
+        fs::write(&path, numbered_lines(0..3000)).unwrap();
+        let mut index = LineIndex::build(&path).unwrap();
+
+        fs::write(&path, numbered_lines(0..10)).unwrap();
+        index.update(&path).unwrap();
+
+        assert_eq!(index.total_lines(), 10);
+        assert_eq!(index.checkpoints, [0]);
+        assert_eq!(lines_at(&path, &index, 8, 20), ["line 8", "line 9"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replaced_files_are_reindexed() {
+        let dir = temp_dir();
+        let path = dir.join("log");
+        fs::write(&path, "a\nb\n").unwrap();
+        let mut index = LineIndex::build(&path).unwrap();
+
+        // A longer file whose end differs from the indexed content isn't an append
+        fs::write(&path, "x\nyy\nz\n").unwrap();
+        index.update(&path).unwrap();
+        assert_eq!(index.total_lines(), 3);
+        assert_eq!(lines_at(&path, &index, 0, 3), ["x", "yy", "z"]);
+
+        // Neither is a file of the same size modified later
+        fs::write(&path, "1\n22\n3\n").unwrap();
+        let modified = index.modified.unwrap() + Duration::from_secs(1);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert!(index.is_stale(&fs::metadata(&path).unwrap()));
+        index.update(&path).unwrap();
+        assert_eq!(lines_at(&path, &index, 0, 3), ["1", "22", "3"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn long_lines_are_cut_off() {
+        let dir = temp_dir();
+        let path = dir.join("log");
+        let long_line = "x".repeat(MAX_LINE_SIZE * 3);
+        let exact_line = "y".repeat(MAX_LINE_SIZE);
+        fs::write(&path, format!("{long_line}\n{exact_line}\nshort\n")).unwrap();
+        let index = LineIndex::build(&path).unwrap();
+
+        let cut_line = format!("{}{TRUNCATED_MARKER}", &long_line[..MAX_LINE_SIZE]);
+        let expected = [cut_line.as_str(), exact_line.as_str(), "short"];
+        assert_eq!(lines_at(&path, &index, 0, 3), expected);
+        assert_eq!(read_tail(&path, 3, UTF_8).unwrap().lines, expected);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,612 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use serde::Serialize;
+use symphonia::core::{
+    formats::FormatOptions,
+    io::MediaSourceStream,
+    meta::{MetadataOptions, MetadataRevision},
+    probe::Hint,
+};
+
+/// Upper bound on the size of container headers (e.g. an MP4 `moov` box) read into memory
+const MAX_HEADER_SIZE: u64 = 1024 * 1024 * 64; // 64MB
+
+#[derive(Debug, Default, Serialize)]
+pub struct MediaMetadata {
+    pub mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<ImageMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio: Option<AudioMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video: Option<VideoMetadata>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImageMetadata {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub orientation: Option<u32>,
+    pub taken_at: Option<String>,
+    pub gps: Option<GpsPosition>,
+    /// Every EXIF field of the primary image, formatted for display
+    pub exif: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AudioMetadata {
+    pub codec: Option<String>,
+    pub duration_seconds: Option<f64>,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<usize>,
+    pub bits_per_sample: Option<u32>,
+    /// ID3, Vorbis comment or MP4 tags, keyed by their standard name when known
+    pub tags: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct VideoMetadata {
+    pub container: String,
+    pub duration_seconds: Option<f64>,
+    pub tracks: Vec<TrackMetadata>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TrackMetadata {
+    /// `video`, `audio`, `subtitle` or the raw handler/track type
+    pub kind: String,
+    pub codec: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+}
+
+/// Extracts the metadata relevant for `mime_type`, leaving sections that can't be read empty
+pub fn extract_metadata(path: &Path, mime_type: &str) -> io::Result<MediaMetadata> {
+    let mut metadata = MediaMetadata {
+        mime_type: mime_type.to_owned(),
+        ..Default::default()
+    };
+
+    match mime_type.split_once('/').map(|(kind, _)| kind) {
+        Some("image") => metadata.image = Some(image_metadata(path)?),
+        Some("audio") => metadata.audio = Some(audio_metadata(path)?),
+        Some("video") => metadata.video = Some(video_metadata(path)?),
+        _ => {}
+    }
+
+    Ok(metadata)
+}
+
+fn image_metadata(path: &Path) -> io::Result<ImageMetadata> {
+    let mut metadata = ImageMetadata::default();
+
+    if let Ok((width, height)) = image::image_dimensions(path) {
+        metadata.width = Some(width);
+        metadata.height = Some(height);
+    }
+
+    let exif = match exif::Reader::new().read_from_container(&mut BufReader::new(File::open(path)?))
+    {
+        Ok(exif) => exif,
+        // Plenty of images simply have no EXIF data
+        Err(_) => return Ok(metadata),
+    };
+
+    let field = |tag| exif.get_field(tag, exif::In::PRIMARY);
+    let text = |tag| {
+        field(tag).map(|field| {
+            field
+                .display_value()
+                .to_string()
+                .trim_matches('"')
+                .to_owned()
+        })
+    };
+
+    metadata.camera_make = text(exif::Tag::Make);
+    metadata.camera_model = text(exif::Tag::Model);
+    metadata.taken_at = text(exif::Tag::DateTimeOriginal).or_else(|| text(exif::Tag::DateTime));
+    metadata.orientation = field(exif::Tag::Orientation).and_then(|field| field.value.get_uint(0));
+
+    if metadata.width.is_none() {
+        metadata.width =
+            field(exif::Tag::PixelXDimension).and_then(|field| field.value.get_uint(0));
+        metadata.height =
+            field(exif::Tag::PixelYDimension).and_then(|field| field.value.get_uint(0));
+    }
+
+    let coordinate = |tag, reference_tag, negative_reference| {
+        let degrees = match &field(tag)?.value {
+            exif::Value::Rational(parts) if parts.len() >= 3 => {
+                parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0
+            }
+            _ => return None,
+        };
+        let is_negative = text(reference_tag)
+            .map(|reference| reference.starts_with(negative_reference))
+            .unwrap_or(false);
+
+        Some(if is_negative { -degrees } else { degrees })
+    };
+
+    if let (Some(latitude), Some(longitude)) = (
+        coordinate(exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, 'S'),
+        coordinate(exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, 'W'),
+    ) {
+        let altitude = match field(exif::Tag::GPSAltitude).map(|field| &field.value) {
+            Some(exif::Value::Rational(parts)) if !parts.is_empty() => Some(parts[0].to_f64()),
+            _ => None,
+        };
+
+        metadata.gps = Some(GpsPosition {
+            latitude,
+            longitude,
+            altitude,
+        });
+    }
+
+    metadata.exif = exif
+        .fields()
+        .filter(|field| field.ifd_num == exif::In::PRIMARY)
+        .map(|field| {
+            (
+                field.tag.to_string(),
+                field.display_value().with_unit(&exif).to_string(),
+            )
+        })
+        .collect();
+
+    Ok(metadata)
+}
+
+fn audio_metadata(path: &Path) -> io::Result<AudioMetadata> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|extension| extension.to_str()) {
+        hint.with_extension(extension);
+    }
+
+    let mut probed = symphonia::default::get_probe()
+        .format(
+            &hint,
+            source,
+            &FormatOptions::default(),
+            &MetadataOptions::default(),
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+
+    let mut metadata = AudioMetadata::default();
+
+    // Tags may precede the container (e.g. ID3v2) or be part of it (e.g. Vorbis comments)
+    if let Some(revision) = probed.metadata.get().as_ref().and_then(|log| log.current()) {
+        add_tags(&mut metadata.tags, revision);
+    }
+    if let Some(revision) = probed.format.metadata().current() {
+        add_tags(&mut metadata.tags, revision);
+    }
+
+    if let Some(track) = probed.format.default_track() {
+        let params = &track.codec_params;
+
+        metadata.codec = symphonia::default::get_codecs()
+            .get_codec(params.codec)
+            .map(|codec| codec.long_name.to_owned());
+        metadata.sample_rate = params.sample_rate;
+        metadata.channels = params.channels.map(|channels| channels.count());
+        metadata.bits_per_sample = params.bits_per_sample;
+        metadata.duration_seconds = match (params.time_base, params.n_frames) {
+            (Some(time_base), Some(frames)) => {
+                let time = time_base.calc_time(frames);
+                Some(time.seconds as f64 + time.frac)
+            }
+            (None, Some(frames)) => params
+                .sample_rate
+                .map(|sample_rate| frames as f64 / sample_rate as f64),
+            _ => None,
+        };
+    }
+
+    Ok(metadata)
+}
+
+fn add_tags(tags: &mut BTreeMap<String, String>, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        let key = match tag.std_key {
+            Some(std_key) => format!("{:?}", std_key),
+            None => tag.key.clone(),
+        };
+        // RIFF INFO chunks pad their strings with NUL bytes
+        tags.insert(key, tag.value.to_string().trim_end_matches('\0').to_owned());
+    }
+}
+
+fn video_metadata(path: &Path) -> io::Result<VideoMetadata> {
+    let mut file = File::open(path)?;
+    let mut magic = [0; 12];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    if read >= 4 && magic[..4] == [0x1A, 0x45, 0xDF, 0xA3] {
+        matroska_metadata(&mut file)
+    } else if read >= 8 && &magic[4..8] == b"ftyp" {
+        mp4_metadata(&mut file)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Unsupported video container",
+        ))
+    }
+}
+
+/// Reads the `moov` box of an ISO base media file (MP4, MOV, 3GP, ...)
+fn mp4_metadata(file: &mut File) -> io::Result<VideoMetadata> {
+    let file_len = file.metadata()?.len();
+    let mut metadata = VideoMetadata {
+        container: "mp4".to_owned(),
+        ..Default::default()
+    };
+
+    let mut position = 0;
+    while position < file_len {
+        file.seek(SeekFrom::Start(position))?;
+        let (box_type, header_len, box_len) = match read_box_header(file, file_len - position)? {
+            Some(header) => header,
+            None => break,
+        };
+
+        match &box_type {
+            b"ftyp" => {
+                let mut brand = [0; 4];
+                file.read_exact(&mut brand)?;
+                metadata.container = format!("mp4 ({})", String::from_utf8_lossy(&brand).trim());
+            }
+            b"moov" if box_len - header_len <= MAX_HEADER_SIZE => {
+                let mut moov = vec![0; (box_len - header_len) as usize];
+                file.read_exact(&mut moov)?;
+                parse_moov(&moov, &mut metadata);
+                break;
+            }
+            _ => {}
+        }
+
+        position += box_len;
+    }
+
+    Ok(metadata)
+}
+
+/// Returns the type, header length and total length of the box at the current position
+fn read_box_header(
+    reader: &mut impl Read,
+    remaining: u64,
+) -> io::Result<Option<([u8; 4], u64, u64)>> {
+    let mut header = [0; 8];
+    if remaining < 8 || reader.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+
+    let box_type = [header[4], header[5], header[6], header[7]];
+    let (header_len, box_len) =
+        match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            0 => (8, remaining),
+            1 => {
+                let mut large_size = [0; 8];
+                reader.read_exact(&mut large_size)?;
+                (16, u64::from_be_bytes(large_size))
+            }
+            size => (8, size as u64),
+        };
+
+    if box_len < header_len || box_len > remaining {
+        return Ok(None);
+    }
+
+    Ok(Some((box_type, header_len, box_len)))
+}
+
+/// Iterates over the child boxes of an in-memory box body
+fn child_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut reader = data;
+
+    loop {
+        let remaining = reader.len() as u64;
+        let (box_type, header_len, box_len) = match read_box_header(&mut reader, remaining) {
+            Ok(Some(header)) => header,
+            _ => break,
+        };
+        let body_len = (box_len - header_len) as usize;
+        boxes.push((box_type, &reader[..body_len]));
+        reader = &reader[body_len..];
+    }
+
+    boxes
+}
+
+fn parse_moov(moov: &[u8], metadata: &mut VideoMetadata) {
+    for (box_type, body) in child_boxes(moov) {
+        match &box_type {
+            b"mvhd" => metadata.duration_seconds = parse_mvhd(body),
+            b"trak" => metadata.tracks.push(parse_trak(body)),
+            _ => {}
+        }
+    }
+}
+
+fn parse_mvhd(mvhd: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match *mvhd.first()? {
+        1 => (
+            be_u32(mvhd.get(20..24)?) as u64,
+            u64::from_be_bytes(mvhd.get(24..32)?.try_into().ok()?),
+        ),
+        _ => (
+            be_u32(mvhd.get(12..16)?) as u64,
+            be_u32(mvhd.get(16..20)?) as u64,
+        ),
+    };
+
+    (timescale > 0).then(|| duration as f64 / timescale as f64)
+}
+
+fn parse_trak(trak: &[u8]) -> TrackMetadata {
+    let mut track = TrackMetadata::default();
+    let mut sample_entry = None;
+
+    for (box_type, body) in child_boxes(trak) {
+        if &box_type != b"mdia" {
+            continue;
+        }
+
+        for (box_type, body) in child_boxes(body) {
+            match &box_type {
+                b"hdlr" => {
+                    track.kind = match body.get(8..12) {
+                        Some(b"vide") => "video".to_owned(),
+                        Some(b"soun") => "audio".to_owned(),
+                        Some(b"sbtl") | Some(b"subt") | Some(b"text") => "subtitle".to_owned(),
+                        Some(handler) => String::from_utf8_lossy(handler).into_owned(),
+                        None => String::new(),
+                    }
+                }
+                b"minf" => {
+                    sample_entry = child_boxes(body)
+                        .into_iter()
+                        .find(|(box_type, _)| box_type == b"stbl")
+                        .and_then(|(_, stbl)| {
+                            child_boxes(stbl)
+                                .into_iter()
+                                .find(|(box_type, _)| box_type == b"stsd")
+                        })
+                        // Skip version, flags and entry count to reach the first sample entry
+                        .and_then(|(_, stsd)| child_boxes(stsd.get(8..)?).into_iter().next());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if let Some((codec, entry)) = sample_entry {
+        track.codec = String::from_utf8_lossy(&codec).trim().to_owned();
+
+        // Visual sample entries store the dimensions after 24 bytes of reserved/predefined fields
+        if track.kind == "video" {
+            track.width = entry
+                .get(24..26)
+                .map(|width| u16::from_be_bytes([width[0], width[1]]) as u32);
+            track.height = entry
+                .get(26..28)
+                .map(|height| u16::from_be_bytes([height[0], height[1]]) as u32);
+        }
+    }
+
+    track
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+const EBML_DOC_TYPE: u64 = 0x4282;
+const MATROSKA_SEGMENT: u64 = 0x18538067;
+const MATROSKA_INFO: u64 = 0x1549A966;
+const MATROSKA_TIMESTAMP_SCALE: u64 = 0x2AD7B1;
+const MATROSKA_DURATION: u64 = 0x4489;
+const MATROSKA_TRACKS: u64 = 0x1654AE6B;
+const MATROSKA_TRACK_ENTRY: u64 = 0xAE;
+const MATROSKA_TRACK_TYPE: u64 = 0x83;
+const MATROSKA_CODEC_ID: u64 = 0x86;
+const MATROSKA_VIDEO: u64 = 0xE0;
+const MATROSKA_PIXEL_WIDTH: u64 = 0xB0;
+const MATROSKA_PIXEL_HEIGHT: u64 = 0xBA;
+const MATROSKA_CLUSTER: u64 = 0x1F43B675;
+
+/// Reads the `Info` and `Tracks` elements of a Matroska or WebM file
+fn matroska_metadata(file: &mut File) -> io::Result<VideoMetadata> {
+    let file_len = file.metadata()?.len();
+    let mut metadata = VideoMetadata {
+        container: "matroska".to_owned(),
+        ..Default::default()
+    };
+
+    let mut reader = BufReader::new(file);
+    let mut position = 0;
+    while position < file_len {
+        reader.seek(SeekFrom::Start(position))?;
+        let (id, header_len, size) = match read_ebml_header(&mut reader)? {
+            Some(header) => header,
+            None => break,
+        };
+        let size = size.unwrap_or(file_len - position - header_len);
+
+        match id {
+            // Descend into the segment instead of skipping over it
+            MATROSKA_SEGMENT => {
+                position += header_len;
+                continue;
+            }
+            MATROSKA_INFO | MATROSKA_TRACKS if size <= MAX_HEADER_SIZE => {
+                let mut body = vec![0; size as usize];
+                reader.read_exact(&mut body)?;
+                if id == MATROSKA_INFO {
+                    parse_matroska_info(&body, &mut metadata);
+                } else {
+                    parse_matroska_tracks(&body, &mut metadata);
+                }
+            }
+            // The header of the file is an EBML element with the document type
+            0x1A45DFA3 if size <= MAX_HEADER_SIZE => {
+                let mut body = vec![0; size as usize];
+                reader.read_exact(&mut body)?;
+                if let Some((_, doc_type)) = ebml_children(&body)
+                    .into_iter()
+                    .find(|(id, _)| *id == EBML_DOC_TYPE)
+                {
+                    metadata.container = String::from_utf8_lossy(doc_type)
+                        .trim_end_matches('\0')
+                        .to_owned();
+                }
+            }
+            // Media data follows, the headers should have been seen by now
+            MATROSKA_CLUSTER => break,
+            _ => {}
+        }
+
+        position += header_len + size;
+    }
+
+    Ok(metadata)
+}
+
+fn parse_matroska_info(info: &[u8], metadata: &mut VideoMetadata) {
+    let mut timestamp_scale = 1_000_000;
+    let mut duration = None;
+
+    for (id, body) in ebml_children(info) {
+        match id {
+            MATROSKA_TIMESTAMP_SCALE => timestamp_scale = ebml_uint(body),
+            MATROSKA_DURATION => {
+                duration = match body.len() {
+                    4 => Some(f32::from_be_bytes(body.try_into().unwrap()) as f64),
+                    8 => Some(f64::from_be_bytes(body.try_into().unwrap())),
+                    _ => None,
+                }
+            }
+            _ => {}
+        }
+    }
+
+    metadata.duration_seconds = duration.map(|duration| duration * timestamp_scale as f64 / 1e9);
+}
+
+fn parse_matroska_tracks(tracks: &[u8], metadata: &mut VideoMetadata) {
+    for (id, entry) in ebml_children(tracks) {
+        if id != MATROSKA_TRACK_ENTRY {
+            continue;
+        }
+
+        let mut track = TrackMetadata::default();
+        for (id, body) in ebml_children(entry) {
+            match id {
+                MATROSKA_TRACK_TYPE => {
+                    track.kind = match ebml_uint(body) {
+                        1 => "video".to_owned(),
+                        2 => "audio".to_owned(),
+                        17 => "subtitle".to_owned(),
+                        other => other.to_string(),
+                    }
+                }
+                MATROSKA_CODEC_ID => track.codec = String::from_utf8_lossy(body).into_owned(),
+                MATROSKA_VIDEO => {
+                    for (id, body) in ebml_children(body) {
+                        match id {
+                            MATROSKA_PIXEL_WIDTH => track.width = Some(ebml_uint(body) as u32),
+                            MATROSKA_PIXEL_HEIGHT => track.height = Some(ebml_uint(body) as u32),
+                            _ => {}
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        metadata.tracks.push(track);
+    }
+}
+
+/// Reads an element ID and size, returning `None` as size when it is unknown
+fn read_ebml_header(reader: &mut impl Read) -> io::Result<Option<(u64, u64, Option<u64>)>> {
+    let (id, id_len) = match read_vint(reader, false)? {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let (size, size_len) = match read_vint(reader, true)? {
+        Some(size) => size,
+        None => return Ok(None),
+    };
+
+    let unknown_size = size == (1 << (7 * size_len)) - 1;
+    Ok(Some((
+        id,
+        id_len + size_len,
+        (!unknown_size).then_some(size),
+    )))
+}
+
+/// Reads an EBML variable length integer, keeping the length marker for IDs
+fn read_vint(reader: &mut impl Read, strip_marker: bool) -> io::Result<Option<(u64, u64)>> {
+    let mut first = [0; 1];
+    if reader.read(&mut first)? == 0 || first[0] == 0 {
+        return Ok(None);
+    }
+
+    let len = first[0].leading_zeros() as u64 + 1;
+    let mut value = if strip_marker {
+        (first[0] & 0xFF_u8.checked_shr(len as u32).unwrap_or(0)) as u64
+    } else {
+        first[0] as u64
+    };
+
+    for _ in 1..len {
+        let mut byte = [0; 1];
+        reader.read_exact(&mut byte)?;
+        value = (value << 8) | byte[0] as u64;
+    }
+
+    Ok(Some((value, len)))
+}
+
+fn ebml_children(data: &[u8]) -> Vec<(u64, &[u8])> {
+    let mut children = Vec::new();
+    let mut reader = data;
+
+    while let Ok(Some((id, _, Some(size)))) = read_ebml_header(&mut reader) {
+        let size = (size as usize).min(reader.len());
+        children.push((id, &reader[..size]));
+        reader = &reader[size..];
+    }
+
+    children
+}
+
+fn ebml_uint(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .fold(0, |value, &byte| (value << 8) | byte as u64)
+}
@@ -1,4 +1,10 @@
-use std::{cmp::Ordering, fs::{File, Metadata}, io::Read, path::PathBuf, task::Poll};
+use std::{
+    cmp::Ordering,
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+    task::Poll,
+};
 
 use actix_web::web::Bytes;
 use futures_util::Stream;
@@ -11,19 +17,28 @@ pub struct Directory {
     // TODO: Make optional to represent unvisited state (Or use enum?)
     pub entries: Vec<DirectoryEntry>,
     pub path: PathBuf,
+    /// Overlay layer the directory comes from, the topmost one having it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layer: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub enum DirectoryEntry {
     Directory(Directory),
-    File { name: String, path: PathBuf },
+    File {
+        name: String,
+        path: PathBuf,
+        /// Overlay layer the file comes from
+        #[serde(skip_serializing_if = "Option::is_none")]
+        layer: Option<String>,
+    },
 }
 
 impl DirectoryEntry {
     pub fn is_directory(&self) -> bool {
         match self {
             Self::Directory(_) => true,
-            Self::File { name: _, path: _ } => false,
+            Self::File { .. } => false,
         }
     }
 }
@@ -40,26 +55,35 @@ impl Directory {
         root.path = PathBuf::from(root.path.to_string_lossy().replacen(base_path, "", 1));
         root.entries.iter_mut().for_each(|entry| match entry {
             DirectoryEntry::Directory(dir) => Self::remove_base_path(dir, base_path),
-            DirectoryEntry::File { name: _, path } => {
+            DirectoryEntry::File { path, .. } => {
                 *path = PathBuf::from(path.to_string_lossy().replacen(base_path, "", 1))
             }
         })
     }
 
+    /// Replaces the `from` prefix of the paths of the directory and its entries with `to`
+    pub fn rebase_path(&mut self, from: &Path, to: &Path) {
+        let rebase = |path: &mut PathBuf| {
+            if let Ok(suffix) = path.strip_prefix(from) {
+                *path = match suffix.as_os_str().is_empty() {
+                    true => to.to_owned(),
+                    false => to.join(suffix),
+                };
+            }
+        };
+
+        rebase(&mut self.path);
+        self.entries.iter_mut().for_each(|entry| match entry {
+            DirectoryEntry::Directory(dir) => dir.rebase_path(from, to),
+            DirectoryEntry::File { path, .. } => rebase(path),
+        })
+    }
+
     pub fn sort_entries(&mut self) {
         use DirectoryEntry::*;
         self.entries.sort_by(|a, b| match (a, b) {
             (Directory(a), Directory(b)) => a.name.cmp(&b.name),
-            (
-                File {
-                    name: a_name,
-                    path: _,
-                },
-                File {
-                    name: b_name,
-                    path: _,
-                },
-            ) => a_name.cmp(b_name),
+            (File { name: a_name, .. }, File { name: b_name, .. }) => a_name.cmp(b_name),
             _ => {
                 if a.is_directory() {
                     Ordering::Less
@@ -74,6 +98,8 @@ impl Directory {
 #[derive(Debug, Serialize)]
 pub enum MediaType {
     TEXT,
+    /// Text with a known syntax, previewed highlighted
+    SOURCE,
     IMAGE,
     AUDIO,
     VIDEO,
@@ -82,8 +108,10 @@ pub enum MediaType {
 
 impl From<&str> for MediaType {
     fn from(mime_type: &str) -> Self {
-        let (mime_type, _subtype) = mime_type.split_once("/").unwrap();
-        match mime_type {
+        let mime_type = mime_type
+            .split_once('/')
+            .map_or(mime_type, |(kind, _)| kind);
+        match mime_type.trim() {
             "text" => MediaType::TEXT,
             "image" => MediaType::IMAGE,
             "audio" => MediaType::AUDIO,
@@ -93,53 +121,65 @@ impl From<&str> for MediaType {
     }
 }
 
-#[derive(Debug)]
+/// Streams a file of a [`Storage`](crate::storage::Storage) in chunks
 pub struct FileStream {
-    pub file: File,
-    metadata: Metadata,
+    reader: Box<dyn Read + Send>,
+    len: u64,
     bytes_read: usize,
 }
 
 impl FileStream {
-    pub fn new(file: File) -> Self {
-        let metadata = file.metadata().unwrap();
+    /// Streams the `len` bytes read by `reader`
+    pub fn new(reader: Box<dyn Read + Send>, len: u64) -> Self {
         Self {
-            file,
-            metadata,
+            reader,
+            len,
             bytes_read: 0,
         }
     }
 }
 
+impl fmt::Debug for FileStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileStream")
+            .field("len", &self.len)
+            .field("bytes_read", &self.bytes_read)
+            .finish()
+    }
+}
+
 impl Stream for FileStream {
     type Item = Result<Bytes, std::io::Error>;
 
     fn size_hint(&self) -> (usize, Option<usize>) {
-        let hint = (0, Some(self.metadata.len() as usize - self.bytes_read));
-
-        hint
+        (0, Some((self.len as usize).saturating_sub(self.bytes_read)))
     }
 
     fn poll_next(
         mut self: std::pin::Pin<&mut Self>,
         _cx: &mut std::task::Context<'_>,
     ) -> Poll<Option<Self::Item>> {
-        let mut buffer = [0;1024 * 500]; // 500KB
-        match self.file.read(&mut buffer) {
-            Ok(n) if n == 0 => {
+        let mut buffer = [0; 1024 * 500]; // 500KB
+        let read = match self.reader.read(&mut buffer) {
+            Ok(0) => {
                 debug!("Read nothing");
-                return Poll::Ready(None)
+                return Poll::Ready(None);
             }
-            Ok(n) => self.bytes_read += n,
+            Ok(n) => n,
             Err(err) => {
                 debug!("{}", err);
-                return Poll::Ready(Some(Err(err)))
+                return Poll::Ready(Some(Err(err)));
             }
         };
+        self.bytes_read += read;
 
-        let buffer = Bytes::from(Vec::from(buffer));
+        let buffer = Bytes::copy_from_slice(&buffer[..read]);
 
-        debug!("Returning {:?} bytes, bytes read: {}", buffer.len(), self.bytes_read);
+        debug!(
+            "Returning {:?} bytes, bytes read: {}",
+            buffer.len(),
+            self.bytes_read
+        );
 
         Poll::Ready(Some(Ok(buffer)))
     }
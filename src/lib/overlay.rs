@@ -0,0 +1,514 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    io::{self, ErrorKind, Read},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::storage::{copy_out, remove_local, Storage, StorageEntry, StorageMetadata};
+
+/// Prefix of the files hiding the entry named after the rest of their name in lower layers,
+/// e.g. `.wh.notes.txt` hides `notes.txt`
+pub const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Name of the marker hiding every entry lower layers have in the directory holding it, which
+/// replaced a hidden directory of theirs
+pub const OPAQUE_MARKER: &str = ".wh..wh..opq";
+
+/// Storage merged into an overlay
+#[derive(Debug, Clone)]
+pub struct OverlayLayer {
+    pub name: String,
+    pub storage: Arc<dyn Storage>,
+}
+
+impl OverlayLayer {
+    pub fn new(name: &str, storage: Arc<dyn Storage>) -> Self {
+        Self {
+            name: name.to_owned(),
+            storage,
+        }
+    }
+}
+
+/// Several storages merged into one, higher layers shadowing the entries of lower ones.
+/// Whiteout files hide entries of lower layers, opaque markers hide every entry of a directory
+/// of lower layers, and every write goes to the top layer.
+#[derive(Debug)]
+pub struct OverlayStorage {
+    /// Topmost layer first
+    layers: Vec<OverlayLayer>,
+}
+
+impl OverlayStorage {
+    /// Merges `layers`, given from the bottom to the top layer which receives the writes
+    pub fn new(mut layers: Vec<OverlayLayer>) -> io::Result<Self> {
+        if layers.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "An overlay needs at least one layer",
+            ));
+        }
+        layers.reverse();
+
+        Ok(Self { layers })
+    }
+
+    fn top(&self) -> &OverlayLayer {
+        &self.layers[0]
+    }
+
+    /// Finds the layer an entry comes from, `None` when no layer has it or it is hidden
+    fn resolve(&self, path: &Path) -> Option<(&OverlayLayer, StorageMetadata)> {
+        if is_whiteout(path) {
+            return None;
+        }
+
+        for layer in &self.layers {
+            if let Ok(metadata) = layer.storage.stat(path) {
+                return Some((layer, metadata));
+            }
+            if is_hidden(layer, path) {
+                return None;
+            }
+        }
+
+        None
+    }
+
+    fn lookup(&self, path: &Path) -> io::Result<(&OverlayLayer, StorageMetadata)> {
+        self.resolve(path).ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} does not exist", path.display()),
+            )
+        })
+    }
+
+    /// Whether a layer below the top one has a visible entry at `path`
+    fn in_lower_layers(&self, path: &Path) -> bool {
+        if is_hidden(self.top(), path) {
+            return false;
+        }
+
+        for layer in &self.layers[1..] {
+            if layer.storage.stat(path).is_ok() {
+                return true;
+            }
+            if is_hidden(layer, path) {
+                return false;
+            }
+        }
+
+        false
+    }
+
+    /// Creates the directories leading to `path` in the top layer, copying up the ones only
+    /// lower layers have, and removes the whiteout of `path`
+    fn prepare_top(&self, path: &Path) -> io::Result<()> {
+        if path
+            .iter()
+            .any(|name| name.to_string_lossy().starts_with(WHITEOUT_PREFIX))
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Names starting with {WHITEOUT_PREFIX} are reserved"),
+            ));
+        }
+        let top = &self.top().storage;
+        let parent = path.parent().unwrap_or(Path::new(""));
+
+        let mut missing: Vec<&Path> = parent
+            .ancestors()
+            .filter(|ancestor| !ancestor.as_os_str().is_empty())
+            .take_while(|ancestor| top.stat(ancestor).is_err())
+            .collect();
+        missing.reverse();
+        for ancestor in missing {
+            match self.resolve(ancestor) {
+                Some((_, metadata)) if metadata.is_dir => {}
+                _ => {
+                    return Err(io::Error::new(
+                        ErrorKind::NotFound,
+                        format!("{} is not a directory", ancestor.display()),
+                    ))
+                }
+            }
+            top.create_dir(ancestor)?;
+        }
+
+        match top.remove(&whiteout_path(path)) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Marks the directory just put at `path` in the top layer as opaque when lower layers have
+    /// an entry there, which it replaces instead of merging with
+    fn cover_lower_layers(&self, path: &Path) -> io::Result<()> {
+        let top = &self.top().storage;
+        let is_dir = top.stat(path).is_ok_and(|metadata| metadata.is_dir);
+        if is_dir
+            && self.layers[1..]
+                .iter()
+                .any(|layer| layer.storage.stat(path).is_ok())
+        {
+            top.write(&path.join(OPAQUE_MARKER), &mut io::empty())?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for OverlayStorage {
+    fn list(&self, path: &Path) -> io::Result<Vec<StorageEntry>> {
+        let mut entries = BTreeMap::new();
+        let mut hidden = HashSet::new();
+        let mut found = false;
+
+        for layer in &self.layers {
+            match layer.storage.stat(path) {
+                Ok(metadata) if metadata.is_dir => {
+                    found = true;
+                    let mut whiteouts = Vec::new();
+                    for entry in layer.storage.list(path)? {
+                        if let Some(name) = entry.name.strip_prefix(WHITEOUT_PREFIX) {
+                            whiteouts.push(name.to_owned());
+                        } else if !hidden.contains(&entry.name) {
+                            entries.entry(entry.name.clone()).or_insert(StorageEntry {
+                                layer: Some(layer.name.clone()),
+                                ..entry
+                            });
+                        }
+                    }
+                    hidden.extend(whiteouts);
+                }
+                // A file shadows the directories of lower layers
+                Ok(_) if found => break,
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{} is not a directory", path.display()),
+                    ))
+                }
+                Err(_) => {}
+            }
+            if is_hidden(layer, path) || is_opaque(layer, path) {
+                break;
+            }
+        }
+
+        if !found {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} does not exist", path.display()),
+            ));
+        }
+
+        Ok(entries.into_values().collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<StorageMetadata> {
+        self.lookup(path).map(|(_, metadata)| metadata)
+    }
+
+    fn open(&self, path: &Path, range: Option<Range<u64>>) -> io::Result<Box<dyn Read + Send>> {
+        let (layer, _) = self.lookup(path)?;
+        layer.storage.open(path, range)
+    }
+
+    fn write(&self, path: &Path, content: &mut dyn Read) -> io::Result<u64> {
+        if let Some((_, metadata)) = self.resolve(path) {
+            if metadata.is_dir {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is a directory", path.display()),
+                ));
+            }
+        }
+
+        self.prepare_top(path)?;
+        self.top().storage.write(path, content)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        if self.resolve(path).is_some() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+
+        self.prepare_top(path)?;
+        self.top().storage.create_dir(path)?;
+        self.cover_lower_layers(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let (layer, metadata) = self.lookup(from)?;
+        let only_in_top = std::ptr::eq(layer, self.top()) && !self.in_lower_layers(from);
+
+        if only_in_top {
+            self.prepare_top(to)?;
+            self.top().storage.rename(from, to)?;
+            return self.cover_lower_layers(to);
+        }
+        if metadata.is_dir {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} comes from a lower layer and can't be moved",
+                    from.display()
+                ),
+            ));
+        }
+
+        // Files of lower layers are copied up, then hidden
+        let mut content = layer.storage.open(from, None)?;
+        self.write(to, &mut content)?;
+        self.remove(from)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        if path.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "The root can't be removed",
+            ));
+        }
+        self.lookup(path)?;
+
+        let top = &self.top().storage;
+        if top.stat(path).is_ok() {
+            top.remove(path)?;
+        }
+        if self.in_lower_layers(path) {
+            self.prepare_top(path)?;
+            top.write(&whiteout_path(path), &mut io::empty())?;
+        }
+
+        Ok(())
+    }
+
+    fn move_in(&self, source: &Path, path: &Path, replace: bool) -> io::Result<()> {
+        if let Some((_, metadata)) = self.resolve(path) {
+            if metadata.is_dir || !replace || fs::symlink_metadata(source)?.is_dir() {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} already exists", path.display()),
+                ));
+            }
+        }
+
+        self.prepare_top(path)?;
+        self.top().storage.move_in(source, path, replace)?;
+        self.cover_lower_layers(path)
+    }
+
+    fn move_out(&self, path: &Path, destination: &Path) -> io::Result<()> {
+        let (layer, _) = self.lookup(path)?;
+        if std::ptr::eq(layer, self.top()) && !self.in_lower_layers(path) {
+            return self.top().storage.move_out(path, destination);
+        }
+
+        // Entries of lower layers are copied out, then hidden
+        copy_out(self, path, destination)?;
+        self.remove(path).inspect_err(|_| {
+            let _ = remove_local(destination);
+        })
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        // The overlay as a whole isn't a directory of the local disk
+        if path.as_os_str().is_empty() {
+            return None;
+        }
+
+        let (layer, _) = self.resolve(path)?;
+        layer.storage.local_path(path)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.top().storage.is_read_only()
+    }
+}
+
+/// Path of the whiteout hiding `path` in lower layers
+fn whiteout_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{WHITEOUT_PREFIX}{name}"))
+}
+
+fn is_whiteout(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with(WHITEOUT_PREFIX))
+}
+
+/// Whether `layer` hides `path` from lower layers with a whiteout of it or of a parent, or with
+/// an opaque parent
+fn is_hidden(layer: &OverlayLayer, path: &Path) -> bool {
+    path.ancestors()
+        .filter(|ancestor| !ancestor.as_os_str().is_empty())
+        .any(|ancestor| layer.storage.stat(&whiteout_path(ancestor)).is_ok())
+        || path
+            .ancestors()
+            .skip(1)
+            .any(|parent| is_opaque(layer, parent))
+}
+
+/// Whether the directory `path` of `layer` hides the entries lower layers have in it
+fn is_opaque(layer: &OverlayLayer, path: &Path) -> bool {
+    layer.storage.stat(&path.join(OPAQUE_MARKER)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        tests::{read_str, write_str},
+        MemoryStorage,
+    };
+
+    /// Overlay of a lower layer holding `shared.txt` and `dir/lower.txt`, and an empty top layer
+    fn overlay() -> (OverlayStorage, Arc<MemoryStorage>, Arc<MemoryStorage>) {
+        let lower = Arc::new(MemoryStorage::default());
+        write_str(lower.as_ref(), "shared.txt", "lower");
+        lower.create_dir(Path::new("dir")).unwrap();
+        write_str(lower.as_ref(), "dir/lower.txt", "lower");
+
+        let top = Arc::new(MemoryStorage::default());
+        let overlay = OverlayStorage::new(vec![
+            OverlayLayer::new("lower", lower.clone()),
+            OverlayLayer::new("top", top.clone()),
+        ])
+        .unwrap();
+
+        (overlay, lower, top)
+    }
+
+    fn names(storage: &dyn Storage, path: &str) -> Vec<String> {
+        storage
+            .list(Path::new(path))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    #[test]
+    fn top_layer_shadows_lower_layers() {
+        let (overlay, lower, _) = overlay();
+        assert_eq!(read_str(&overlay, "shared.txt"), "lower");
+
+        write_str(&overlay, "shared.txt", "top");
+        assert_eq!(read_str(&overlay, "shared.txt"), "top");
+        assert_eq!(read_str(lower.as_ref(), "shared.txt"), "lower");
+
+        let entries = overlay.list(Path::new("")).unwrap();
+        let shared = entries
+            .iter()
+            .find(|entry| entry.name == "shared.txt")
+            .unwrap();
+        assert_eq!(shared.layer.as_deref(), Some("top"));
+    }
+
+    #[test]
+    fn writes_under_lower_directories_create_them_in_the_top_layer() {
+        let (overlay, _, top) = overlay();
+        write_str(&overlay, "dir/top.txt", "top");
+
+        assert!(top.stat(Path::new("dir")).unwrap().is_dir);
+        assert_eq!(names(&overlay, "dir"), ["lower.txt", "top.txt"]);
+    }
+
+    #[test]
+    fn removing_lower_entries_hides_them_with_whiteouts() {
+        let (overlay, lower, top) = overlay();
+        overlay.remove(Path::new("shared.txt")).unwrap();
+
+        assert!(overlay.stat(Path::new("shared.txt")).is_err());
+        assert!(lower.stat(Path::new("shared.txt")).is_ok());
+        assert!(top.stat(Path::new(".wh.shared.txt")).is_ok());
+        assert_eq!(names(&overlay, ""), ["dir"]);
+
+        write_str(&overlay, "shared.txt", "again");
+        assert_eq!(read_str(&overlay, "shared.txt"), "again");
+        assert!(top.stat(Path::new(".wh.shared.txt")).is_err());
+    }
+
+    #[test]
+    fn renaming_lower_files_copies_them_up() {
+        let (overlay, lower, _) = overlay();
+        overlay
+            .rename(Path::new("dir/lower.txt"), Path::new("moved.txt"))
+            .unwrap();
+
+        assert_eq!(read_str(&overlay, "moved.txt"), "lower");
+        assert!(overlay.stat(Path::new("dir/lower.txt")).is_err());
+        assert!(lower.stat(Path::new("dir/lower.txt")).is_ok());
+    }
+
+    #[test]
+    fn moving_lower_entries_out_hides_them() {
+        let (overlay, _, _) = overlay();
+        let local_dir = crate::storage::tests::temp_dir();
+
+        overlay
+            .move_out(Path::new("dir"), &local_dir.join("dir"))
+            .unwrap();
+        assert!(overlay.stat(Path::new("dir")).is_err());
+        assert!(local_dir.join("dir/lower.txt").is_file());
+
+        overlay
+            .move_in(&local_dir.join("dir"), Path::new("dir"), false)
+            .unwrap();
+        assert_eq!(read_str(&overlay, "dir/lower.txt"), "lower");
+
+        std::fs::remove_dir_all(local_dir).unwrap();
+    }
+
+    #[test]
+    fn recreated_directories_hide_what_lower_layers_had_in_them() {
+        let (overlay, _, top) = overlay();
+        overlay.remove(Path::new("dir")).unwrap();
+        overlay.create_dir(Path::new("dir")).unwrap();
+
+        assert!(top.stat(&Path::new("dir").join(OPAQUE_MARKER)).is_ok());
+        assert!(names(&overlay, "dir").is_empty());
+        assert!(overlay.stat(Path::new("dir/lower.txt")).is_err());
+
+        write_str(&overlay, "dir/top.txt", "top");
+        assert_eq!(names(&overlay, "dir"), ["top.txt"]);
+
+        overlay.remove(Path::new("dir")).unwrap();
+        assert!(overlay.stat(Path::new("dir")).is_err());
+        assert!(overlay.stat(Path::new("dir/lower.txt")).is_err());
+    }
+
+    #[test]
+    fn directories_moved_over_hidden_ones_replace_them() {
+        let (overlay, _, _) = overlay();
+        overlay.create_dir(Path::new("new")).unwrap();
+        write_str(&overlay, "new/top.txt", "top");
+        overlay.remove(Path::new("dir")).unwrap();
+
+        overlay.rename(Path::new("new"), Path::new("dir")).unwrap();
+        assert_eq!(names(&overlay, "dir"), ["top.txt"]);
+    }
+
+    #[test]
+    fn whiteout_names_are_reserved() {
+        let (overlay, _, _) = overlay();
+        for path in [".wh.shared.txt", "dir/.wh.lower.txt", OPAQUE_MARKER] {
+            let err = overlay
+                .write(Path::new(path), &mut io::empty())
+                .unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        assert_eq!(read_str(&overlay, "shared.txt"), "lower");
+        assert_eq!(names(&overlay, "dir"), ["lower.txt"]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+
+use crate::drop_box::sanitize_file_name;
+
+/// Largest paste in bytes
+pub const MAX_PASTE_SIZE: usize = 1024 * 1024; // 1MB
+/// Longest expiry of a paste
+pub const MAX_PASTE_EXPIRY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+const ID_LENGTH: usize = 8;
+const DEFAULT_NAME: &str = "paste.txt";
+
+/// Text snippet stored as a file of the paste directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Paste {
+    pub id: String,
+    /// Name given by the author, which picks the syntax highlighting
+    pub name: String,
+    /// Name of the file holding the content in the paste directory
+    pub file_name: String,
+    pub size: u64,
+    pub created_by: String,
+    /// Seconds since the epoch
+    pub created_at: u64,
+    /// Seconds since the epoch, `None` keeps the paste until it is deleted by hand
+    pub expires_at: Option<u64>,
+    /// Whether the paste is deleted the first time it is read
+    pub burn_after_read: bool,
+}
+
+impl Paste {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at <= now().as_secs())
+    }
+}
+
+/// Parameters of a new paste
+#[derive(Debug, Clone, Default)]
+pub struct NewPaste {
+    /// File name or extension picking the syntax, e.g. `server.log` or `toml`
+    pub name: Option<String>,
+    pub expires_in: Option<Duration>,
+    pub burn_after_read: bool,
+    pub created_by: String,
+}
+
+/// Pastes kept as files of `dir`, with their details in the data directory
+#[derive(Debug)]
+pub struct PasteStore {
+    dir: PathBuf,
+    meta_dir: PathBuf,
+    /// Makes sure a paste burnt after reading is only read once
+    reads: Mutex<()>,
+}
+
+impl PasteStore {
+    pub fn new(paste_dir: &Path, data_dir: &Path) -> Self {
+        Self {
+            dir: paste_dir.to_owned(),
+            meta_dir: data_dir.join("pastes"),
+            reads: Mutex::new(()),
+        }
+    }
+
+    pub fn create(&self, content: &str, new_paste: NewPaste) -> io::Result<Paste> {
+        if content.len() > MAX_PASTE_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Pastes are limited to {MAX_PASTE_SIZE} bytes"),
+            ));
+        }
+        if new_paste
+            .expires_in
+            .is_some_and(|expires_in| expires_in.is_zero() || expires_in > MAX_PASTE_EXPIRY)
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Pastes must expire within {} days",
+                    MAX_PASTE_EXPIRY.as_secs() / 86400
+                ),
+            ));
+        }
+
+        let name = match new_paste.name.as_deref().map(str::trim) {
+            None | Some("") => DEFAULT_NAME.to_owned(),
+            // A bare extension, e.g. `rs`
+            Some(name) if !name.contains('.') && !name.contains(['/', '\\']) => {
+                format!("paste.{}", sanitize_file_name(name))
+            }
+            Some(name) => sanitize_file_name(name),
+        };
+        let extension = Path::new(&name)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("txt");
+
+        fs::create_dir_all(&self.dir)?;
+        fs::create_dir_all(&self.meta_dir)?;
+        self.expire()?;
+
+        let (id, file_name) = loop {
+            let id: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(ID_LENGTH)
+                .map(char::from)
+                .collect();
+            let file_name = format!("{id}.{extension}");
+
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.dir.join(&file_name))
+            {
+                Ok(mut file) => {
+                    io::Write::write_all(&mut file, content.as_bytes())?;
+                    break (id, file_name);
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => return Err(err),
+            }
+        };
+
+        let created_at = now();
+        let paste = Paste {
+            id,
+            name,
+            file_name,
+            size: content.len() as u64,
+            created_by: new_paste.created_by,
+            created_at: created_at.as_secs(),
+            expires_at: new_paste
+                .expires_in
+                .map(|expires_in| (created_at + expires_in).as_secs()),
+            burn_after_read: new_paste.burn_after_read,
+        };
+        fs::write(
+            self.meta_path(&paste.id)?,
+            serde_json::to_vec(&paste).map_err(io::Error::other)?,
+        )?;
+
+        Ok(paste)
+    }
+
+    /// Looks up a paste without reading it, removing it if it expired
+    pub fn get(&self, id: &str) -> io::Result<Paste> {
+        let content = fs::read(self.meta_path(id)?)?;
+        let paste: Paste = serde_json::from_slice(&content)
+            .map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+
+        if paste.is_expired() {
+            self.remove(&paste)?;
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("{id} has expired"),
+            ));
+        }
+
+        Ok(paste)
+    }
+
+    /// Reads the content of a paste, deleting it if it burns after reading
+    pub fn read(&self, id: &str) -> io::Result<(Paste, String)> {
+        let _guard = self.reads.lock().unwrap();
+        let paste = self.get(id)?;
+        let content = fs::read_to_string(self.dir.join(&paste.file_name))?;
+
+        if paste.burn_after_read {
+            self.remove(&paste)?;
+        }
+
+        Ok((paste, content))
+    }
+
+    /// Removes the expired pastes
+    pub fn expire(&self) -> io::Result<()> {
+        let entries = match fs::read_dir(&self.meta_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err),
+        };
+
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let Some(id) = name.to_str().and_then(|name| name.strip_suffix(".json")) else {
+                continue;
+            };
+            match self.get(id) {
+                Ok(_) => {}
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, paste: &Paste) -> io::Result<()> {
+        match fs::remove_file(self.dir.join(&paste.file_name)) {
+            Ok(()) => {}
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+        fs::remove_file(self.meta_path(&paste.id)?)
+    }
+
+    fn meta_path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_alphanumeric()) {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("{id} is not a paste"),
+            ));
+        }
+
+        Ok(self.meta_dir.join(format!("{id}.json")))
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
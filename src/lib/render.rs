@@ -0,0 +1,263 @@
+use std::{cmp::Ordering, io, path::Path};
+
+use encoding_rs::Encoding;
+use percent_encoding::utf8_percent_encode;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+use serde_json::Value;
+
+use crate::{charset::decode, escape_html, PATH_SEGMENT};
+
+/// Files larger than this are not rendered, only previewed raw
+pub const MAX_RENDER_SIZE: u64 = 1024 * 1024 * 10; // 10MB
+
+/// Rich previews available on top of the raw text view
+#[derive(Debug, Clone, Copy)]
+pub enum Renderer {
+    Markdown,
+    Table { delimiter: u8 },
+    Tree(TreeFormat),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum TreeFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl Renderer {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "md" | "markdown" => Some(Self::Markdown),
+            "csv" => Some(Self::Table { delimiter: b',' }),
+            "tsv" => Some(Self::Table { delimiter: b'\t' }),
+            "json" => Some(Self::Tree(TreeFormat::Json)),
+            "yaml" | "yml" => Some(Self::Tree(TreeFormat::Yaml)),
+            "toml" => Some(Self::Tree(TreeFormat::Toml)),
+            _ => None,
+        }
+    }
+}
+
+/// Renders markdown to sanitized HTML.
+///
+/// Relative links and images are resolved against `/api/v1/files/{base_path}/` where
+/// `base_path` is the directory of the markdown file relative to `base_dir`, each of its names
+/// being percent-encoded.
+pub fn render_markdown(content: &str, base_path: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TASKLISTS);
+    options.insert(Options::ENABLE_FOOTNOTES);
+
+    let parser = Parser::new_ext(content, options).map(|event| match event {
+        Event::Start(Tag::Link(link_type, destination, title)) => Event::Start(Tag::Link(
+            link_type,
+            resolve_link(destination, base_path),
+            title,
+        )),
+        Event::Start(Tag::Image(link_type, destination, title)) => Event::Start(Tag::Image(
+            link_type,
+            resolve_link(destination, base_path),
+            title,
+        )),
+        event => event,
+    });
+
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, parser);
+
+    ammonia::clean(&unsafe_html)
+}
+
+fn resolve_link<'a>(destination: CowStr<'a>, base_path: &str) -> CowStr<'a> {
+    let is_relative = !destination.is_empty()
+        && !destination.starts_with(['/', '#', '?'])
+        && !destination.contains(':');
+
+    if !is_relative {
+        return destination;
+    }
+
+    // The destination is already a URL, only the names of the directories need encoding
+    let base_url: String = base_path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| format!("{}/", utf8_percent_encode(segment, PATH_SEGMENT)))
+        .collect();
+    format!("/api/v1/files/{}{}", base_url, destination).into()
+}
+
+#[derive(Debug)]
+pub struct Table {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    pub total_rows: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TableSort {
+    pub column: usize,
+    pub descending: bool,
+}
+
+/// Reads one page of a delimited file in `encoding`, optionally sorted by a column.
+///
+/// Columns containing only numbers are sorted numerically.
+pub fn read_table(
+    path: &Path,
+    delimiter: u8,
+    encoding: &'static Encoding,
+    sort: Option<TableSort>,
+    page: usize,
+    page_size: usize,
+) -> io::Result<Table> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_path(path)?;
+
+    let decode_record = |record: &csv::ByteRecord| -> Vec<String> {
+        record.iter().map(|field| decode(field, encoding)).collect()
+    };
+
+    let headers = decode_record(reader.byte_headers()?);
+
+    let mut rows = reader
+        .byte_records()
+        .map(|record| record.map(|record| decode_record(&record)))
+        .collect::<Result<Vec<Vec<String>>, _>>()?;
+    let total_rows = rows.len();
+
+    if let Some(sort) = sort {
+        rows.sort_by(|a, b| {
+            let ordering = compare_cells(a.get(sort.column), b.get(sort.column));
+            if sort.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        });
+    }
+
+    let rows = rows
+        .into_iter()
+        .skip(page.saturating_mul(page_size))
+        .take(page_size)
+        .collect();
+
+    Ok(Table {
+        headers,
+        rows,
+        total_rows,
+    })
+}
+
+fn compare_cells(a: Option<&String>, b: Option<&String>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => a.cmp(b),
+        },
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+/// Parses a JSON, YAML or TOML document into a common representation
+pub fn parse_tree(content: &str, format: TreeFormat) -> io::Result<Value> {
+    let invalid_data = |err: String| io::Error::new(io::ErrorKind::InvalidData, err);
+
+    match format {
+        TreeFormat::Json => {
+            serde_json::from_str(content).map_err(|err| invalid_data(err.to_string()))
+        }
+        TreeFormat::Yaml => {
+            serde_yaml::from_str(content).map_err(|err| invalid_data(err.to_string()))
+        }
+        TreeFormat::Toml => toml::from_str(content).map_err(|err| invalid_data(err.to_string())),
+    }
+}
+
+/// Pretty prints a parsed document in its original format, falling back to JSON when the
+/// document can't be represented in it (e.g. `null` in TOML)
+pub fn pretty_print(value: &Value, format: TreeFormat) -> String {
+    let pretty = match format {
+        TreeFormat::Json => None,
+        TreeFormat::Yaml => serde_yaml::to_string(value).ok(),
+        TreeFormat::Toml => toml::to_string_pretty(value).ok(),
+    };
+
+    pretty.unwrap_or_else(|| serde_json::to_string_pretty(value).unwrap_or_default())
+}
+
+/// Renders a parsed document as nested, collapsible `<details>` elements with escaped content
+pub fn render_tree(value: &Value) -> String {
+    let mut html = String::from("<ul class=\"tree\">");
+    render_tree_node(None, value, &mut html);
+    html.push_str("</ul>");
+
+    html
+}
+
+fn render_tree_node(key: Option<&str>, value: &Value, html: &mut String) {
+    let key = key
+        .map(|key| format!("<span class=\"tree-key\">{}</span>: ", escape_html(key)))
+        .unwrap_or_default();
+
+    let children: Vec<(String, &Value)> = match value {
+        Value::Object(object) => object.iter().map(|(k, v)| (k.to_owned(), v)).collect(),
+        Value::Array(array) => array
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i.to_string(), v))
+            .collect(),
+        leaf => {
+            let (class, content) = match leaf {
+                Value::String(string) => ("tree-string", format!("\"{}\"", escape_html(string))),
+                Value::Null => ("tree-null", "null".to_owned()),
+                leaf => ("tree-literal", leaf.to_string()),
+            };
+            html.push_str(&format!(
+                "<li>{key}<span class=\"{class}\">{content}</span></li>"
+            ));
+            return;
+        }
+    };
+
+    let summary = match value {
+        Value::Object(_) => format!("{{…}} {} keys", children.len()),
+        _ => format!("[…] {} items", children.len()),
+    };
+
+    html.push_str(&format!(
+        "<li><details open><summary>{key}<span class=\"tree-summary\">{summary}</span></summary><ul>"
+    ));
+    for (child_key, child) in children {
+        render_tree_node(Some(&child_key), child, html);
+    }
+    html.push_str("</ul></details></li>");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_links_are_resolved_against_encoded_directories() {
+        let html = render_markdown(
+            "[next](part%202.md) [top](/readme.md) [site](https://example.com) ![](img/a.png)",
+            "notes/100% done/#1",
+        );
+
+        assert!(html.contains(r#"href="/api/v1/files/notes/100%25%20done/%231/part%202.md""#));
+        assert!(html.contains(r#"href="/readme.md""#));
+        assert!(html.contains(r#"href="https://example.com""#));
+        assert!(html.contains(r#"src="/api/v1/files/notes/100%25%20done/%231/img/a.png""#));
+        assert_eq!(
+            resolve_link("a.md".into(), "").as_ref(),
+            "/api/v1/files/a.md"
+        );
+    }
+}
@@ -0,0 +1,828 @@
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use md5::{Digest, Md5};
+use percent_encoding::{utf8_percent_encode, AsciiSet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    dav::escape_xml,
+    normalize_path,
+    sigv4::hex,
+    sigv4::URI_ENCODE,
+    storage::{entity_tag, Storage, StorageMetadata},
+    DATA_DIR_NAME,
+};
+
+pub const S3_NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+/// Most keys returned by a single listing
+pub const MAX_KEYS: usize = 1000;
+/// Highest part number of a multipart upload
+pub const MAX_PART_NUMBER: u32 = 10_000;
+
+/// Characters escaped in keys of listings requested with `encoding-type=url`
+const KEY_ENCODE: &AsciiSet = &URI_ENCODE.remove(b'/');
+
+/// Error returned to S3 clients as an XML document
+#[derive(Debug, Clone)]
+pub struct S3Error {
+    pub status: u16,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl S3Error {
+    pub fn new(status: u16, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn no_such_key(key: &str) -> Self {
+        Self::new(404, "NoSuchKey", format!("{key} does not exist"))
+    }
+
+    pub fn read_only(bucket: &str) -> Self {
+        Self::new(403, "AccessDenied", format!("{bucket} is read-only"))
+    }
+
+    pub fn not_implemented(operation: &str) -> Self {
+        Self::new(
+            501,
+            "NotImplemented",
+            format!("{operation} is not implemented"),
+        )
+    }
+
+    pub fn to_xml(&self, resource: &str) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Error><Code>{}</Code>\
+             <Message>{}</Message><Resource>{}</Resource></Error>",
+            self.code,
+            escape_xml(&self.message),
+            escape_xml(resource),
+        )
+    }
+}
+
+impl fmt::Display for S3Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+impl From<io::Error> for S3Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => Self::new(404, "NoSuchKey", err.to_string()),
+            ErrorKind::PermissionDenied => Self::new(403, "AccessDenied", err.to_string()),
+            ErrorKind::InvalidInput => Self::new(400, "InvalidArgument", err.to_string()),
+            _ => Self::new(500, "InternalError", err.to_string()),
+        }
+    }
+}
+
+/// Path relative to the root of the storage of the file stored under `key`
+pub fn key_path(key: &str) -> Result<PathBuf, S3Error> {
+    let path = normalize_path(key)?;
+    if path.as_os_str().is_empty() {
+        return Err(S3Error::new(400, "InvalidArgument", "A key is required"));
+    }
+
+    Ok(path)
+}
+
+/// Parameters of a ListObjectsV2 request
+#[derive(Debug, Clone, Default)]
+pub struct ListQuery {
+    pub prefix: String,
+    pub delimiter: Option<String>,
+    pub max_keys: usize,
+    /// Keys up to this one are skipped
+    pub start_after: Option<String>,
+    pub continuation_token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: SystemTime,
+    pub etag: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ListResult {
+    pub objects: Vec<ObjectInfo>,
+    pub common_prefixes: Vec<String>,
+    pub is_truncated: bool,
+    pub next_continuation_token: Option<String>,
+}
+
+/// Entry of a listing before it is paginated
+enum Listed {
+    Object(ObjectInfo),
+    Prefix(String),
+}
+
+impl Listed {
+    fn key(&self) -> &str {
+        match self {
+            Listed::Object(object) => &object.key,
+            Listed::Prefix(prefix) => prefix,
+        }
+    }
+}
+
+/// Lists the files of `storage` as objects keyed by their path. Only the directory holding the
+/// prefix is read, and with a `/` delimiter only its direct entries are. Entries are walked in
+/// key order from the continuation point, stopping once the page is full.
+pub fn list_objects(storage: &dyn Storage, query: &ListQuery) -> Result<ListResult, S3Error> {
+    let max_keys = query.max_keys.min(MAX_KEYS);
+    let after = match &query.continuation_token {
+        Some(token) => Some(decode_continuation_token(token)?),
+        None => query.start_after.clone(),
+    };
+
+    // Keys can only be under the directory part of the prefix
+    let dir_prefix = match query.prefix.rfind('/') {
+        Some(end) => &query.prefix[..=end],
+        None => "",
+    };
+    let Ok(dir) = normalize_path(dir_prefix) else {
+        return Ok(ListResult::default());
+    };
+
+    // One entry past the page tells whether the listing is truncated
+    let limit = max_keys + 1;
+    let after = after.as_deref();
+    let mut listed = Vec::new();
+    match query.delimiter.as_deref() {
+        Some("/") => list_dir(
+            storage,
+            &dir,
+            dir_prefix,
+            &query.prefix,
+            after,
+            limit,
+            &mut listed,
+        )?,
+        delimiter => {
+            let delimiter = delimiter.filter(|delimiter| !delimiter.is_empty());
+            walk_dir(
+                storage,
+                &dir,
+                dir_prefix,
+                &query.prefix,
+                after,
+                &mut |object| {
+                    let rest = &object.key[query.prefix.len()..];
+                    let entry = match delimiter {
+                        Some(delimiter) if rest.contains(delimiter) => {
+                            let end = rest.find(delimiter).unwrap_or_default() + delimiter.len();
+                            Listed::Prefix(format!("{}{}", query.prefix, &rest[..end]))
+                        }
+                        _ => Listed::Object(object),
+                    };
+
+                    // Keys sharing a common prefix come one after another
+                    let is_listed = after.is_some_and(|after| entry.key() <= after)
+                        || listed
+                            .last()
+                            .is_some_and(|last: &Listed| last.key() == entry.key());
+                    if !is_listed {
+                        listed.push(entry);
+                    }
+                    listed.len() < limit
+                },
+            )?;
+        }
+    }
+
+    let is_truncated = listed.len() > max_keys;
+    listed.truncate(max_keys);
+
+    let mut result = ListResult {
+        is_truncated,
+        next_continuation_token: is_truncated
+            .then(|| listed.last())
+            .flatten()
+            .map(|last| URL_SAFE_NO_PAD.encode(last.key())),
+        ..ListResult::default()
+    };
+    for entry in listed {
+        match entry {
+            Listed::Object(object) => result.objects.push(object),
+            Listed::Prefix(prefix) => result.common_prefixes.push(prefix),
+        }
+    }
+
+    Ok(result)
+}
+
+/// Lists the direct entries of `dir` after the key `after`, in key order, until `limit`
+/// entries are listed. Directories holding files are common prefixes.
+fn list_dir(
+    storage: &dyn Storage,
+    dir: &Path,
+    dir_prefix: &str,
+    prefix: &str,
+    after: Option<&str>,
+    limit: usize,
+    listed: &mut Vec<Listed>,
+) -> io::Result<()> {
+    for (key, path, metadata) in read_dir_entries(storage, dir, dir_prefix)? {
+        if listed.len() >= limit {
+            break;
+        }
+
+        let entry_key = match metadata.is_dir {
+            true => format!("{key}/"),
+            false => key,
+        };
+        if !entry_key.starts_with(prefix) || after.is_some_and(|after| entry_key.as_str() <= after)
+        {
+            continue;
+        }
+
+        if metadata.is_dir {
+            if contains_file(storage, &path, &entry_key) {
+                listed.push(Listed::Prefix(entry_key));
+            }
+        } else {
+            listed.push(Listed::Object(object_info(entry_key, &metadata)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Walks the files under `dir` with a key starting with `prefix` in key order, skipping the
+/// keys up to `after`, until `visit` returns `false`. Returns whether the walk went through.
+fn walk_dir(
+    storage: &dyn Storage,
+    dir: &Path,
+    dir_prefix: &str,
+    prefix: &str,
+    after: Option<&str>,
+    visit: &mut dyn FnMut(ObjectInfo) -> bool,
+) -> io::Result<bool> {
+    for (key, path, metadata) in read_dir_entries(storage, dir, dir_prefix)? {
+        if metadata.is_dir {
+            // Directories only hold matching keys when they start like the prefix, and keys
+            // after `after` when they start after it or like it
+            let dir_key = format!("{key}/");
+            let is_skipped = !(dir_key.starts_with(prefix) || prefix.starts_with(&dir_key))
+                || after
+                    .is_some_and(|after| dir_key.as_str() <= after && !after.starts_with(&dir_key));
+            if !is_skipped && !walk_dir(storage, &path, &dir_key, prefix, after, visit)? {
+                return Ok(false);
+            }
+        } else if key.starts_with(prefix)
+            && after.is_none_or(|after| key.as_str() > after)
+            && !visit(object_info(key, &metadata))
+        {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Entries of a directory with their keys in key order, skipping the data directory and names
+/// that can't be keys. Symbolic links to directories on the local disk are not followed.
+///
+/// The keys under a directory all start with its name and a `/`, so sorting directories by
+/// that puts every key of their tree in order with the keys around it.
+fn read_dir_entries(
+    storage: &dyn Storage,
+    dir: &Path,
+    dir_prefix: &str,
+) -> io::Result<Vec<(String, PathBuf, StorageMetadata)>> {
+    let entries = match storage.list(dir) {
+        Ok(entries) => entries,
+        Err(err)
+            if matches!(
+                err.kind(),
+                ErrorKind::NotFound | ErrorKind::NotADirectory | ErrorKind::InvalidInput
+            ) =>
+        {
+            return Ok(Vec::new())
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut entries: Vec<_> = entries
+        .into_iter()
+        .filter_map(|entry| {
+            if dir_prefix.is_empty() && entry.name == DATA_DIR_NAME {
+                return None;
+            }
+
+            let path = dir.join(&entry.name);
+            let is_link = || {
+                storage
+                    .local_path(&path)
+                    .is_some_and(|local_path| local_path.is_symlink())
+            };
+            if entry.metadata.is_dir && is_link() {
+                return None;
+            }
+
+            Some((format!("{dir_prefix}{}", entry.name), path, entry.metadata))
+        })
+        .collect();
+
+    entries.sort_by_cached_key(|(key, _, metadata)| match metadata.is_dir {
+        true => format!("{key}/"),
+        false => key.clone(),
+    });
+    Ok(entries)
+}
+
+/// Whether a directory holds a file, directories without any are not listed as prefixes
+fn contains_file(storage: &dyn Storage, dir: &Path, dir_prefix: &str) -> bool {
+    read_dir_entries(storage, dir, dir_prefix)
+        .unwrap_or_default()
+        .iter()
+        .any(|(key, path, metadata)| {
+            !metadata.is_dir || contains_file(storage, path, &format!("{key}/"))
+        })
+}
+
+fn object_info(key: String, metadata: &StorageMetadata) -> ObjectInfo {
+    ObjectInfo {
+        key,
+        size: metadata.len,
+        last_modified: metadata.modified.unwrap_or(UNIX_EPOCH),
+        etag: object_etag(metadata),
+    }
+}
+
+/// Entity tag of a file, the same as the one sent when it is downloaded
+pub fn object_etag(metadata: &StorageMetadata) -> String {
+    format!("\"{}\"", entity_tag(metadata))
+}
+
+fn decode_continuation_token(token: &str) -> Result<String, S3Error> {
+    URL_SAFE_NO_PAD
+        .decode(token)
+        .ok()
+        .and_then(|key| String::from_utf8(key).ok())
+        .ok_or_else(|| {
+            S3Error::new(
+                400,
+                "InvalidArgument",
+                "The continuation token is not valid",
+            )
+        })
+}
+
+/// `ListBucketResult` document of a ListObjectsV2 request. Keys and prefixes are encoded when
+/// the client asked for `encoding-type=url`.
+pub fn list_objects_xml(
+    bucket: &str,
+    query: &ListQuery,
+    result: &ListResult,
+    url_encoded: bool,
+) -> String {
+    let encode = |value: &str| match url_encoded {
+        true => utf8_percent_encode(value, KEY_ENCODE).to_string(),
+        false => escape_xml(value),
+    };
+
+    let mut xml = format!(
+        "<ListBucketResult xmlns=\"{S3_NAMESPACE}\">\
+         <Name>{}</Name><Prefix>{}</Prefix><KeyCount>{}</KeyCount><MaxKeys>{}</MaxKeys>\
+         <IsTruncated>{}</IsTruncated>",
+        escape_xml(bucket),
+        encode(&query.prefix),
+        result.objects.len() + result.common_prefixes.len(),
+        query.max_keys.min(MAX_KEYS),
+        result.is_truncated,
+    );
+    if let Some(delimiter) = &query.delimiter {
+        xml.push_str(&format!("<Delimiter>{}</Delimiter>", encode(delimiter)));
+    }
+    if url_encoded {
+        xml.push_str("<EncodingType>url</EncodingType>");
+    }
+    if let Some(start_after) = &query.start_after {
+        xml.push_str(&format!("<StartAfter>{}</StartAfter>", encode(start_after)));
+    }
+    if let Some(token) = &query.continuation_token {
+        xml.push_str(&format!(
+            "<ContinuationToken>{}</ContinuationToken>",
+            escape_xml(token)
+        ));
+    }
+    if let Some(token) = &result.next_continuation_token {
+        xml.push_str(&format!(
+            "<NextContinuationToken>{token}</NextContinuationToken>"
+        ));
+    }
+
+    for object in &result.objects {
+        xml.push_str(&format!(
+            "<Contents><Key>{}</Key><LastModified>{}</LastModified><ETag>{}</ETag>\
+             <Size>{}</Size><StorageClass>STANDARD</StorageClass></Contents>",
+            encode(&object.key),
+            iso8601(object.last_modified),
+            escape_xml(&object.etag),
+            object.size,
+        ));
+    }
+    for prefix in &result.common_prefixes {
+        xml.push_str(&format!(
+            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+            encode(prefix)
+        ));
+    }
+
+    xml.push_str("</ListBucketResult>");
+    xml
+}
+
+/// Writes the content of an object, keeping its size and MD5 for its entity tag
+pub struct ObjectWriter<W: Write> {
+    inner: W,
+    md5: Md5,
+    pub size: u64,
+}
+
+impl<W: Write> ObjectWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            md5: Md5::new(),
+            size: 0,
+        }
+    }
+
+    pub fn into_inner(self) -> (W, [u8; 16]) {
+        (self.inner, self.md5.finalize().into())
+    }
+}
+
+impl<W: Write> Write for ObjectWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.md5.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Multipart upload started with CreateMultipartUpload
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultipartUpload {
+    pub id: String,
+    pub key: String,
+    /// Seconds since the epoch
+    pub initiated: u64,
+}
+
+/// Keeps the parts of multipart uploads until they are completed into a file
+#[derive(Debug, Clone)]
+pub struct MultipartStore {
+    dir: PathBuf,
+    /// Unfinished uploads older than this are removed
+    expiry: Duration,
+}
+
+impl MultipartStore {
+    pub fn new(data_dir: &Path, expiry: Duration) -> Self {
+        Self {
+            dir: data_dir.join("multipart"),
+            expiry,
+        }
+    }
+
+    pub fn create(&self, key: &str) -> Result<MultipartUpload, S3Error> {
+        key_path(key)?;
+        self.expire()?;
+
+        let upload = MultipartUpload {
+            id: format!("{:032x}", rand::random::<u128>()),
+            key: key.to_owned(),
+            initiated: now().as_secs(),
+        };
+        let upload_dir = self.dir.join(&upload.id);
+        fs::create_dir_all(&upload_dir)?;
+        fs::write(
+            upload_dir.join("info.json"),
+            serde_json::to_vec(&upload).map_err(io::Error::other)?,
+        )?;
+
+        Ok(upload)
+    }
+
+    /// Looks up an upload of `key`
+    pub fn get(&self, id: &str, key: &str) -> Result<MultipartUpload, S3Error> {
+        let no_such_upload =
+            || S3Error::new(404, "NoSuchUpload", format!("Upload {id} does not exist"));
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(no_such_upload());
+        }
+
+        let info = fs::read(self.dir.join(id).join("info.json")).map_err(|_| no_such_upload())?;
+        let upload: MultipartUpload =
+            serde_json::from_slice(&info).map_err(|_| no_such_upload())?;
+        match upload.key == key {
+            true => Ok(upload),
+            false => Err(no_such_upload()),
+        }
+    }
+
+    /// Creates the file a part is written to before [`MultipartStore::save_part`] stores it
+    pub fn create_part_file(
+        &self,
+        upload: &MultipartUpload,
+        part_number: u32,
+    ) -> Result<(PathBuf, fs::File), S3Error> {
+        check_part_number(part_number)?;
+        let path = self
+            .dir
+            .join(&upload.id)
+            .join(format!("{part_number}.{:016x}.tmp", rand::random::<u64>()));
+        let file = fs::File::create(&path)?;
+        Ok((path, file))
+    }
+
+    /// Stores a received part, replacing any previous upload of the same part
+    pub fn save_part(
+        &self,
+        upload: &MultipartUpload,
+        part_number: u32,
+        part_file: &Path,
+        md5: &[u8; 16],
+    ) -> Result<(), S3Error> {
+        let upload_dir = self.dir.join(&upload.id);
+        fs::write(upload_dir.join(format!("{part_number}.md5")), md5)?;
+        fs::rename(part_file, upload_dir.join(part_number.to_string()))?;
+        Ok(())
+    }
+
+    /// Concatenates the listed parts into `output`, returning the entity tag of the object
+    pub fn assemble(
+        &self,
+        upload: &MultipartUpload,
+        parts: &[(u32, String)],
+        output: &mut impl Write,
+    ) -> Result<String, S3Error> {
+        if parts.is_empty() {
+            return Err(S3Error::new(
+                400,
+                "MalformedXML",
+                "At least one part is required",
+            ));
+        }
+        if parts.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(S3Error::new(
+                400,
+                "InvalidPartOrder",
+                "Parts must be listed in ascending order",
+            ));
+        }
+
+        let upload_dir = self.dir.join(&upload.id);
+        let mut md5s = Md5::new();
+        for (part_number, etag) in parts {
+            let invalid_part = || {
+                S3Error::new(
+                    400,
+                    "InvalidPart",
+                    format!("Part {part_number} was not uploaded or its ETag does not match"),
+                )
+            };
+            let md5 = fs::read(upload_dir.join(format!("{part_number}.md5")))
+                .map_err(|_| invalid_part())?;
+            if etag.trim_matches('"') != hex(&md5) {
+                return Err(invalid_part());
+            }
+
+            let mut part = fs::File::open(upload_dir.join(part_number.to_string()))?;
+            io::copy(&mut part, output)?;
+            md5s.update(&md5);
+        }
+
+        // The entity tag of multipart objects is the MD5 of the MD5s of their parts
+        Ok(format!("\"{}-{}\"", hex(&md5s.finalize()), parts.len()))
+    }
+
+    /// Removes an upload and its parts
+    pub fn remove(&self, upload: &MultipartUpload) -> Result<(), S3Error> {
+        fs::remove_dir_all(self.dir.join(&upload.id))?;
+        Ok(())
+    }
+
+    /// Removes the uploads started before the expiry
+    pub fn expire(&self) -> Result<(), S3Error> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        let oldest_kept = now().saturating_sub(self.expiry).as_secs();
+
+        for entry in entries.flatten() {
+            let info = fs::read(entry.path().join("info.json")).ok();
+            let upload =
+                info.and_then(|info| serde_json::from_slice::<MultipartUpload>(&info).ok());
+            if upload.is_none_or(|upload| upload.initiated < oldest_kept) {
+                fs::remove_dir_all(entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_part_number(part_number: u32) -> Result<(), S3Error> {
+    match (1..=MAX_PART_NUMBER).contains(&part_number) {
+        true => Ok(()),
+        false => Err(S3Error::new(
+            400,
+            "InvalidArgument",
+            format!("Part numbers must be between 1 and {MAX_PART_NUMBER}"),
+        )),
+    }
+}
+
+/// Parses the body of a CompleteMultipartUpload request into part numbers and entity tags
+pub fn parse_complete_multipart(body: &str) -> Result<Vec<(u32, String)>, S3Error> {
+    let malformed = || S3Error::new(400, "MalformedXML", "The XML is not well-formed");
+    let document = roxmltree::Document::parse(body).map_err(|_| malformed())?;
+
+    document
+        .root_element()
+        .children()
+        .filter(|part| part.tag_name().name() == "Part")
+        .map(|part| {
+            let part_number = child_text(&part, "PartNumber")
+                .and_then(|number| number.trim().parse::<u32>().ok())
+                .ok_or_else(malformed)?;
+            let etag = child_text(&part, "ETag").ok_or_else(malformed)?;
+            Ok((part_number, etag.trim().to_owned()))
+        })
+        .collect()
+}
+
+/// Parses the body of a DeleteObjects request into its keys and whether it is quiet
+pub fn parse_delete_objects(body: &str) -> Result<(Vec<String>, bool), S3Error> {
+    let malformed = || S3Error::new(400, "MalformedXML", "The XML is not well-formed");
+    let document = roxmltree::Document::parse(body).map_err(|_| malformed())?;
+    let root = document.root_element();
+
+    let quiet = child_text(&root, "Quiet").is_some_and(|quiet| quiet.trim() == "true");
+    let keys = root
+        .children()
+        .filter(|object| object.tag_name().name() == "Object")
+        .map(|object| {
+            child_text(&object, "Key")
+                .map(str::to_owned)
+                .ok_or_else(malformed)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match keys.len() {
+        1..=MAX_KEYS => Ok((keys, quiet)),
+        _ => Err(malformed()),
+    }
+}
+
+/// Text of the first child element named `name`, whatever its namespace
+fn child_text<'a>(node: &roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .map(|child| child.text().unwrap_or_default())
+}
+
+/// Formats a time like `2023-11-04T12:34:56.000Z`
+pub fn iso8601(time: SystemTime) -> String {
+    let timestamp = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, seconds) = (timestamp / 86400, timestamp % 86400);
+
+    // Converts days since the epoch to a civil date, see
+    // https://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year}-{month:02}-{day:02}T{:02}:{:02}:{:02}.000Z",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{tests::write_str, MemoryStorage};
+
+    fn bucket() -> MemoryStorage {
+        let storage = MemoryStorage::default();
+        for dir in ["a", "a/b", "empty", DATA_DIR_NAME] {
+            storage.create_dir(Path::new(dir)).unwrap();
+        }
+        for file in ["a.txt", "a/1.txt", "a/b/2.txt", "a/c.txt", "b.txt"] {
+            write_str(&storage, file, file);
+        }
+        write_str(&storage, &format!("{DATA_DIR_NAME}/secret"), "");
+        storage
+    }
+
+    fn keys(result: &ListResult) -> Vec<&str> {
+        result
+            .objects
+            .iter()
+            .map(|object| object.key.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn lists_every_key_in_order() {
+        let query = ListQuery {
+            max_keys: MAX_KEYS,
+            ..ListQuery::default()
+        };
+        let result = list_objects(&bucket(), &query).unwrap();
+
+        assert_eq!(
+            keys(&result),
+            ["a.txt", "a/1.txt", "a/b/2.txt", "a/c.txt", "b.txt"]
+        );
+        assert!(!result.is_truncated);
+    }
+
+    #[test]
+    fn pages_through_keys_with_continuation_tokens() {
+        let storage = bucket();
+        let mut query = ListQuery {
+            max_keys: 2,
+            ..ListQuery::default()
+        };
+
+        let mut listed = Vec::new();
+        loop {
+            let result = list_objects(&storage, &query).unwrap();
+            listed.extend(keys(&result).into_iter().map(str::to_owned));
+            if !result.is_truncated {
+                break;
+            }
+            query.continuation_token = result.next_continuation_token;
+        }
+
+        assert_eq!(
+            listed,
+            ["a.txt", "a/1.txt", "a/b/2.txt", "a/c.txt", "b.txt"]
+        );
+    }
+
+    #[test]
+    fn groups_keys_by_delimiter() {
+        let query = ListQuery {
+            prefix: "a/".to_owned(),
+            delimiter: Some("/".to_owned()),
+            max_keys: MAX_KEYS,
+            ..ListQuery::default()
+        };
+        let result = list_objects(&bucket(), &query).unwrap();
+
+        assert_eq!(keys(&result), ["a/1.txt", "a/c.txt"]);
+        assert_eq!(result.common_prefixes, ["a/b/"]);
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{constant_time_eq, storage::Storage, versions::normalize_file_path};
+
+/// How long links are valid for when no expiry is requested
+pub const DEFAULT_SHARE_EXPIRY: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Longest expiry of a link
+pub const MAX_SHARE_EXPIRY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+const SECRET_FILE_NAME: &str = "secret";
+const PASSWORD_ROUNDS: u32 = 100_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareMode {
+    /// Files can be downloaded, nothing can be changed
+    #[default]
+    ReadOnly,
+    /// Files can be added to a directory, which is not listed
+    UploadOnly,
+}
+
+impl fmt::Display for ShareMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "read_only"),
+            Self::UploadOnly => write!(f, "upload_only"),
+        }
+    }
+}
+
+/// File or directory shared through a signed link
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Share {
+    pub id: String,
+    /// Path relative to the root of the storage
+    pub path: String,
+    pub is_dir: bool,
+    pub mode: ShareMode,
+    pub created_by: String,
+    /// Seconds since the epoch
+    pub created_at: u64,
+    /// Seconds since the epoch
+    pub expires_at: u64,
+    pub has_password: bool,
+    pub max_downloads: Option<u64>,
+    pub downloads: u64,
+}
+
+impl Share {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= now().as_secs()
+    }
+
+    pub fn remaining_downloads(&self) -> Option<u64> {
+        self.max_downloads
+            .map(|max_downloads| max_downloads.saturating_sub(self.downloads))
+    }
+}
+
+/// Share as stored, with the hash of its password
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredShare {
+    #[serde(flatten)]
+    share: Share,
+    /// Hex encoded salt and PBKDF2 hash, separated by `$`
+    password_hash: Option<String>,
+}
+
+/// Parameters of a new share
+#[derive(Debug, Clone, Default)]
+pub struct NewShare {
+    pub path: String,
+    pub mode: ShareMode,
+    pub expires_in: Option<Duration>,
+    pub password: Option<String>,
+    pub max_downloads: Option<u64>,
+    pub created_by: String,
+}
+
+#[derive(Debug)]
+pub enum ShareError {
+    /// The share doesn't exist, was revoked or the signature of its link doesn't match
+    NotFound,
+    Expired,
+    /// Every allowed download was used
+    LimitReached,
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for ShareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "This link does not exist or has been revoked"),
+            Self::Expired => write!(f, "This link has expired"),
+            Self::LimitReached => write!(f, "This link has reached its download limit"),
+            Self::Invalid(message) => write!(f, "{message}"),
+            Self::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<io::Error> for ShareError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => Self::NotFound,
+            ErrorKind::InvalidInput | ErrorKind::PermissionDenied => Self::Invalid(err.to_string()),
+            _ => Self::Io(err),
+        }
+    }
+}
+
+/// Shares of a storage and the key signing their links.
+///
+/// The key is generated on first use and kept in the data directory, so links stay valid
+/// across restarts. Deleting it invalidates every link.
+#[derive(Debug)]
+pub struct ShareStore {
+    dir: PathBuf,
+    secret: Vec<u8>,
+    /// Serializes the updates of download counts
+    downloads: Mutex<()>,
+}
+
+impl ShareStore {
+    pub fn open(data_dir: &Path) -> io::Result<Self> {
+        let dir = data_dir.join("shares");
+        fs::create_dir_all(&dir)?;
+
+        let secret_path = dir.join(SECRET_FILE_NAME);
+        let secret = match fs::read(&secret_path) {
+            Ok(secret) if !secret.is_empty() => secret,
+            Ok(_) => return Err(io::Error::new(ErrorKind::InvalidData, "Empty share key")),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                let secret = rand::random::<[u8; 32]>().to_vec();
+                write_private(&secret_path, &secret)?;
+                secret
+            }
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            dir,
+            secret,
+            downloads: Mutex::new(()),
+        })
+    }
+
+    /// Shares the file or directory at `new_share.path` in `storage`
+    pub fn create(&self, storage: &dyn Storage, new_share: NewShare) -> Result<Share, ShareError> {
+        let path = normalize_file_path(&new_share.path)?;
+        let metadata = storage
+            .stat(&path)
+            .map_err(|_| ShareError::Invalid(format!("{} does not exist", new_share.path)))?;
+        if new_share.mode == ShareMode::UploadOnly && !metadata.is_dir {
+            return Err(ShareError::Invalid(
+                "Only directories can be shared for uploads".to_owned(),
+            ));
+        }
+
+        let expires_in = new_share.expires_in.unwrap_or(DEFAULT_SHARE_EXPIRY);
+        if expires_in.is_zero() || expires_in > MAX_SHARE_EXPIRY {
+            return Err(ShareError::Invalid(format!(
+                "Links must expire within {} days",
+                MAX_SHARE_EXPIRY.as_secs() / 86400
+            )));
+        }
+        let password_hash = new_share
+            .password
+            .filter(|password| !password.is_empty())
+            .map(|password| hash_password(&password));
+
+        let stored = StoredShare {
+            share: Share {
+                id: format!("{:016x}", rand::random::<u64>()),
+                path: path.to_string_lossy().into_owned(),
+                is_dir: metadata.is_dir,
+                mode: new_share.mode,
+                created_by: new_share.created_by,
+                created_at: now().as_secs(),
+                expires_at: (now() + expires_in).as_secs(),
+                has_password: password_hash.is_some(),
+                max_downloads: new_share.max_downloads,
+                downloads: 0,
+            },
+            password_hash,
+        };
+
+        self.expire()?;
+        self.write(&stored)?;
+        Ok(stored.share)
+    }
+
+    /// Token of the link to a share, its id followed by the signature of what it grants
+    pub fn token(&self, share: &Share) -> String {
+        let signature = self.sign(&format!(
+            "{}:{}:{}:{}",
+            share.id, share.path, share.mode, share.expires_at
+        ));
+        format!("{}.{}", share.id, URL_SAFE_NO_PAD.encode(signature))
+    }
+
+    /// Looks up the share of a link, checking its signature and expiry
+    pub fn verify(&self, token: &str) -> Result<Share, ShareError> {
+        let (id, _) = token.split_once('.').ok_or(ShareError::NotFound)?;
+        let share = self.read(id)?.share;
+
+        let expected = self.token(&share);
+        if !constant_time_eq(expected.as_bytes(), token.as_bytes()) {
+            return Err(ShareError::NotFound);
+        }
+        if share.is_expired() {
+            return Err(ShareError::Expired);
+        }
+
+        Ok(share)
+    }
+
+    pub fn check_password(&self, share: &Share, password: &str) -> bool {
+        let Ok(stored) = self.read(&share.id) else {
+            return false;
+        };
+
+        match stored.password_hash {
+            Some(password_hash) => verify_password(&password_hash, password),
+            None => true,
+        }
+    }
+
+    /// Value of the cookie proving the password of a share was entered. It changes with the
+    /// password hash, so it can't be derived from the share alone.
+    pub fn unlock_token(&self, share: &Share) -> Option<String> {
+        let password_hash = self.read(&share.id).ok()?.password_hash?;
+        Some(URL_SAFE_NO_PAD.encode(self.sign(&format!("unlock:{}:{password_hash}", share.id))))
+    }
+
+    /// Whether a share can be used, without a password or with the cookie of its unlock token
+    pub fn is_unlocked(&self, share: &Share, cookie: Option<&str>) -> bool {
+        if !share.has_password {
+            return true;
+        }
+
+        match (self.unlock_token(share), cookie) {
+            (Some(expected), Some(cookie)) => {
+                constant_time_eq(expected.as_bytes(), cookie.as_bytes())
+            }
+            _ => false,
+        }
+    }
+
+    /// Counts a download, failing once the share reached its limit
+    pub fn record_download(&self, share: &Share) -> Result<Share, ShareError> {
+        let _guard = self.downloads.lock().unwrap();
+        let mut stored = self.read(&share.id)?;
+
+        if stored
+            .share
+            .remaining_downloads()
+            .is_some_and(|remaining| remaining == 0)
+        {
+            return Err(ShareError::LimitReached);
+        }
+
+        stored.share.downloads += 1;
+        self.write(&stored)?;
+        Ok(stored.share)
+    }
+
+    /// Lists the shares that have not expired, newest first
+    pub fn list(&self) -> io::Result<Vec<Share>> {
+        self.expire()?;
+
+        let mut shares: Vec<Share> = self
+            .read_all()?
+            .into_iter()
+            .map(|stored| stored.share)
+            .collect();
+        shares.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(b.id.cmp(&a.id)));
+        Ok(shares)
+    }
+
+    /// Removes a share, its link stops working immediately
+    pub fn revoke(&self, id: &str) -> Result<Share, ShareError> {
+        let stored = self.read(id)?;
+        fs::remove_file(self.share_path(id)?)?;
+        Ok(stored.share)
+    }
+
+    /// Removes the expired shares
+    pub fn expire(&self) -> io::Result<()> {
+        for stored in self.read_all()? {
+            if stored.share.is_expired() {
+                fs::remove_file(self.share_path(&stored.share.id)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_all(&self) -> io::Result<Vec<StoredShare>> {
+        Ok(fs::read_dir(&self.dir)?
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                self.read(name.strip_suffix(".json")?).ok()
+            })
+            .collect())
+    }
+
+    fn read(&self, id: &str) -> Result<StoredShare, ShareError> {
+        let content = fs::read(self.share_path(id).map_err(|_| ShareError::NotFound)?)
+            .map_err(|_| ShareError::NotFound)?;
+        serde_json::from_slice(&content)
+            .map_err(|err| ShareError::Io(io::Error::new(ErrorKind::InvalidData, err)))
+    }
+
+    fn write(&self, stored: &StoredShare) -> io::Result<()> {
+        let path = self.share_path(&stored.share.id)?;
+        let staging_path = path.with_extension("json.tmp");
+
+        write_private(
+            &staging_path,
+            &serde_json::to_vec(stored).map_err(io::Error::other)?,
+        )?;
+        fs::rename(staging_path, path)
+    }
+
+    fn share_path(&self, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{id} is not a valid share"),
+            ));
+        }
+
+        Ok(self.dir.join(format!("{id}.json")))
+    }
+
+    fn sign(&self, message: &str) -> Vec<u8> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(message.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+fn hash_password(password: &str) -> String {
+    let salt = rand::random::<[u8; 16]>();
+    let mut hash = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, PASSWORD_ROUNDS, &mut hash);
+
+    format!(
+        "{}${}",
+        URL_SAFE_NO_PAD.encode(salt),
+        URL_SAFE_NO_PAD.encode(hash)
+    )
+}
+
+fn verify_password(password_hash: &str, password: &str) -> bool {
+    let Some((salt, expected)) = password_hash.split_once('$') else {
+        return false;
+    };
+    let (Ok(salt), Ok(expected)) = (
+        URL_SAFE_NO_PAD.decode(salt),
+        URL_SAFE_NO_PAD.decode(expected),
+    ) else {
+        return false;
+    };
+
+    let mut hash = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), &salt, PASSWORD_ROUNDS, &mut hash);
+    constant_time_eq(&hash, &expected)
+}
+
+/// Writes a file only the user running the server can read
+fn write_private(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt as _;
+        options.mode(0o600);
+    }
+
+    io::Write::write_all(&mut options.open(path)?, content)
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
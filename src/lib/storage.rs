@@ -0,0 +1,817 @@
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::detect::SNIFF_SIZE;
+
+/// Where the served files are kept.
+///
+/// Paths are relative to the root of the storage and normalized with
+/// [`normalize_path`](crate::normalize_path), the empty path being the root itself.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// Entries of the directory at `path`, in no particular order
+    fn list(&self, path: &Path) -> io::Result<Vec<StorageEntry>>;
+
+    fn stat(&self, path: &Path) -> io::Result<StorageMetadata>;
+
+    /// Reads the file at `path`, only the bytes in `range` when given. A range past the end of
+    /// the file is cut short.
+    fn open(&self, path: &Path, range: Option<Range<u64>>) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Creates or replaces the file at `path` with everything read from `content`, returning
+    /// the number of bytes written. The parent directory must exist.
+    fn write(&self, path: &Path, content: &mut dyn Read) -> io::Result<u64>;
+
+    fn create_dir(&self, path: &Path) -> io::Result<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Removes a file, or a directory with everything in it
+    fn remove(&self, path: &Path) -> io::Result<()>;
+
+    /// Path of `path` on the local disk, for features handing files to libraries that read
+    /// them by path (archives, images, line indexes...). `None` when the storage doesn't keep
+    /// its files on the local disk.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    /// Whether every write fails, so requests can be rejected before their body is received
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Moves the file or directory `source` of the local disk to `path`, e.g. an upload
+    /// staged in the data directory or an entry of the trash. The file at `path` is replaced
+    /// when `replace` is set, otherwise an existing entry fails with `AlreadyExists`.
+    /// Directories never replace anything.
+    fn move_in(&self, source: &Path, path: &Path, replace: bool) -> io::Result<()> {
+        copy_in(self, source, path, replace)?;
+        remove_local(source)
+    }
+
+    /// Moves the entry at `path` out of the storage to `destination` on the local disk, which
+    /// must not exist yet
+    fn move_out(&self, path: &Path, destination: &Path) -> io::Result<()> {
+        copy_out(self, path, destination)?;
+        self.remove(path).inspect_err(|_| {
+            let _ = remove_local(destination);
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StorageMetadata {
+    pub is_dir: bool,
+    /// Size in bytes, 0 for directories
+    pub len: u64,
+    pub modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageEntry {
+    pub name: String,
+    pub metadata: StorageMetadata,
+    /// Overlay layer the entry comes from, `None` outside of overlays
+    pub layer: Option<String>,
+}
+
+/// Entity tag of a file without its quotes, changing whenever its size or modification time
+/// does
+pub fn entity_tag(metadata: &StorageMetadata) -> String {
+    let modified = metadata
+        .modified
+        .unwrap_or(UNIX_EPOCH)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    format!(
+        "{:x}:{:x}:{:x}",
+        metadata.len,
+        modified.as_secs(),
+        modified.subsec_nanos()
+    )
+}
+
+/// Reads the whole file at `path`, failing with `InvalidInput` when it is larger than
+/// `max_size`
+pub fn read_to_end(storage: &dyn Storage, path: &Path, max_size: u64) -> io::Result<Vec<u8>> {
+    let metadata = storage.stat(path)?;
+    if metadata.is_dir {
+        return Err(is_a_directory(path));
+    }
+    if metadata.len > max_size {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is larger than {max_size} bytes", path.display()),
+        ));
+    }
+
+    let mut content = Vec::with_capacity(metadata.len as usize);
+    storage.open(path, None)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Reads the first bytes of the file at `path`, enough to detect its type and encoding
+pub fn read_header(storage: &dyn Storage, path: &Path) -> io::Result<Vec<u8>> {
+    let mut header = Vec::with_capacity(SNIFF_SIZE);
+    storage
+        .open(path, Some(0..SNIFF_SIZE as u64))?
+        .read_to_end(&mut header)?;
+    Ok(header)
+}
+
+/// Creates the directory at `path` along with its missing parents
+pub fn create_dir_all(storage: &dyn Storage, path: &Path) -> io::Result<()> {
+    match storage.stat(path) {
+        Ok(metadata) if metadata.is_dir => return Ok(()),
+        Ok(_) => {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} is not a directory", path.display()),
+            ))
+        }
+        Err(err) if err.kind() == ErrorKind::NotFound => {}
+        Err(err) => return Err(err),
+    }
+
+    if let Some(parent) = path.parent() {
+        create_dir_all(storage, parent)?;
+    }
+    match storage.create_dir(path) {
+        // Created by another request in the meantime
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => Ok(()),
+        result => result,
+    }
+}
+
+/// Copies the file or directory `source` of the local disk to `path`, which [`Storage::move_in`]
+/// does for storages that aren't on the local disk. Without `replace`, an existing entry is
+/// only detected before copying.
+pub(crate) fn copy_in<S: Storage + ?Sized>(
+    storage: &S,
+    source: &Path,
+    path: &Path,
+    replace: bool,
+) -> io::Result<()> {
+    if storage.is_read_only() {
+        return Err(read_only(path));
+    }
+    if !replace && storage.stat(path).is_ok() {
+        return Err(io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+
+    if !fs::symlink_metadata(source)?.is_dir() {
+        storage.write(path, &mut fs::File::open(source)?)?;
+        return Ok(());
+    }
+
+    storage.create_dir(path)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        copy_in(
+            storage,
+            &entry.path(),
+            &path.join(entry.file_name()),
+            replace,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Copies the entry at `path` to `destination` on the local disk, which [`Storage::move_out`]
+/// does for storages that aren't on the local disk
+pub(crate) fn copy_out<S: Storage + ?Sized>(
+    storage: &S,
+    path: &Path,
+    destination: &Path,
+) -> io::Result<()> {
+    if storage.is_read_only() {
+        return Err(read_only(path));
+    }
+
+    let copied = if storage.stat(path)?.is_dir {
+        fs::create_dir(destination).and_then(|()| {
+            storage.list(path)?.into_iter().try_for_each(|entry| {
+                copy_out(
+                    storage,
+                    &path.join(&entry.name),
+                    &destination.join(&entry.name),
+                )
+            })
+        })
+    } else {
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(destination)
+            .and_then(|mut file| io::copy(&mut storage.open(path, None)?, &mut file))
+            .map(|_| ())
+    };
+
+    copied.inspect_err(|err| {
+        if err.kind() != ErrorKind::AlreadyExists {
+            let _ = remove_local(destination);
+        }
+    })
+}
+
+pub(crate) fn remove_local(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+fn read_only(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::PermissionDenied,
+        format!("{} is read-only", path.display()),
+    )
+}
+
+fn is_a_directory(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("{} is a directory", path.display()),
+    )
+}
+
+/// Files of a directory on the local disk
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_owned(),
+        }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn list(&self, path: &Path) -> io::Result<Vec<StorageEntry>> {
+        Ok(fs::read_dir(self.root.join(path))?
+            .flatten()
+            .filter_map(|entry| {
+                Some(StorageEntry {
+                    name: entry.file_name().into_string().ok()?,
+                    // Follows symbolic links like the rest of the server
+                    metadata: fs::metadata(entry.path())
+                        .map(StorageMetadata::from)
+                        .unwrap_or(StorageMetadata {
+                            is_dir: false,
+                            len: 0,
+                            modified: None,
+                        }),
+                    layer: None,
+                })
+            })
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<StorageMetadata> {
+        fs::metadata(self.root.join(path)).map(StorageMetadata::from)
+    }
+
+    fn open(&self, path: &Path, range: Option<Range<u64>>) -> io::Result<Box<dyn Read + Send>> {
+        let mut file = fs::File::open(self.root.join(path))?;
+        if file.metadata()?.is_dir() {
+            return Err(is_a_directory(path));
+        }
+
+        match range {
+            Some(range) => {
+                file.seek(SeekFrom::Start(range.start))?;
+                Ok(Box::new(file.take(range.end.saturating_sub(range.start))))
+            }
+            None => Ok(Box::new(file)),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &mut dyn Read) -> io::Result<u64> {
+        let mut file = fs::File::create(self.root.join(path))?;
+        let written = io::copy(content, &mut file)?;
+        file.sync_all()?;
+        Ok(written)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        fs::create_dir(self.root.join(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs::rename(self.root.join(from), self.root.join(to))
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        remove_local(&self.root.join(path))
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        Some(self.root.join(path))
+    }
+
+    fn move_in(&self, source: &Path, path: &Path, replace: bool) -> io::Result<()> {
+        let target = self.root.join(path);
+        let moved = match replace && !fs::symlink_metadata(source)?.is_dir() {
+            true => fs::rename(source, &target),
+            false => move_without_replacing(source, &target),
+        };
+
+        match moved {
+            Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+                copy_in(self, source, path, replace)?;
+                remove_local(source)
+            }
+            result => result,
+        }
+    }
+
+    fn move_out(&self, path: &Path, destination: &Path) -> io::Result<()> {
+        match fs::rename(self.root.join(path), destination) {
+            Err(err) if err.kind() == ErrorKind::CrossesDevices => {
+                copy_out(self, path, destination)?;
+                self.remove(path).inspect_err(|_| {
+                    let _ = remove_local(destination);
+                })
+            }
+            result => result,
+        }
+    }
+}
+
+/// Renames `from` to `to`, failing with `AlreadyExists` instead of replacing an entry created
+/// at `to` at the same time.
+///
+/// Files are hard linked then unlinked, since creating a link never replaces anything, or
+/// copied to a file created with `create_new` where links aren't possible. Directories are
+/// renamed onto an empty directory created first, which fails unless that directory is still
+/// empty.
+fn move_without_replacing(from: &Path, to: &Path) -> io::Result<()> {
+    if !fs::symlink_metadata(from)?.is_dir() {
+        match fs::hard_link(from, to) {
+            Ok(()) => return fs::remove_file(from),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => return Err(err),
+            // Hard links can't cross file systems
+            Err(_) => {}
+        }
+
+        let mut target = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(to)?;
+        if let Err(err) = io::copy(&mut fs::File::open(from)?, &mut target) {
+            let _ = fs::remove_file(to);
+            return Err(err);
+        }
+        return fs::remove_file(from);
+    }
+
+    fs::create_dir(to)?;
+    fs::rename(from, to).map_err(|err| {
+        // Only removed while still empty, i.e. while it's the directory created above
+        let _ = fs::remove_dir(to);
+        match err.kind() {
+            ErrorKind::DirectoryNotEmpty => io::Error::new(ErrorKind::AlreadyExists, err),
+            _ => err,
+        }
+    })
+}
+
+impl From<fs::Metadata> for StorageMetadata {
+    fn from(metadata: fs::Metadata) -> Self {
+        Self {
+            is_dir: metadata.is_dir(),
+            len: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MemoryNode {
+    Directory {
+        modified: SystemTime,
+    },
+    File {
+        content: Arc<[u8]>,
+        modified: SystemTime,
+    },
+}
+
+impl MemoryNode {
+    fn metadata(&self) -> StorageMetadata {
+        match self {
+            Self::Directory { modified } => StorageMetadata {
+                is_dir: true,
+                len: 0,
+                modified: Some(*modified),
+            },
+            Self::File { content, modified } => StorageMetadata {
+                is_dir: false,
+                len: content.len() as u64,
+                modified: Some(*modified),
+            },
+        }
+    }
+}
+
+/// Files kept in memory, mostly useful to exercise the handlers without a disk
+#[derive(Debug)]
+pub struct MemoryStorage {
+    /// Every file and directory by path, the root being the empty path
+    nodes: RwLock<BTreeMap<PathBuf, MemoryNode>>,
+}
+
+impl Default for MemoryStorage {
+    fn default() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            PathBuf::new(),
+            MemoryNode::Directory {
+                modified: SystemTime::now(),
+            },
+        );
+
+        Self {
+            nodes: RwLock::new(nodes),
+        }
+    }
+}
+
+impl MemoryStorage {
+    /// Checks that the parent of `path` is a directory a new entry can be added to
+    fn check_parent(nodes: &BTreeMap<PathBuf, MemoryNode>, path: &Path) -> io::Result<()> {
+        let parent = path
+            .parent()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "The root can't be replaced"))?;
+
+        match nodes.get(parent) {
+            Some(MemoryNode::Directory { .. }) => Ok(()),
+            Some(MemoryNode::File { .. }) => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a directory", parent.display()),
+            )),
+            None => Err(not_found(parent)),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        ErrorKind::NotFound,
+        format!("{} does not exist", path.display()),
+    )
+}
+
+impl Storage for MemoryStorage {
+    fn list(&self, path: &Path) -> io::Result<Vec<StorageEntry>> {
+        let nodes = self.nodes.read().unwrap();
+        match nodes.get(path) {
+            Some(MemoryNode::Directory { .. }) => {}
+            Some(MemoryNode::File { .. }) => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{} is not a directory", path.display()),
+                ))
+            }
+            None => return Err(not_found(path)),
+        }
+
+        Ok(nodes
+            .iter()
+            .filter(|(entry_path, _)| {
+                !entry_path.as_os_str().is_empty() && entry_path.parent() == Some(path)
+            })
+            .filter_map(|(entry_path, node)| {
+                Some(StorageEntry {
+                    name: entry_path.file_name()?.to_str()?.to_owned(),
+                    metadata: node.metadata(),
+                    layer: None,
+                })
+            })
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> io::Result<StorageMetadata> {
+        let nodes = self.nodes.read().unwrap();
+        nodes
+            .get(path)
+            .map(MemoryNode::metadata)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn open(&self, path: &Path, range: Option<Range<u64>>) -> io::Result<Box<dyn Read + Send>> {
+        let nodes = self.nodes.read().unwrap();
+        let content = match nodes.get(path) {
+            Some(MemoryNode::File { content, .. }) => content.clone(),
+            Some(MemoryNode::Directory { .. }) => return Err(is_a_directory(path)),
+            None => return Err(not_found(path)),
+        };
+
+        let mut reader = Cursor::new(content);
+        match range {
+            Some(range) => {
+                reader.set_position(range.start);
+                Ok(Box::new(reader.take(range.end.saturating_sub(range.start))))
+            }
+            None => Ok(Box::new(reader)),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &mut dyn Read) -> io::Result<u64> {
+        // Read before locking so slow readers don't block the other requests
+        let mut buffer = Vec::new();
+        content.read_to_end(&mut buffer)?;
+
+        let mut nodes = self.nodes.write().unwrap();
+        Self::check_parent(&nodes, path)?;
+        if let Some(MemoryNode::Directory { .. }) = nodes.get(path) {
+            return Err(is_a_directory(path));
+        }
+
+        let written = buffer.len() as u64;
+        nodes.insert(
+            path.to_owned(),
+            MemoryNode::File {
+                content: buffer.into(),
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(written)
+    }
+
+    fn create_dir(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.write().unwrap();
+        Self::check_parent(&nodes, path)?;
+        if nodes.contains_key(path) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+
+        nodes.insert(
+            path.to_owned(),
+            MemoryNode::Directory {
+                modified: SystemTime::now(),
+            },
+        );
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.write().unwrap();
+        if !nodes.contains_key(from) || from.as_os_str().is_empty() {
+            return Err(not_found(from));
+        }
+        Self::check_parent(&nodes, to)?;
+        if to.starts_with(from) && to != from {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} can't be moved into itself", from.display()),
+            ));
+        }
+        if let Some(MemoryNode::Directory { .. }) = nodes.get(to) {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+
+        let moved: Vec<PathBuf> = nodes
+            .keys()
+            .filter(|path| path.starts_with(from))
+            .cloned()
+            .collect();
+        for path in moved {
+            let node = nodes.remove(&path).unwrap();
+            let suffix = path.strip_prefix(from).unwrap();
+            let new_path = if suffix.as_os_str().is_empty() {
+                to.to_owned()
+            } else {
+                to.join(suffix)
+            };
+            nodes.insert(new_path, node);
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        let mut nodes = self.nodes.write().unwrap();
+        if path.as_os_str().is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "The root can't be removed",
+            ));
+        }
+        if !nodes.contains_key(path) {
+            return Err(not_found(path));
+        }
+
+        nodes.retain(|node_path, _| !node_path.starts_with(path));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Empty directory of its own under the system's temporary directory
+    pub(crate) fn temp_dir() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("file-server-test-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    pub(crate) fn write_str(storage: &dyn Storage, path: &str, content: &str) {
+        storage
+            .write(Path::new(path), &mut content.as_bytes())
+            .unwrap();
+    }
+
+    pub(crate) fn read_str(storage: &dyn Storage, path: &str) -> String {
+        let mut content = String::new();
+        storage
+            .open(Path::new(path), None)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    fn names(storage: &dyn Storage, path: &str) -> Vec<String> {
+        let mut names: Vec<String> = storage
+            .list(Path::new(path))
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn memory_storage_writes_and_reads_files() {
+        let storage = MemoryStorage::default();
+        write_str(&storage, "notes.txt", "hello world");
+
+        let metadata = storage.stat(Path::new("notes.txt")).unwrap();
+        assert!(!metadata.is_dir);
+        assert_eq!(metadata.len, 11);
+        assert_eq!(read_str(&storage, "notes.txt"), "hello world");
+
+        let mut range = String::new();
+        storage
+            .open(Path::new("notes.txt"), Some(6..100))
+            .unwrap()
+            .read_to_string(&mut range)
+            .unwrap();
+        assert_eq!(range, "world");
+    }
+
+    #[test]
+    fn memory_storage_needs_parent_directories() {
+        let storage = MemoryStorage::default();
+        let err = storage
+            .write(Path::new("docs/notes.txt"), &mut io::empty())
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+
+        storage.create_dir(Path::new("docs")).unwrap();
+        write_str(&storage, "docs/notes.txt", "");
+        assert_eq!(names(&storage, ""), ["docs"]);
+        assert_eq!(names(&storage, "docs"), ["notes.txt"]);
+
+        let err = storage.create_dir(Path::new("docs")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn memory_storage_renames_directories_with_their_entries() {
+        let storage = MemoryStorage::default();
+        storage.create_dir(Path::new("a")).unwrap();
+        storage.create_dir(Path::new("a/b")).unwrap();
+        write_str(&storage, "a/b/c.txt", "c");
+
+        storage.rename(Path::new("a"), Path::new("z")).unwrap();
+        assert!(storage.stat(Path::new("a")).is_err());
+        assert_eq!(read_str(&storage, "z/b/c.txt"), "c");
+
+        let err = storage
+            .rename(Path::new("z"), Path::new("z/b/inside"))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn memory_storage_removes_directories_with_their_entries() {
+        let storage = MemoryStorage::default();
+        storage.create_dir(Path::new("a")).unwrap();
+        write_str(&storage, "a/b.txt", "b");
+        write_str(&storage, "ab.txt", "ab");
+
+        storage.remove(Path::new("a")).unwrap();
+        assert!(storage.stat(Path::new("a/b.txt")).is_err());
+        assert_eq!(names(&storage, ""), ["ab.txt"]);
+        assert!(storage.remove(Path::new("")).is_err());
+    }
+
+    #[test]
+    fn create_dir_all_creates_missing_parents() {
+        let storage = MemoryStorage::default();
+        create_dir_all(&storage, Path::new("a/b/c")).unwrap();
+        create_dir_all(&storage, Path::new("a/b")).unwrap();
+        assert!(storage.stat(Path::new("a/b/c")).unwrap().is_dir);
+
+        write_str(&storage, "a/file", "");
+        assert!(create_dir_all(&storage, Path::new("a/file/d")).is_err());
+    }
+
+    #[test]
+    fn moves_between_the_local_disk_and_memory() {
+        let local_dir = temp_dir();
+        let storage = MemoryStorage::default();
+
+        fs::create_dir(local_dir.join("dir")).unwrap();
+        fs::write(local_dir.join("dir/file.txt"), "content").unwrap();
+        storage
+            .move_in(&local_dir.join("dir"), Path::new("dir"), false)
+            .unwrap();
+        assert!(!local_dir.join("dir").exists());
+        assert_eq!(read_str(&storage, "dir/file.txt"), "content");
+
+        storage
+            .move_out(Path::new("dir"), &local_dir.join("out"))
+            .unwrap();
+        assert!(storage.stat(Path::new("dir")).is_err());
+        assert_eq!(
+            fs::read_to_string(local_dir.join("out/file.txt")).unwrap(),
+            "content"
+        );
+
+        fs::remove_dir_all(local_dir).unwrap();
+    }
+
+    #[test]
+    fn move_in_replaces_files_only_when_asked() {
+        let local_dir = temp_dir();
+        let storage = MemoryStorage::default();
+        write_str(&storage, "file.txt", "old");
+
+        fs::write(local_dir.join("new"), "new").unwrap();
+        let err = storage
+            .move_in(&local_dir.join("new"), Path::new("file.txt"), false)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(read_str(&storage, "file.txt"), "old");
+
+        storage
+            .move_in(&local_dir.join("new"), Path::new("file.txt"), true)
+            .unwrap();
+        assert_eq!(read_str(&storage, "file.txt"), "new");
+        assert!(!local_dir.join("new").exists());
+
+        fs::remove_dir_all(local_dir).unwrap();
+    }
+
+    #[test]
+    fn local_storage_moves_without_replacing() {
+        let root = temp_dir();
+        let staging = temp_dir();
+        let storage = LocalStorage::new(&root);
+        fs::write(root.join("file.txt"), "old").unwrap();
+
+        fs::write(staging.join("new"), "new").unwrap();
+        let err = storage
+            .move_in(&staging.join("new"), Path::new("file.txt"), false)
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert!(staging.join("new").exists());
+
+        storage
+            .move_in(&staging.join("new"), Path::new("other.txt"), false)
+            .unwrap();
+        assert_eq!(fs::read_to_string(root.join("other.txt")).unwrap(), "new");
+
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(staging).unwrap();
+    }
+}
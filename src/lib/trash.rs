@@ -0,0 +1,260 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    storage::{create_dir_all, Storage},
+    versions::normalize_file_path,
+};
+
+const INFO_FILE_NAME: &str = "info.json";
+const CONTENT_NAME: &str = "content";
+
+/// Headers naming the authenticated user, set by a reverse proxy doing the authentication
+pub const USER_HEADERS: [&str; 2] = ["Remote-User", "X-Forwarded-User"];
+/// User recorded when a deleting request doesn't name one
+pub const ANONYMOUS_USER: &str = "anonymous";
+
+/// File or directory deleted through the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashEntry {
+    pub id: String,
+    /// Path relative to the root of the storage the entry was deleted from
+    pub original_path: String,
+    /// Seconds since the epoch
+    pub deleted_at: u64,
+    pub deleted_by: String,
+    pub is_dir: bool,
+    /// Size of files, `None` for directories
+    pub size: Option<u64>,
+}
+
+/// Keeps deleted entries of a storage so they can be restored until they expire.
+///
+/// Every entry is moved into its own directory along with a file recording where it came
+/// from. Entries of local storages are renamed when the trash is on the same file system,
+/// copied otherwise.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    root: PathBuf,
+    /// Entries older than this are purged, kept until purged by hand when `None`
+    max_age: Option<Duration>,
+}
+
+impl Trash {
+    pub fn new(data_dir: &Path, max_age: Option<Duration>) -> Self {
+        Self {
+            root: data_dir.join("trash"),
+            max_age,
+        }
+    }
+
+    /// Moves the entry at `relative_path` in `storage` into the trash
+    pub fn delete(
+        &self,
+        storage: &dyn Storage,
+        relative_path: &str,
+        user: &str,
+    ) -> io::Result<TrashEntry> {
+        let path = normalize_file_path(relative_path)?;
+        let metadata = storage.stat(&path)?;
+        self.expire()?;
+
+        let (id, entry_dir) = self.create_entry_dir()?;
+        let entry = TrashEntry {
+            id,
+            original_path: relative_path.trim_start_matches('/').to_owned(),
+            deleted_at: now().as_secs(),
+            deleted_by: user.to_owned(),
+            is_dir: metadata.is_dir,
+            size: (!metadata.is_dir).then_some(metadata.len),
+        };
+
+        // The info is written first so an entry is never left without it
+        fs::write(
+            entry_dir.join(INFO_FILE_NAME),
+            serde_json::to_vec(&entry).map_err(io::Error::other)?,
+        )?;
+        if let Err(err) = storage.move_out(&path, &entry_dir.join(CONTENT_NAME)) {
+            let _ = fs::remove_dir_all(&entry_dir);
+            return Err(err);
+        }
+
+        Ok(entry)
+    }
+
+    /// Lists the entries in the trash, most recently deleted first
+    pub fn list(&self) -> io::Result<Vec<TrashEntry>> {
+        self.expire()?;
+        self.read_entries()
+    }
+
+    /// Moves an entry back to where it was deleted from, failing when something else has been
+    /// created there since
+    pub fn restore(&self, storage: &dyn Storage, id: &str) -> io::Result<TrashEntry> {
+        let (entry_dir, entry) = self.find(id)?;
+        let path = normalize_file_path(&entry.original_path)?;
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(storage, parent)?;
+        }
+
+        match storage.move_in(&entry_dir.join(CONTENT_NAME), &path, false) {
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} already exists", entry.original_path),
+                ))
+            }
+            result => result?,
+        }
+        fs::remove_dir_all(entry_dir)?;
+        Ok(entry)
+    }
+
+    /// Permanently removes an entry
+    pub fn purge(&self, id: &str) -> io::Result<TrashEntry> {
+        let (entry_dir, entry) = self.find(id)?;
+        fs::remove_dir_all(entry_dir)?;
+        Ok(entry)
+    }
+
+    /// Permanently removes every entry, returning how many were removed
+    pub fn purge_all(&self) -> io::Result<usize> {
+        let entries = self.read_entries()?;
+        for entry in &entries {
+            fs::remove_dir_all(self.root.join(&entry.id))?;
+        }
+
+        Ok(entries.len())
+    }
+
+    /// Purges the entries older than the configured maximum age
+    pub fn expire(&self) -> io::Result<()> {
+        let Some(max_age) = self.max_age else {
+            return Ok(());
+        };
+        let oldest_kept = now().saturating_sub(max_age).as_secs();
+
+        for entry in self.read_entries()? {
+            if entry.deleted_at < oldest_kept {
+                fs::remove_dir_all(self.root.join(&entry.id))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_entries(&self) -> io::Result<Vec<TrashEntry>> {
+        let dirs = match fs::read_dir(&self.root) {
+            Ok(dirs) => dirs,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut entries: Vec<TrashEntry> = dirs
+            .flatten()
+            .filter_map(|dir| read_info(&dir.path()).ok())
+            .collect();
+
+        entries.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(b.id.cmp(&a.id)));
+        Ok(entries)
+    }
+
+    fn find(&self, id: &str) -> io::Result<(PathBuf, TrashEntry)> {
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{id} is not a valid trash entry"),
+            ));
+        }
+
+        let entry_dir = self.root.join(id);
+        match read_info(&entry_dir) {
+            Ok(entry) => Ok((entry_dir, entry)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("Trash entry {id} does not exist"),
+            )),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn create_entry_dir(&self) -> io::Result<(String, PathBuf)> {
+        fs::create_dir_all(&self.root)?;
+
+        let mut id = now().as_nanos();
+        loop {
+            let entry_dir = self.root.join(id.to_string());
+            match fs::create_dir(&entry_dir) {
+                Ok(()) => return Ok((id.to_string(), entry_dir)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => id += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+fn read_info(entry_dir: &Path) -> io::Result<TrashEntry> {
+    let info = fs::read(entry_dir.join(INFO_FILE_NAME))?;
+    serde_json::from_slice(&info).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        tests::{read_str, temp_dir, write_str},
+        MemoryStorage,
+    };
+
+    #[test]
+    fn deleted_entries_are_restored_where_they_were() {
+        let data_dir = temp_dir();
+        let storage = MemoryStorage::default();
+        storage.create_dir(Path::new("docs")).unwrap();
+        write_str(&storage, "docs/notes.txt", "notes");
+        let trash = Trash::new(&data_dir, None);
+
+        let entry = trash.delete(&storage, "docs", "alice").unwrap();
+        assert!(entry.is_dir);
+        assert!(storage.stat(Path::new("docs")).is_err());
+        assert_eq!(trash.list().unwrap().len(), 1);
+
+        trash.restore(&storage, &entry.id).unwrap();
+        assert_eq!(read_str(&storage, "docs/notes.txt"), "notes");
+        assert!(trash.list().unwrap().is_empty());
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn restoring_never_replaces_new_entries() {
+        let data_dir = temp_dir();
+        let storage = MemoryStorage::default();
+        write_str(&storage, "notes.txt", "old");
+        let trash = Trash::new(&data_dir, None);
+
+        let entry = trash.delete(&storage, "notes.txt", "alice").unwrap();
+        assert_eq!(entry.size, Some(3));
+        write_str(&storage, "notes.txt", "new");
+
+        let err = trash.restore(&storage, &entry.id).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::AlreadyExists);
+        assert_eq!(read_str(&storage, "notes.txt"), "new");
+        assert_eq!(trash.list().unwrap().len(), 1);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -0,0 +1,454 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt, fs,
+    io::{self, ErrorKind, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::{
+    storage::Storage,
+    versions::{normalize_file_path, Version, VersionStore},
+};
+
+/// Version of the tus resumable upload protocol implemented
+pub const TUS_VERSION: &str = "1.0.0";
+pub const TUS_EXTENSIONS: &str = "creation,termination,checksum,expiration";
+pub const CHECKSUM_ALGORITHMS: &str = "sha1,sha256,md5";
+
+/// Upload created with the tus protocol, its content is appended to a staging file until
+/// complete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Upload {
+    pub id: String,
+    /// Where the file is moved to when complete, relative to `base_dir`
+    pub path: String,
+    pub length: u64,
+    /// Bytes received so far, read from the staging file
+    #[serde(skip)]
+    pub offset: u64,
+    /// Raw `Upload-Metadata` pairs, values are still base64 encoded
+    pub metadata: BTreeMap<String, String>,
+    /// Seconds since the epoch
+    pub expires_at: u64,
+    /// Whether the upload has been moved to its path, it is still reported until it expires
+    #[serde(default)]
+    pub completed: bool,
+}
+
+impl Upload {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    NotFound,
+    /// The client's offset is not the one of the upload
+    OffsetMismatch {
+        expected: u64,
+    },
+    /// Another request is appending to the upload
+    Locked,
+    /// More bytes were sent than the upload length
+    TooLarge,
+    ChecksumMismatch,
+    Invalid(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "Upload does not exist or has expired"),
+            Self::OffsetMismatch { expected } => write!(f, "Expected offset {expected}"),
+            Self::Locked => write!(f, "Upload is being written by another request"),
+            Self::TooLarge => write!(f, "Content exceeds the upload length"),
+            Self::ChecksumMismatch => write!(f, "Checksum mismatch"),
+            Self::Invalid(message) => write!(f, "{message}"),
+            Self::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<io::Error> for UploadError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            ErrorKind::NotFound => Self::NotFound,
+            _ => Self::Io(err),
+        }
+    }
+}
+
+/// Keeps unfinished uploads in a staging directory until they are complete or expire.
+///
+/// Staging must be on the same file system as local storages so completed files are moved
+/// into place atomically.
+#[derive(Debug)]
+pub struct UploadStore {
+    dir: PathBuf,
+    expiry: Duration,
+    /// Uploads being appended to, a PATCH holds its upload until done
+    busy: Arc<Mutex<HashSet<String>>>,
+}
+
+impl UploadStore {
+    pub fn new(data_dir: &Path, expiry: Duration) -> Self {
+        Self {
+            dir: data_dir.join("uploads"),
+            expiry,
+            busy: Arc::default(),
+        }
+    }
+
+    /// Starts an upload of `length` bytes to `path`
+    pub fn create(
+        &self,
+        path: &str,
+        length: u64,
+        metadata: BTreeMap<String, String>,
+    ) -> Result<Upload, UploadError> {
+        let path = path.trim_start_matches('/');
+        // Rejects paths outside of the storage
+        normalize_file_path(path).map_err(|err| UploadError::Invalid(err.to_string()))?;
+
+        self.expire()?;
+        fs::create_dir_all(&self.dir)?;
+
+        let upload = Upload {
+            id: format!("{:032x}", rand::random::<u128>()),
+            path: path.to_owned(),
+            length,
+            offset: 0,
+            metadata,
+            expires_at: (now() + self.expiry).as_secs(),
+            completed: false,
+        };
+
+        fs::File::create(self.part_path(&upload.id))?;
+        self.write_info(&upload)?;
+        Ok(upload)
+    }
+
+    /// Looks up an upload that has not expired
+    pub fn get(&self, id: &str) -> Result<Upload, UploadError> {
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(UploadError::NotFound);
+        }
+
+        let info = fs::read(self.info_path(id))?;
+        let mut upload: Upload = serde_json::from_slice(&info)
+            .map_err(|err| UploadError::Io(io::Error::new(ErrorKind::InvalidData, err)))?;
+
+        if upload.expires_at < now().as_secs() {
+            self.remove(id)?;
+            return Err(UploadError::NotFound);
+        }
+
+        upload.offset = match upload.completed {
+            true => upload.length,
+            false => fs::metadata(self.part_path(id))?.len(),
+        };
+        Ok(upload)
+    }
+
+    /// Starts appending to an upload at `offset`, verifying the appended bytes against
+    /// `checksum` (an `Upload-Checksum` header value) when given
+    pub fn append(
+        &self,
+        id: &str,
+        offset: u64,
+        checksum: Option<&str>,
+    ) -> Result<UploadWriter, UploadError> {
+        let checksum = checksum.map(Checksum::parse).transpose()?;
+        let lock = UploadLock::acquire(&self.busy, id)?;
+
+        let upload = self.get(id)?;
+        if upload.completed {
+            return Err(UploadError::Invalid(format!(
+                "Upload {id} is already complete"
+            )));
+        }
+        if upload.offset != offset {
+            return Err(UploadError::OffsetMismatch {
+                expected: upload.offset,
+            });
+        }
+
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(self.part_path(id))?;
+        file.seek(SeekFrom::Start(offset))?;
+
+        Ok(UploadWriter {
+            start: offset,
+            upload,
+            file,
+            checksum,
+            _lock: lock,
+        })
+    }
+
+    /// Moves a complete upload to its path in `storage`, keeping the content it replaces as a
+    /// version
+    pub fn complete(
+        &self,
+        upload: &mut Upload,
+        storage: &dyn Storage,
+        versions: &VersionStore,
+    ) -> Result<Option<Version>, UploadError> {
+        if !upload.is_complete() {
+            return Err(UploadError::Invalid(format!(
+                "Upload {} is not complete",
+                upload.id
+            )));
+        }
+
+        let version = versions.replace(storage, &upload.path, &self.part_path(&upload.id))?;
+        upload.completed = true;
+        self.write_info(upload)?;
+        Ok(version)
+    }
+
+    /// Removes an upload, discarding what was received
+    pub fn terminate(&self, id: &str) -> Result<(), UploadError> {
+        let _lock = UploadLock::acquire(&self.busy, id)?;
+        self.get(id)?;
+        self.remove(id)
+    }
+
+    /// Removes the uploads that expired along with staging files left without their upload
+    pub fn expire(&self) -> Result<(), UploadError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let ids: HashSet<String> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name();
+                let (id, _) = name.to_str()?.split_once('.')?;
+                Some(id.to_owned())
+            })
+            .collect();
+
+        for id in ids {
+            if self.busy.lock().unwrap().contains(&id) {
+                continue;
+            }
+
+            match self.get(&id) {
+                Ok(_) => {}
+                Err(UploadError::NotFound) => self.remove(&id)?,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> Result<(), UploadError> {
+        for path in [self.part_path(id), self.info_path(id)] {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != ErrorKind::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn write_info(&self, upload: &Upload) -> io::Result<()> {
+        let info = serde_json::to_vec(upload).map_err(io::Error::other)?;
+        fs::write(self.info_path(&upload.id), info)
+    }
+
+    fn part_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.part"))
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{id}.json"))
+    }
+}
+
+/// Appends the body of a PATCH request to an upload
+pub struct UploadWriter {
+    /// Offset the request started appending at
+    start: u64,
+    upload: Upload,
+    file: fs::File,
+    checksum: Option<Checksum>,
+    _lock: UploadLock,
+}
+
+impl UploadWriter {
+    pub fn write(&mut self, chunk: &[u8]) -> Result<(), UploadError> {
+        if self.upload.offset + chunk.len() as u64 > self.upload.length {
+            self.discard()?;
+            return Err(UploadError::TooLarge);
+        }
+
+        self.file.write_all(chunk)?;
+        self.upload.offset += chunk.len() as u64;
+        if let Some(checksum) = self.checksum.as_mut() {
+            checksum.update(chunk);
+        }
+
+        Ok(())
+    }
+
+    /// Keeps the appended bytes when they match the checksum, returning the upload with its
+    /// new offset
+    pub fn finish(mut self) -> Result<Upload, UploadError> {
+        if let Some(checksum) = self.checksum.take() {
+            if !checksum.matches() {
+                self.discard()?;
+                return Err(UploadError::ChecksumMismatch);
+            }
+        }
+
+        self.file.sync_all()?;
+        Ok(self.upload)
+    }
+
+    /// Drops the bytes appended by this request when a checksum was requested, which can only
+    /// be verified once all of them are received. Otherwise they are kept for the client to
+    /// resume from.
+    pub fn abort(mut self) -> Result<Upload, UploadError> {
+        if self.checksum.is_some() {
+            self.discard()?;
+        }
+
+        Ok(self.upload)
+    }
+
+    fn discard(&mut self) -> io::Result<()> {
+        self.file.set_len(self.start)?;
+        self.upload.offset = self.start;
+        Ok(())
+    }
+}
+
+/// Expected digest of the bytes appended by a PATCH request
+struct Checksum {
+    hasher: Hasher,
+    expected: Vec<u8>,
+}
+
+enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Md5(Md5),
+}
+
+impl Checksum {
+    /// Parses an `Upload-Checksum` header value, e.g. `sha1 Kq5sNclPz7QV2+lfQIuc6R7oRu0=`
+    fn parse(header: &str) -> Result<Self, UploadError> {
+        let (algorithm, digest) = header.trim().split_once(' ').ok_or_else(|| {
+            UploadError::Invalid(format!("{header} is not a valid Upload-Checksum"))
+        })?;
+
+        let hasher = match algorithm {
+            "sha1" => Hasher::Sha1(Sha1::new()),
+            "sha256" => Hasher::Sha256(Sha256::new()),
+            "md5" => Hasher::Md5(Md5::new()),
+            _ => {
+                return Err(UploadError::Invalid(format!(
+                    "Unsupported checksum algorithm {algorithm}"
+                )))
+            }
+        };
+        let expected = STANDARD
+            .decode(digest.trim())
+            .map_err(|_| UploadError::Invalid(format!("{digest} is not valid base64")))?;
+
+        Ok(Self { hasher, expected })
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        match &mut self.hasher {
+            Hasher::Sha1(hasher) => hasher.update(chunk),
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+            Hasher::Md5(hasher) => hasher.update(chunk),
+        }
+    }
+
+    fn matches(self) -> bool {
+        let digest = match self.hasher {
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+        };
+
+        digest == self.expected
+    }
+}
+
+/// Marks an upload as busy until dropped
+struct UploadLock {
+    busy: Arc<Mutex<HashSet<String>>>,
+    id: String,
+}
+
+impl UploadLock {
+    fn acquire(busy: &Arc<Mutex<HashSet<String>>>, id: &str) -> Result<Self, UploadError> {
+        if !busy.lock().unwrap().insert(id.to_owned()) {
+            return Err(UploadError::Locked);
+        }
+
+        Ok(Self {
+            busy: busy.clone(),
+            id: id.to_owned(),
+        })
+    }
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.busy.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Parses an `Upload-Metadata` header, comma separated keys each followed by an optional
+/// base64 encoded value
+pub fn parse_metadata(header: &str) -> Result<BTreeMap<String, String>, UploadError> {
+    header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+            let value = value.trim();
+            if STANDARD.decode(value).is_err() {
+                return Err(UploadError::Invalid(format!(
+                    "Metadata {key} is not valid base64"
+                )));
+            }
+
+            Ok((key.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Decodes a value of the `Upload-Metadata` header
+pub fn metadata_value(metadata: &BTreeMap<String, String>, key: &str) -> Option<String> {
+    let value = STANDARD.decode(metadata.get(key)?).ok()?;
+    String::from_utf8(value).ok()
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
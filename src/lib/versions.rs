@@ -0,0 +1,346 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{
+    normalize_path,
+    storage::{create_dir_all, Storage},
+};
+
+/// How long previous copies of a file are kept
+#[derive(Debug, Clone, Copy)]
+pub struct Retention {
+    /// Most copies kept per file, versioning is disabled when 0
+    pub max_count: usize,
+    /// Copies older than this are removed, kept regardless of age when `None`
+    pub max_age: Option<Duration>,
+}
+
+/// Previous copy of a file. Ids are the time the copy was taken, in nanoseconds since the epoch.
+#[derive(Debug, Clone, Serialize)]
+pub struct Version {
+    pub id: String,
+    pub size: u64,
+    /// Seconds since the epoch
+    pub created: u64,
+}
+
+/// Keeps previous copies of files when they are overwritten.
+///
+/// Copies of a file are stored in a directory named after its path relative to the root of the
+/// storage, with `/` escaped so the directories of different files never nest.
+#[derive(Debug, Clone)]
+pub struct VersionStore {
+    root: PathBuf,
+    /// Where new content is written before replacing a file, on the same file system as local
+    /// storages so the replacement is atomic
+    staging_dir: PathBuf,
+    retention: Retention,
+}
+
+impl VersionStore {
+    pub fn new(data_dir: &Path, retention: Retention) -> Self {
+        Self {
+            root: data_dir.join("versions"),
+            staging_dir: data_dir.join("tmp"),
+            retention,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.retention.max_count > 0
+    }
+
+    /// Creates an empty file to write new content to before it replaces a file with
+    /// [`VersionStore::replace`]
+    pub fn create_staging_file(&self) -> io::Result<(PathBuf, fs::File)> {
+        fs::create_dir_all(&self.staging_dir)?;
+
+        let mut id = now_nanos();
+        loop {
+            let path = self.staging_dir.join(format!("{id}.part"));
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(file) => return Ok((path, file)),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => id += 1,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Replaces the file at `relative_path` in `storage` with `new_content`, keeping a copy of
+    /// the previous content. Returns the version of the previous content, if any.
+    pub fn replace(
+        &self,
+        storage: &dyn Storage,
+        relative_path: &str,
+        new_content: &Path,
+    ) -> io::Result<Option<Version>> {
+        let path = normalize_file_path(relative_path)?;
+        let version = match storage.stat(&path) {
+            Ok(metadata) if metadata.is_dir => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("{relative_path} is a directory"),
+                ))
+            }
+            Ok(_) => self.save(storage, relative_path, &path)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                if let Some(parent) = path.parent() {
+                    create_dir_all(storage, parent)?;
+                }
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        storage.move_in(new_content, &path, true)?;
+        Ok(version)
+    }
+
+    /// Stores the current content of a file that is about to be replaced
+    fn save(
+        &self,
+        storage: &dyn Storage,
+        relative_path: &str,
+        path: &Path,
+    ) -> io::Result<Option<Version>> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        let versions_dir = self.versions_dir(relative_path)?;
+        fs::create_dir_all(&versions_dir)?;
+
+        let mut id = now_nanos();
+        while versions_dir.join(id.to_string()).exists() {
+            id += 1;
+        }
+        let version_path = versions_dir.join(id.to_string());
+
+        // Files on the local disk are replaced by renaming over them, so a hard link keeps the
+        // previous content without copying it
+        let linked = storage
+            .local_path(path)
+            .is_some_and(|file_path| fs::hard_link(file_path, &version_path).is_ok());
+        if !linked {
+            let mut version_file = fs::File::create(&version_path)?;
+            if let Err(err) = io::copy(&mut storage.open(path, None)?, &mut version_file) {
+                let _ = fs::remove_file(&version_path);
+                return Err(err);
+            }
+        }
+
+        self.prune(relative_path)?;
+        Ok(Some(Version {
+            id: id.to_string(),
+            size: fs::metadata(&version_path)?.len(),
+            created: (id / 1_000_000_000) as u64,
+        }))
+    }
+
+    /// Lists the stored copies of a file, newest first
+    pub fn list(&self, relative_path: &str) -> io::Result<Vec<Version>> {
+        // Copies also expire while a file is not written to
+        self.prune(relative_path)?;
+        self.read_versions(relative_path)
+    }
+
+    fn read_versions(&self, relative_path: &str) -> io::Result<Vec<Version>> {
+        let versions_dir = self.versions_dir(relative_path)?;
+        let entries = match fs::read_dir(versions_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err),
+        };
+
+        let mut versions: Vec<(u128, Version)> = entries
+            .flatten()
+            .filter_map(|entry| {
+                let id = entry.file_name().to_str()?.parse::<u128>().ok()?;
+                Some((
+                    id,
+                    Version {
+                        id: id.to_string(),
+                        size: entry.metadata().ok()?.len(),
+                        created: (id / 1_000_000_000) as u64,
+                    },
+                ))
+            })
+            .collect();
+
+        versions.sort_by(|(a, _), (b, _)| b.cmp(a));
+        Ok(versions.into_iter().map(|(_, version)| version).collect())
+    }
+
+    /// Path of a stored copy of a file
+    pub fn version_path(&self, relative_path: &str, id: &str) -> io::Result<PathBuf> {
+        if id.is_empty() || !id.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{id} is not a valid version"),
+            ));
+        }
+
+        let path = self.versions_dir(relative_path)?.join(id);
+        if !path.is_file() {
+            return Err(io::Error::new(
+                ErrorKind::NotFound,
+                format!("Version {id} of {relative_path} does not exist"),
+            ));
+        }
+
+        Ok(path)
+    }
+
+    /// Replaces a file with one of its stored copies. The content being replaced is stored as a
+    /// new version, so a restore can be undone.
+    pub fn restore(
+        &self,
+        storage: &dyn Storage,
+        relative_path: &str,
+        id: &str,
+    ) -> io::Result<Option<Version>> {
+        let version_path = self.version_path(relative_path, id)?;
+
+        let (staging_path, _) = self.create_staging_file()?;
+        if let Err(err) = fs::copy(&version_path, &staging_path) {
+            let _ = fs::remove_file(&staging_path);
+            return Err(err);
+        }
+
+        self.replace(storage, relative_path, &staging_path)
+            .inspect_err(|_| {
+                let _ = fs::remove_file(&staging_path);
+            })
+    }
+
+    /// Removes the copies of a file exceeding the retention policy
+    fn prune(&self, relative_path: &str) -> io::Result<()> {
+        let versions_dir = self.versions_dir(relative_path)?;
+        let oldest_kept = self
+            .retention
+            .max_age
+            .map(|max_age| now_nanos().saturating_sub(max_age.as_nanos()));
+
+        for (i, version) in self.read_versions(relative_path)?.iter().enumerate() {
+            let id = version.id.parse::<u128>().unwrap_or_default();
+            let expired = oldest_kept.is_some_and(|oldest_kept| id < oldest_kept);
+
+            if i >= self.retention.max_count || expired {
+                fs::remove_file(versions_dir.join(&version.id))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn versions_dir(&self, relative_path: &str) -> io::Result<PathBuf> {
+        let normalized = normalize_file_path(relative_path)?;
+        let escaped = normalized
+            .to_str()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Paths must be UTF-8"))?
+            .replace('%', "%25")
+            .replace('/', "%2F");
+
+        Ok(self.root.join(escaped))
+    }
+}
+
+/// Normalizes the path of a file like [`normalize_path`], rejecting the root
+pub fn normalize_file_path(relative_path: &str) -> io::Result<PathBuf> {
+    let normalized = normalize_path(relative_path)?;
+    if normalized.as_os_str().is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "A file path is required",
+        ));
+    }
+
+    Ok(normalized)
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{
+        tests::{read_str, temp_dir, write_str},
+        MemoryStorage,
+    };
+
+    fn staged(versions: &VersionStore, content: &str) -> PathBuf {
+        let (path, _) = versions.create_staging_file().unwrap();
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn replacing_a_file_keeps_its_previous_content() {
+        let data_dir = temp_dir();
+        let storage = MemoryStorage::default();
+        let versions = VersionStore::new(
+            &data_dir,
+            Retention {
+                max_count: 5,
+                max_age: None,
+            },
+        );
+
+        let created = versions
+            .replace(&storage, "docs/notes.txt", &staged(&versions, "first"))
+            .unwrap();
+        assert!(created.is_none());
+
+        let previous = versions
+            .replace(&storage, "docs/notes.txt", &staged(&versions, "second"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(previous.size, 5);
+        assert_eq!(read_str(&storage, "docs/notes.txt"), "second");
+
+        versions
+            .restore(&storage, "docs/notes.txt", &previous.id)
+            .unwrap();
+        assert_eq!(read_str(&storage, "docs/notes.txt"), "first");
+        assert_eq!(versions.list("docs/notes.txt").unwrap().len(), 2);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+
+    #[test]
+    fn directories_are_not_replaced() {
+        let data_dir = temp_dir();
+        let storage = MemoryStorage::default();
+        storage.create_dir(Path::new("docs")).unwrap();
+        write_str(&storage, "docs/notes.txt", "");
+        let versions = VersionStore::new(
+            &data_dir,
+            Retention {
+                max_count: 0,
+                max_age: None,
+            },
+        );
+
+        let err = versions
+            .replace(&storage, "docs", &staged(&versions, "content"))
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+
+        fs::remove_dir_all(data_dir).unwrap();
+    }
+}
@@ -1,8 +1,14 @@
 use configs::ServerConfigs;
 
 mod configs;
+mod dav;
+mod drop_box;
 mod file_manager;
 mod file_server;
+mod pastes;
+mod s3;
+mod shares;
+mod site;
 mod start;
 
 #[actix_web::main]
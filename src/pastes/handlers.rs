@@ -0,0 +1,219 @@
+use std::{io::ErrorKind, path::Path as FilePath, time::Duration};
+
+use actix_web::{
+    get,
+    http::header::{self, CacheControl, CacheDirective, ContentType},
+    post,
+    web::{self, BytesMut, Data, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use askama::Template;
+use file_server_core::{
+    escape_html,
+    highlight::{find_syntax, highlight_css, highlight_to_html, MAX_HIGHLIGHT_SIZE},
+    pastes::{NewPaste, Paste, PasteStore, MAX_PASTE_SIZE},
+};
+use futures_util::StreamExt;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    file_manager::templates::format_timestamp,
+    file_server::handlers::request_user,
+    pastes::templates::{PasteFormTemplate, PasteTemplate},
+};
+
+#[get("/paste")]
+pub async fn paste_form() -> HttpResponse {
+    let template = PasteFormTemplate {
+        max_size: MAX_PASTE_SIZE,
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreatePasteQuery {
+    /// File name or extension picking the syntax
+    name: Option<String>,
+    /// Seconds until the paste is deleted
+    expires_in: Option<u64>,
+    #[serde(default)]
+    burn: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PasteResponse {
+    #[serde(flatten)]
+    paste: Paste,
+    url: String,
+    raw_url: String,
+}
+
+/// Stores the request body as a paste
+#[post("/api/v1/pastes")]
+pub async fn create_paste(
+    req: HttpRequest,
+    pastes: Data<PasteStore>,
+    query: Query<CreatePasteQuery>,
+    mut payload: web::Payload,
+) -> HttpResponse {
+    let mut content = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => return HttpResponse::BadRequest().body(err.to_string()),
+        };
+        if content.len() + chunk.len() > MAX_PASTE_SIZE {
+            return HttpResponse::PayloadTooLarge()
+                .body(format!("Pastes are limited to {MAX_PASTE_SIZE} bytes"));
+        }
+        content.extend_from_slice(&chunk);
+    }
+    let Ok(content) = String::from_utf8(content.to_vec()) else {
+        return HttpResponse::BadRequest().body("Pastes must be UTF-8 text");
+    };
+    if content.is_empty() {
+        return HttpResponse::BadRequest().body("Pastes can't be empty");
+    }
+
+    let query = query.into_inner();
+    let new_paste = NewPaste {
+        name: query.name,
+        expires_in: query.expires_in.map(Duration::from_secs),
+        burn_after_read: query.burn,
+        created_by: request_user(&req),
+    };
+    let store = pastes.clone();
+    let paste = match web::block(move || store.create(&content, new_paste)).await {
+        Ok(Ok(paste)) => paste,
+        Ok(Err(err)) => return paste_error_response(err),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    info!(
+        "{} pasted {} ({} B) as {}",
+        paste.created_by, paste.name, paste.size, paste.id
+    );
+
+    let connection = req.connection_info();
+    let url = format!(
+        "{}://{}/p/{}",
+        connection.scheme(),
+        connection.host(),
+        paste.id
+    );
+
+    HttpResponse::Created()
+        .insert_header(ContentType::json())
+        .insert_header((header::LOCATION, url.clone()))
+        .body(
+            serde_json::to_string(&PasteResponse {
+                raw_url: format!("{url}/raw"),
+                url,
+                paste,
+            })
+            .unwrap(),
+        )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ViewPasteQuery {
+    /// Shows a paste burnt after reading rather than asking first, so link previews don't
+    /// burn it
+    #[serde(default)]
+    reveal: bool,
+}
+
+#[get("/p/{id}")]
+pub async fn view_paste(
+    pastes: Data<PasteStore>,
+    id: Path<String>,
+    query: Query<ViewPasteQuery>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    let store = pastes.clone();
+    let paste = match web::block(move || store.get(&id)).await {
+        Ok(Ok(paste)) => paste,
+        Ok(Err(err)) => return paste_error_response(err),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let content = if paste.burn_after_read && !query.reveal {
+        None
+    } else {
+        let store = pastes.clone();
+        let id = paste.id.clone();
+        match web::block(move || store.read(&id)).await {
+            Ok(Ok((_, content))) => Some(content),
+            Ok(Err(err)) => return paste_error_response(err),
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        }
+    };
+    if content.is_some() && paste.burn_after_read {
+        info!("Paste {} was read and deleted", paste.id);
+    }
+
+    let line_count = content
+        .as_deref()
+        .map_or(0, |content| content.lines().count());
+    let content = content.map(|content| {
+        let syntax = find_syntax(FilePath::new(&paste.name), content.as_bytes());
+        match syntax.filter(|_| paste.size <= MAX_HIGHLIGHT_SIZE) {
+            Some(syntax) => highlight_to_html(&content, syntax),
+            None => escape_html(&content),
+        }
+    });
+
+    let template = PasteTemplate {
+        id: paste.id,
+        name: escape_html(&paste.name),
+        created_at: format_timestamp(paste.created_at),
+        expires_at: paste.expires_at.map(format_timestamp),
+        burn_after_read: paste.burn_after_read,
+        highlight_css: highlight_css(),
+        content,
+        line_count,
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::html())
+        .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+        .body(template)
+}
+
+#[get("/p/{id}/raw")]
+pub async fn raw_paste(pastes: Data<PasteStore>, id: Path<String>) -> HttpResponse {
+    let id = id.into_inner();
+
+    match web::block(move || pastes.read(&id)).await {
+        Ok(Ok((paste, content))) => {
+            if paste.burn_after_read {
+                info!("Paste {} was read and deleted", paste.id);
+            }
+
+            HttpResponse::Ok()
+                .insert_header(ContentType::plaintext())
+                .insert_header(CacheControl(vec![CacheDirective::NoStore]))
+                .body(content)
+        }
+        Ok(Err(err)) => paste_error_response(err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+fn paste_error_response(err: std::io::Error) -> HttpResponse {
+    match err.kind() {
+        ErrorKind::NotFound => HttpResponse::NotFound().body("No such paste, it may have expired"),
+        ErrorKind::InvalidInput => HttpResponse::BadRequest().body(err.to_string()),
+        _ => {
+            info!("Failed to access a paste: {}", err);
+            HttpResponse::InternalServerError().body(err.to_string())
+        }
+    }
+}
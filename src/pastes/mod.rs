@@ -0,0 +1,11 @@
+use actix_web::web;
+
+pub mod handlers;
+pub mod templates;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(handlers::paste_form)
+        .service(handlers::create_paste)
+        .service(handlers::view_paste)
+        .service(handlers::raw_paste);
+}
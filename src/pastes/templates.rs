@@ -0,0 +1,23 @@
+use askama::Template;
+
+/// Page posting new pastes
+#[derive(Debug, Template)]
+#[template(path = "paste-form.html", escape = "none")]
+pub struct PasteFormTemplate {
+    pub max_size: usize,
+}
+
+#[derive(Debug, Template)]
+#[template(path = "paste.html", escape = "none")]
+pub struct PasteTemplate {
+    pub id: String,
+    /// Escaped name of the paste
+    pub name: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub burn_after_read: bool,
+    pub highlight_css: &'static str,
+    /// Highlighted or escaped content, `None` until a paste burnt after reading is revealed
+    pub content: Option<String>,
+    pub line_count: usize,
+}
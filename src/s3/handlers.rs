@@ -0,0 +1,706 @@
+use actix_web::{
+    delete, get,
+    http::{header, StatusCode},
+    post, put, route,
+    web::{self, Data},
+    HttpRequest, HttpResponse, HttpResponseBuilder, Responder,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use file_server_core::s3::{
+    iso8601, key_path, list_objects, list_objects_xml, parse_complete_multipart,
+    parse_delete_objects, ListQuery, MultipartStore, ObjectWriter, S3Error, MAX_KEYS, S3_NAMESPACE,
+};
+use file_server_core::sigv4::{self, hex, Authorization, ChunkedDecoder, Payload, RequestParts};
+use file_server_core::storage::{create_dir_all, Storage};
+use file_server_core::trash::Trash;
+use file_server_core::versions::VersionStore;
+use file_server_core::*;
+use futures_util::StreamExt;
+use log::info;
+use percent_encoding::percent_decode_str;
+use sha2::{Digest, Sha256};
+
+use std::fs::{self, File};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::configs::ServerConfigs;
+use crate::file_server::handlers::serve_storage_file;
+
+/// Entity tag of an empty object
+const EMPTY_ETAG: &str = "\"d41d8cd98f00b204e9800998ecf8427e\"";
+
+/// Authenticated request to the bucket
+struct S3Request {
+    authorization: Authorization,
+    bucket: String,
+    /// Key of the object, empty for requests to the bucket itself
+    key: String,
+    query: Vec<(String, String)>,
+}
+
+impl S3Request {
+    fn param(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn resource(&self) -> String {
+        format!("/{}/{}", self.bucket, self.key)
+    }
+
+    fn error(&self, err: S3Error) -> HttpResponse {
+        s3_error_response(err, &self.resource())
+    }
+}
+
+/// ListBuckets, the only bucket serves the storage
+#[get("/")]
+pub async fn list_buckets(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+) -> impl Responder {
+    if let Err(err) = authenticate(&req, &configs) {
+        return s3_error_response(err, "/");
+    }
+
+    let created = storage
+        .stat(Path::new(""))
+        .ok()
+        .and_then(|metadata| metadata.modified)
+        .unwrap_or(UNIX_EPOCH);
+    xml_response(
+        StatusCode::OK,
+        format!(
+            "<ListAllMyBucketsResult xmlns=\"{S3_NAMESPACE}\"><Owner><ID>file-server</ID>\
+             <DisplayName>file-server</DisplayName></Owner><Buckets><Bucket><Name>{}</Name>\
+             <CreationDate>{}</CreationDate></Bucket></Buckets></ListAllMyBucketsResult>",
+            escape_html(&configs.s3_bucket),
+            iso8601(created),
+        ),
+    )
+}
+
+/// GetObject and HeadObject, or ListObjectsV2 and HeadBucket without a key
+#[route("/{bucket}{key:.*}", method = "GET", method = "HEAD")]
+pub async fn get_object(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+) -> impl Responder {
+    let request = match s3_request(&req, &configs) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if request.key.is_empty() {
+        return get_bucket(&req, &storage, &request).await;
+    }
+    if let Some(operation) = ["uploadId", "acl", "tagging", "attributes"]
+        .into_iter()
+        .find(|param| request.param(param).is_some())
+    {
+        return request.error(S3Error::not_implemented(operation));
+    }
+
+    let relative_path = match key_path(&request.key) {
+        Ok(path) => path,
+        Err(err) => return request.error(err),
+    };
+    let metadata = match storage.stat(&relative_path) {
+        Ok(metadata) if !metadata.is_dir => metadata,
+        _ => return request.error(S3Error::no_such_key(&request.key)),
+    };
+
+    serve_storage_file(
+        &req,
+        &storage,
+        &relative_path,
+        metadata,
+        &configs.mime_types,
+    )
+    .await
+}
+
+async fn get_bucket(
+    req: &HttpRequest,
+    storage: &Data<dyn Storage>,
+    request: &S3Request,
+) -> HttpResponse {
+    if req.method() == "HEAD" {
+        return HttpResponse::Ok().finish();
+    }
+    if request.param("location").is_some() {
+        // An empty constraint is the default region
+        return xml_response(
+            StatusCode::OK,
+            format!("<LocationConstraint xmlns=\"{S3_NAMESPACE}\"></LocationConstraint>"),
+        );
+    }
+    if request.param("list-type") != Some("2") {
+        return request.error(S3Error::not_implemented("ListObjects (version 1)"));
+    }
+
+    let max_keys = match request.param("max-keys").map(str::parse::<usize>) {
+        None => MAX_KEYS,
+        Some(Ok(max_keys)) => max_keys,
+        Some(Err(_)) => {
+            return request.error(S3Error::new(
+                400,
+                "InvalidArgument",
+                "max-keys must be a number",
+            ))
+        }
+    };
+    let query = ListQuery {
+        prefix: request.param("prefix").unwrap_or_default().to_owned(),
+        delimiter: request
+            .param("delimiter")
+            .filter(|delimiter| !delimiter.is_empty())
+            .map(str::to_owned),
+        max_keys,
+        start_after: request.param("start-after").map(str::to_owned),
+        continuation_token: request.param("continuation-token").map(str::to_owned),
+    };
+
+    let listing_storage = storage.clone();
+    let list_query = query.clone();
+    match web::block(move || list_objects(listing_storage.as_ref(), &list_query)).await {
+        Ok(Ok(result)) => xml_response(
+            StatusCode::OK,
+            list_objects_xml(
+                &request.bucket,
+                &query,
+                &result,
+                request.param("encoding-type") == Some("url"),
+            ),
+        ),
+        Ok(Err(err)) => request.error(err),
+        Err(err) => request.error(blocking_error(err)),
+    }
+}
+
+/// PutObject, or UploadPart with an upload id
+#[put("/{bucket}{key:.*}")]
+pub async fn put_object(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    versions: Data<VersionStore>,
+    multipart: Data<MultipartStore>,
+    payload: web::Payload,
+) -> impl Responder {
+    let request = match s3_request(&req, &configs) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if storage.is_read_only() {
+        return request.error(S3Error::read_only(&request.bucket));
+    }
+    if request.key.is_empty() {
+        return request.error(S3Error::new(
+            409,
+            "BucketAlreadyOwnedByYou",
+            format!("{} already exists", request.bucket),
+        ));
+    }
+    if req.headers().contains_key("x-amz-copy-source") {
+        return request.error(S3Error::not_implemented("CopyObject"));
+    }
+    let relative_path = match key_path(&request.key) {
+        Ok(path) => path,
+        Err(err) => return request.error(err),
+    };
+
+    if let (Some(part_number), Some(upload_id)) =
+        (request.param("partNumber"), request.param("uploadId"))
+    {
+        let Ok(part_number) = part_number.parse::<u32>() else {
+            return request.error(S3Error::new(
+                400,
+                "InvalidArgument",
+                "partNumber must be a number",
+            ));
+        };
+        let upload_id = upload_id.to_owned();
+        return upload_part(&req, &request, multipart, &upload_id, part_number, payload).await;
+    }
+
+    // Clients create folders with empty objects ending with a slash
+    if request.key.ends_with('/') {
+        return match web::block(move || create_dir_all(storage.as_ref(), &relative_path)).await {
+            Ok(Ok(())) => HttpResponse::Ok()
+                .insert_header((header::ETAG, EMPTY_ETAG))
+                .finish(),
+            Ok(Err(err)) => request.error(err.into()),
+            Err(err) => request.error(blocking_error(err)),
+        };
+    }
+
+    let staging_versions = versions.clone();
+    let (staging_path, staging_file) =
+        match web::block(move || staging_versions.create_staging_file()).await {
+            Ok(Ok(staging)) => staging,
+            Ok(Err(err)) => return request.error(err.into()),
+            Err(err) => return request.error(blocking_error(err)),
+        };
+
+    let md5 =
+        match receive_object(&req, &request.authorization.payload, payload, staging_file).await {
+            Ok(md5) => md5,
+            Err(err) => {
+                let _ = fs::remove_file(&staging_path);
+                return request.error(err);
+            }
+        };
+
+    let relative = relative_path.to_string_lossy().into_owned();
+    let replace_path = staging_path.clone();
+    match web::block(move || versions.replace(storage.as_ref(), &relative, &replace_path)).await {
+        Ok(Ok(_)) => {
+            info!(
+                "{} wrote {} over S3",
+                &request.authorization.access_key, &request.key
+            );
+            HttpResponse::Ok()
+                .insert_header((header::ETAG, format!("\"{}\"", hex(&md5))))
+                .finish()
+        }
+        Ok(Err(err)) => {
+            let _ = fs::remove_file(&staging_path);
+            request.error(err.into())
+        }
+        Err(err) => request.error(blocking_error(err)),
+    }
+}
+
+async fn upload_part(
+    req: &HttpRequest,
+    request: &S3Request,
+    multipart: Data<MultipartStore>,
+    upload_id: &str,
+    part_number: u32,
+    payload: web::Payload,
+) -> HttpResponse {
+    let upload = match multipart.get(upload_id, &request.key) {
+        Ok(upload) => upload,
+        Err(err) => return request.error(err),
+    };
+    let (part_path, part_file) = match multipart.create_part_file(&upload, part_number) {
+        Ok(part) => part,
+        Err(err) => return request.error(err),
+    };
+
+    let saved = match receive_object(req, &request.authorization.payload, payload, part_file).await
+    {
+        Ok(md5) => web::block(move || {
+            multipart
+                .save_part(&upload, part_number, &part_path, &md5)
+                .map(|_| md5)
+                .inspect_err(|_| {
+                    let _ = fs::remove_file(&part_path);
+                })
+        })
+        .await
+        .map_err(blocking_error)
+        .and_then(|saved| saved),
+        Err(err) => {
+            let _ = fs::remove_file(&part_path);
+            Err(err)
+        }
+    };
+
+    match saved {
+        Ok(md5) => HttpResponse::Ok()
+            .insert_header((header::ETAG, format!("\"{}\"", hex(&md5))))
+            .finish(),
+        Err(err) => request.error(err),
+    }
+}
+
+/// DeleteObject, or AbortMultipartUpload with an upload id
+#[delete("/{bucket}{key:.*}")]
+pub async fn delete_object(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    trash: Data<Trash>,
+    multipart: Data<MultipartStore>,
+) -> impl Responder {
+    let request = match s3_request(&req, &configs) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if storage.is_read_only() {
+        return request.error(S3Error::read_only(&request.bucket));
+    }
+    if request.key.is_empty() {
+        return request.error(S3Error::not_implemented("DeleteBucket"));
+    }
+
+    if let Some(upload_id) = request.param("uploadId") {
+        return match multipart
+            .get(upload_id, &request.key)
+            .and_then(|upload| multipart.remove(&upload))
+        {
+            Ok(()) => HttpResponse::NoContent().finish(),
+            Err(err) => request.error(err),
+        };
+    }
+
+    let key = request.key.clone();
+    let user = request.authorization.access_key.clone();
+    match web::block(move || delete_key(storage.as_ref(), &trash, &key, &user)).await {
+        Ok(Ok(())) => HttpResponse::NoContent().finish(),
+        Ok(Err(err)) => request.error(err),
+        Err(err) => request.error(blocking_error(err)),
+    }
+}
+
+/// CreateMultipartUpload and CompleteMultipartUpload, or DeleteObjects without a key
+#[post("/{bucket}{key:.*}")]
+pub async fn post_object(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    versions: Data<VersionStore>,
+    trash: Data<Trash>,
+    multipart: Data<MultipartStore>,
+    body: web::Bytes,
+) -> impl Responder {
+    let request = match s3_request(&req, &configs) {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    if storage.is_read_only() {
+        return request.error(S3Error::read_only(&request.bucket));
+    }
+    if let Err(err) = verify_body(&request.authorization.payload, &body) {
+        return request.error(err);
+    }
+
+    if request.key.is_empty() {
+        return match request.param("delete") {
+            Some(_) => delete_objects(storage, trash, &request, &body).await,
+            None => request.error(S3Error::not_implemented("This bucket operation")),
+        };
+    }
+
+    if request.param("uploads").is_some() {
+        return match multipart.create(&request.key) {
+            Ok(upload) => xml_response(
+                StatusCode::OK,
+                format!(
+                    "<InitiateMultipartUploadResult xmlns=\"{S3_NAMESPACE}\"><Bucket>{}</Bucket>\
+                     <Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    escape_html(&request.bucket),
+                    escape_html(&request.key),
+                    upload.id,
+                ),
+            ),
+            Err(err) => request.error(err),
+        };
+    }
+
+    let Some(upload_id) = request.param("uploadId") else {
+        return request.error(S3Error::not_implemented("This object operation"));
+    };
+    let upload = match multipart.get(upload_id, &request.key) {
+        Ok(upload) => upload,
+        Err(err) => return request.error(err),
+    };
+    let parts = match std::str::from_utf8(&body)
+        .map_err(|_| S3Error::new(400, "MalformedXML", "The XML is not valid UTF-8"))
+        .and_then(parse_complete_multipart)
+    {
+        Ok(parts) => parts,
+        Err(err) => return request.error(err),
+    };
+
+    let relative = request.key.clone();
+    let completed = web::block(move || {
+        let (staging_path, mut staging_file) = versions.create_staging_file()?;
+        let replaced = multipart
+            .assemble(&upload, &parts, &mut staging_file)
+            .and_then(|etag| {
+                staging_file.sync_all()?;
+                versions.replace(storage.as_ref(), &relative, &staging_path)?;
+                Ok(etag)
+            });
+        if replaced.is_err() {
+            let _ = fs::remove_file(&staging_path);
+        }
+
+        let etag = replaced?;
+        multipart.remove(&upload)?;
+        Ok::<_, S3Error>(etag)
+    });
+
+    match completed.await {
+        Ok(Ok(etag)) => {
+            info!(
+                "{} wrote {} over S3",
+                &request.authorization.access_key, &request.key
+            );
+            xml_response(
+                StatusCode::OK,
+                format!(
+                    "<CompleteMultipartUploadResult xmlns=\"{S3_NAMESPACE}\">\
+                     <Location>{}</Location><Bucket>{}</Bucket><Key>{}</Key>\
+                     <ETag>{}</ETag></CompleteMultipartUploadResult>",
+                    escape_html(&request.resource()),
+                    escape_html(&request.bucket),
+                    escape_html(&request.key),
+                    escape_html(&etag),
+                ),
+            )
+        }
+        Ok(Err(err)) => request.error(err),
+        Err(err) => request.error(blocking_error(err)),
+    }
+}
+
+async fn delete_objects(
+    storage: Data<dyn Storage>,
+    trash: Data<Trash>,
+    request: &S3Request,
+    body: &[u8],
+) -> HttpResponse {
+    let (keys, quiet) = match std::str::from_utf8(body)
+        .map_err(|_| S3Error::new(400, "MalformedXML", "The XML is not valid UTF-8"))
+        .and_then(parse_delete_objects)
+    {
+        Ok(parsed) => parsed,
+        Err(err) => return request.error(err),
+    };
+
+    let user = request.authorization.access_key.clone();
+    let deleted = web::block(move || {
+        keys.into_iter()
+            .map(|key| {
+                let deleted = delete_key(storage.as_ref(), &trash, &key, &user);
+                (key, deleted)
+            })
+            .collect::<Vec<_>>()
+    });
+    let deleted = match deleted.await {
+        Ok(deleted) => deleted,
+        Err(err) => return request.error(blocking_error(err)),
+    };
+
+    let mut xml = format!("<DeleteResult xmlns=\"{S3_NAMESPACE}\">");
+    for (key, deleted) in deleted {
+        match deleted {
+            Ok(()) if quiet => {}
+            Ok(()) => xml.push_str(&format!(
+                "<Deleted><Key>{}</Key></Deleted>",
+                escape_html(&key)
+            )),
+            Err(err) => xml.push_str(&format!(
+                "<Error><Key>{}</Key><Code>{}</Code><Message>{}</Message></Error>",
+                escape_html(&key),
+                err.code,
+                escape_html(&err.message),
+            )),
+        }
+    }
+    xml.push_str("</DeleteResult>");
+
+    xml_response(StatusCode::OK, xml)
+}
+
+/// Moves the file of a key to the trash. Deleting a missing key succeeds, and directories are
+/// only removed when empty since they are not objects themselves.
+fn delete_key(storage: &dyn Storage, trash: &Trash, key: &str, user: &str) -> Result<(), S3Error> {
+    let relative_path = key_path(key)?;
+    let metadata = match storage.stat(&relative_path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err.into()),
+    };
+
+    if metadata.is_dir {
+        if storage
+            .list(&relative_path)
+            .is_ok_and(|entries| entries.is_empty())
+        {
+            let _ = storage.remove(&relative_path);
+        }
+        return Ok(());
+    }
+
+    let entry = trash.delete(storage, &relative_path.to_string_lossy(), user)?;
+    info!("{} moved {} to the trash over S3", &entry.deleted_by, key);
+    Ok(())
+}
+
+/// Writes the payload of a request to `file`, checking it against its signature and
+/// `Content-MD5`. Returns the MD5 of the content.
+async fn receive_object(
+    req: &HttpRequest,
+    signed: &Payload,
+    mut payload: web::Payload,
+    file: File,
+) -> Result<[u8; 16], S3Error> {
+    let mut writer = ObjectWriter::new(file);
+    let mut decoder = match signed {
+        Payload::Chunked(signer) => Some(ChunkedDecoder::new(Some(signer.clone()))),
+        Payload::UnsignedChunked => Some(ChunkedDecoder::new(None)),
+        _ => None,
+    };
+    let mut sha256 = matches!(signed, Payload::Sha256(_)).then(Sha256::new);
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| S3Error::new(400, "IncompleteBody", err.to_string()))?;
+        if let Some(sha256) = sha256.as_mut() {
+            sha256.update(&chunk);
+        }
+
+        (writer, decoder) = web::block(move || {
+            match decoder.as_mut() {
+                Some(decoder) => decoder.decode(&chunk, &mut writer)?,
+                None => writer.write_all(&chunk)?,
+            }
+            Ok::<_, S3Error>((writer, decoder))
+        })
+        .await
+        .map_err(blocking_error)??;
+    }
+
+    if let Some(decoder) = decoder {
+        decoder.finish()?;
+    }
+    if let (Payload::Sha256(expected), Some(sha256)) = (signed, sha256) {
+        if hex(&sha256.finalize()) != *expected {
+            return Err(S3Error::new(
+                400,
+                "XAmzContentSHA256Mismatch",
+                "The payload does not match its signed SHA-256",
+            ));
+        }
+    }
+
+    let size = writer.size;
+    let decoded_length = header_value(req, "x-amz-decoded-content-length");
+    if decoded_length.is_some_and(|length| length.parse() != Ok(size)) {
+        return Err(S3Error::new(
+            400,
+            "IncompleteBody",
+            "The payload does not have the declared length",
+        ));
+    }
+
+    let (file, md5) = writer.into_inner();
+    if let Some(content_md5) = header_value(req, "content-md5") {
+        if STANDARD.decode(content_md5.trim()).ok().as_deref() != Some(md5.as_slice()) {
+            return Err(S3Error::new(
+                400,
+                "BadDigest",
+                "The payload does not match its Content-MD5",
+            ));
+        }
+    }
+
+    web::block(move || file.sync_all())
+        .await
+        .map_err(blocking_error)??;
+    Ok(md5)
+}
+
+/// Checks a payload read at once against its signature, chunked payloads are only accepted
+/// for objects
+fn verify_body(signed: &Payload, body: &[u8]) -> Result<(), S3Error> {
+    match signed {
+        Payload::Unsigned => Ok(()),
+        Payload::Sha256(expected) if hex(&Sha256::digest(body)) == *expected => Ok(()),
+        Payload::Sha256(_) => Err(S3Error::new(
+            400,
+            "XAmzContentSHA256Mismatch",
+            "The payload does not match its signed SHA-256",
+        )),
+        Payload::Chunked(_) | Payload::UnsignedChunked => Err(S3Error::new(
+            400,
+            "InvalidRequest",
+            "Chunked payloads are only accepted for objects",
+        )),
+    }
+}
+
+/// Authenticates a request and splits its path into the bucket and the key
+fn s3_request(req: &HttpRequest, configs: &ServerConfigs) -> Result<S3Request, HttpResponse> {
+    let path = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+    let path = path.strip_prefix('/').unwrap_or(&path);
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    let resource = format!("/{bucket}/{key}");
+
+    let authorization =
+        authenticate(req, configs).map_err(|err| s3_error_response(err, &resource))?;
+    if bucket != configs.s3_bucket {
+        return Err(s3_error_response(
+            S3Error::new(404, "NoSuchBucket", format!("{bucket} does not exist")),
+            &resource,
+        ));
+    }
+
+    Ok(S3Request {
+        authorization,
+        bucket: bucket.to_owned(),
+        key: key.to_owned(),
+        query: sigv4::parse_query(req.query_string()),
+    })
+}
+
+fn authenticate(req: &HttpRequest, configs: &ServerConfigs) -> Result<Authorization, S3Error> {
+    let request = RequestParts {
+        method: req.method().to_string(),
+        path: req.uri().path().to_owned(),
+        query: req.query_string().to_owned(),
+        headers: req
+            .headers()
+            .iter()
+            .map(|(name, value)| {
+                (
+                    name.as_str().to_owned(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+            .collect(),
+    };
+
+    sigv4::verify(&request, &configs.s3_credentials.0, SystemTime::now())
+}
+
+fn header_value<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name)?.to_str().ok()
+}
+
+fn blocking_error(err: impl std::fmt::Display) -> S3Error {
+    S3Error::new(500, "InternalError", err.to_string())
+}
+
+fn xml_response(status: StatusCode, body: String) -> HttpResponse {
+    HttpResponseBuilder::new(status)
+        .insert_header(("Content-Type", "application/xml"))
+        .body(format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n{}",
+            body
+        ))
+}
+
+fn s3_error_response(err: S3Error, resource: &str) -> HttpResponse {
+    let status = StatusCode::from_u16(err.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    HttpResponseBuilder::new(status)
+        .insert_header(("Content-Type", "application/xml"))
+        .body(err.to_xml(resource))
+}
+
+/// Responds to the requests of operations that are not implemented
+pub async fn not_implemented(req: HttpRequest) -> impl Responder {
+    s3_error_response(
+        S3Error::not_implemented(&format!("{} {}", req.method(), req.path())),
+        req.path(),
+    )
+}
@@ -0,0 +1,12 @@
+use actix_web::web;
+
+pub mod handlers;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(handlers::list_buckets)
+        .service(handlers::get_object)
+        .service(handlers::put_object)
+        .service(handlers::delete_object)
+        .service(handlers::post_object)
+        .default_service(web::to(handlers::not_implemented));
+}
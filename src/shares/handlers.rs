@@ -0,0 +1,606 @@
+use std::{fs, io::ErrorKind, time::Duration};
+
+use actix_web::{
+    cookie::{Cookie, SameSite},
+    delete, get,
+    http::{
+        header::{
+            self, ContentDisposition, ContentType, DispositionParam, DispositionType, HeaderValue,
+        },
+        StatusCode,
+    },
+    post, put,
+    web::{self, Data, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use askama::Template;
+use file_server_core::{
+    escape_html,
+    shares::{NewShare, Share, ShareError, ShareMode, ShareStore},
+    storage::Storage,
+    versions::{normalize_file_path, VersionStore},
+};
+use log::info;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    configs::ServerConfigs,
+    file_server::handlers::{check_writable, request_user, serve_storage_file, write_payload},
+    shares::templates::{
+        ShareEntryTemplate, ShareErrorTemplate, SharePageContent, SharePageTemplate,
+    },
+};
+
+#[derive(Debug, Deserialize)]
+pub struct CreateShareRequest {
+    /// Path relative to the root of the storage
+    path: String,
+    #[serde(default)]
+    mode: ShareMode,
+    /// Seconds until the link expires
+    expires_in: Option<u64>,
+    password: Option<String>,
+    max_downloads: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ShareResponse {
+    #[serde(flatten)]
+    share: Share,
+    url: String,
+}
+
+#[post("/api/v1/shares")]
+pub async fn create_share(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    shares: Data<ShareStore>,
+    body: web::Json<CreateShareRequest>,
+) -> HttpResponse {
+    let body = body.into_inner();
+    if body.mode == ShareMode::UploadOnly {
+        if let Err(response) = check_writable(storage.as_ref()) {
+            return response;
+        }
+    }
+    let new_share = NewShare {
+        path: body.path,
+        mode: body.mode,
+        expires_in: body.expires_in.map(Duration::from_secs),
+        password: body.password,
+        max_downloads: body.max_downloads,
+        created_by: request_user(&req),
+    };
+
+    let store = shares.clone();
+    // Hashing the password is deliberately slow
+    let share = match web::block(move || store.create(storage.as_ref(), new_share)).await {
+        Ok(Ok(share)) => share,
+        Ok(Err(err)) => return share_error_response(err),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+    info!(
+        "{} shared {} as {} until {}",
+        share.created_by, share.path, share.mode, share.expires_at
+    );
+
+    let connection = req.connection_info();
+    let url = format!(
+        "{}://{}/s/{}",
+        connection.scheme(),
+        connection.host(),
+        shares.token(&share)
+    );
+
+    HttpResponse::Created()
+        .insert_header(ContentType::json())
+        .body(serde_json::to_string(&ShareResponse { share, url }).unwrap())
+}
+
+#[get("/api/v1/shares")]
+pub async fn list_shares(req: HttpRequest, shares: Data<ShareStore>) -> HttpResponse {
+    let store = shares.clone();
+    let shares_list = match web::block(move || store.list()).await {
+        Ok(Ok(shares_list)) => shares_list,
+        Ok(Err(err)) => return share_error_response(err.into()),
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let connection = req.connection_info();
+    let responses: Vec<ShareResponse> = shares_list
+        .into_iter()
+        .map(|share| ShareResponse {
+            url: format!(
+                "{}://{}/s/{}",
+                connection.scheme(),
+                connection.host(),
+                shares.token(&share)
+            ),
+            share,
+        })
+        .collect();
+
+    HttpResponse::Ok()
+        .insert_header(ContentType::json())
+        .body(serde_json::to_string(&responses).unwrap())
+}
+
+#[delete("/api/v1/shares/{id}")]
+pub async fn revoke_share(
+    req: HttpRequest,
+    shares: Data<ShareStore>,
+    id: Path<String>,
+) -> HttpResponse {
+    let id = id.into_inner();
+
+    match web::block(move || shares.revoke(&id)).await {
+        Ok(Ok(share)) => {
+            info!("{} revoked the share of {}", request_user(&req), share.path);
+            HttpResponse::NoContent().finish()
+        }
+        Ok(Err(err)) => share_error_response(err),
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SharePathQuery {
+    /// Path within a shared directory
+    path: Option<String>,
+}
+
+#[get("/s/{token}")]
+pub async fn share_page(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    shares: Data<ShareStore>,
+    token: Path<String>,
+    query: Query<SharePathQuery>,
+) -> HttpResponse {
+    let share = match open_share(&req, &shares, &token) {
+        Ok(share) => share,
+        Err(response) => return response,
+    };
+    if !shares.is_unlocked(&share, unlock_cookie(&req, &share).as_deref()) {
+        return share_page_response(
+            StatusCode::OK,
+            SharePageTemplate::new(&share, &token, SharePageContent::Password { error: None }),
+        );
+    }
+
+    let content = match (share.mode, share.is_dir) {
+        (ShareMode::UploadOnly, _) => SharePageContent::Upload,
+        (ShareMode::ReadOnly, false) => {
+            let file_path = match normalize_file_path(&share.path) {
+                Ok(file_path) => file_path,
+                Err(err) => return share_error_page(err.into()),
+            };
+            match storage.stat(&file_path) {
+                Ok(metadata) => SharePageContent::File { size: metadata.len },
+                Err(err) => return share_error_page(err.into()),
+            }
+        }
+        (ShareMode::ReadOnly, true) => {
+            let relative = query.into_inner().path.unwrap_or_default();
+            let relative = relative.trim_matches('/').to_owned();
+            let shared_path = share.path.clone();
+            let listed =
+                web::block(move || list_share_directory(storage.as_ref(), &shared_path, &relative));
+
+            match listed.await {
+                Ok(Ok(content)) => content,
+                Ok(Err(err)) => return share_error_page(err),
+                Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+            }
+        }
+    };
+
+    share_page_response(
+        StatusCode::OK,
+        SharePageTemplate::new(&share, &token, content),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UnlockForm {
+    password: String,
+}
+
+#[post("/s/{token}")]
+pub async fn unlock_share(
+    req: HttpRequest,
+    shares: Data<ShareStore>,
+    token: Path<String>,
+    form: web::Form<UnlockForm>,
+) -> HttpResponse {
+    let share = match open_share(&req, &shares, &token) {
+        Ok(share) => share,
+        Err(response) => return response,
+    };
+
+    let (store, checked_share) = (shares.clone(), share.clone());
+    let password = form.into_inner().password;
+    let unlock_token = match web::block(move || {
+        store
+            .check_password(&checked_share, &password)
+            .then(|| store.unlock_token(&checked_share))
+    })
+    .await
+    {
+        Ok(Some(unlock_token)) => unlock_token,
+        Ok(None) => {
+            info!("Wrong password for the share of {}", share.path);
+            return share_page_response(
+                StatusCode::FORBIDDEN,
+                SharePageTemplate::new(
+                    &share,
+                    &token,
+                    SharePageContent::Password {
+                        error: Some("Wrong password".to_owned()),
+                    },
+                ),
+            );
+        }
+        Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+    };
+
+    let mut response = HttpResponse::SeeOther();
+    if let Some(unlock_token) = unlock_token {
+        response.cookie(
+            Cookie::build(unlock_cookie_name(&share), unlock_token)
+                .path(format!("/s/{token}"))
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .finish(),
+        );
+    }
+
+    response
+        .insert_header((header::LOCATION, format!("/s/{token}")))
+        .finish()
+}
+
+#[get("/s/{token}/download")]
+pub async fn share_download(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+    shares: Data<ShareStore>,
+    token: Path<String>,
+    query: Query<SharePathQuery>,
+) -> HttpResponse {
+    let share = match open_share(&req, &shares, &token) {
+        Ok(share) => share,
+        Err(response) => return response,
+    };
+    if share.mode != ShareMode::ReadOnly {
+        return HttpResponse::Forbidden().body("This link only accepts uploads");
+    }
+    if !shares.is_unlocked(&share, unlock_cookie(&req, &share).as_deref()) {
+        return HttpResponse::Forbidden().body("This link is protected by a password");
+    }
+
+    let relative = query.into_inner().path.unwrap_or_default();
+    let relative = relative.trim_matches('/');
+    if !share.is_dir && !relative.is_empty() {
+        return HttpResponse::BadRequest().body("Only directories have paths within a share");
+    }
+    let file_path = match normalize_file_path(&join_share_path(&share.path, relative)) {
+        Ok(file_path) => file_path,
+        Err(err) => return share_error_response(err.into()),
+    };
+    let metadata = match storage.stat(&file_path) {
+        Ok(metadata) if !metadata.is_dir => metadata,
+        _ => return HttpResponse::NotFound().body(format!("{relative} is not a file")),
+    };
+
+    // Players and download managers request many parts of a file, which are served while the
+    // share has downloads left. A download counts when it starts at the beginning of the file,
+    // so the limit can't be dodged by asking for `bytes=0-`.
+    if req.headers().contains_key(header::RANGE) && share.remaining_downloads() == Some(0) {
+        return share_error_response(ShareError::LimitReached);
+    }
+    let mut response =
+        serve_storage_file(&req, &storage, &file_path, metadata, &configs.mime_types).await;
+    if counts_as_download(&response) {
+        let store = shares.clone();
+        let counted_share = share.clone();
+        match web::block(move || store.record_download(&counted_share)).await {
+            Ok(Ok(_)) => info!("Downloaded {} through a share", file_path.display()),
+            Ok(Err(err)) => return share_error_response(err),
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        }
+    }
+
+    let file_name = file_path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| share.id.clone());
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(file_name)],
+    };
+    if let Ok(value) = HeaderValue::from_str(&disposition.to_string()) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, value);
+    }
+
+    response
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ShareUploadQuery {
+    /// Name of the new file
+    name: String,
+}
+
+#[put("/s/{token}/upload")]
+pub async fn share_upload(
+    req: HttpRequest,
+    storage: Data<dyn Storage>,
+    shares: Data<ShareStore>,
+    versions: Data<VersionStore>,
+    token: Path<String>,
+    query: Query<ShareUploadQuery>,
+    payload: web::Payload,
+) -> HttpResponse {
+    let share = match open_share(&req, &shares, &token) {
+        Ok(share) => share,
+        Err(response) => return response,
+    };
+    if share.mode != ShareMode::UploadOnly {
+        return HttpResponse::Forbidden().body("This link doesn't accept uploads");
+    }
+    if let Err(response) = check_writable(storage.as_ref()) {
+        return response;
+    }
+    if !shares.is_unlocked(&share, unlock_cookie(&req, &share).as_deref()) {
+        return HttpResponse::Forbidden().body("This link is protected by a password");
+    }
+
+    let name = query.into_inner().name;
+    if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\']) {
+        return HttpResponse::BadRequest().body(format!("{name} is not a valid file name"));
+    }
+    let file_path = match normalize_file_path(&join_share_path(&share.path, &name)) {
+        Ok(file_path) => file_path,
+        Err(err) => return share_error_response(err.into()),
+    };
+    if storage.stat(&file_path).is_ok() {
+        return HttpResponse::Conflict().body(format!("{name} already exists"));
+    }
+
+    let staging_versions = versions.clone();
+    let (staging_path, staging_file) =
+        match web::block(move || staging_versions.create_staging_file()).await {
+            Ok(Ok(staging)) => staging,
+            Ok(Err(err)) => return share_error_response(err.into()),
+            Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+        };
+    let written = match write_payload(payload, staging_file).await {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = fs::remove_file(&staging_path);
+            return HttpResponse::InternalServerError().body(format!("Failed to upload: {err}"));
+        }
+    };
+
+    let target_path = file_path.clone();
+    let cleanup_path = staging_path.clone();
+    let stored = web::block(move || storage.move_in(&staging_path, &target_path, false));
+    match stored.await {
+        Ok(Ok(())) => {
+            info!(
+                "Uploaded {} ({} B) through a share",
+                file_path.display(),
+                written
+            );
+            HttpResponse::Created().finish()
+        }
+        Ok(Err(err)) => {
+            let _ = fs::remove_file(&cleanup_path);
+            match err.kind() {
+                ErrorKind::AlreadyExists => {
+                    HttpResponse::Conflict().body(format!("{name} already exists"))
+                }
+                _ => share_error_response(err.into()),
+            }
+        }
+        Err(err) => HttpResponse::InternalServerError().body(err.to_string()),
+    }
+}
+
+/// Looks up the share of a link, or the page explaining why it can't be used
+fn open_share(req: &HttpRequest, shares: &ShareStore, token: &str) -> Result<Share, HttpResponse> {
+    shares.verify(token).map_err(|err| {
+        info!("Refused share link from {:?}: {}", req.peer_addr(), err);
+        share_error_page(err)
+    })
+}
+
+fn unlock_cookie_name(share: &Share) -> String {
+    format!("share-{}", share.id)
+}
+
+fn unlock_cookie(req: &HttpRequest, share: &Share) -> Option<String> {
+    req.cookie(&unlock_cookie_name(share))
+        .map(|cookie| cookie.value().to_owned())
+}
+
+/// Whether `response` sends a whole file or a part of it starting at the first byte
+fn counts_as_download(response: &HttpResponse) -> bool {
+    match response.status() {
+        StatusCode::OK => true,
+        StatusCode::PARTIAL_CONTENT => response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|range| range.starts_with("bytes 0-")),
+        _ => false,
+    }
+}
+
+fn join_share_path(parent: &str, relative: &str) -> String {
+    match (parent.is_empty(), relative.is_empty()) {
+        (_, true) => parent.to_owned(),
+        (true, false) => relative.to_owned(),
+        (false, false) => format!("{parent}/{relative}"),
+    }
+}
+
+/// Lists one level of a shared directory, `relative` being a path within it
+fn list_share_directory(
+    storage: &dyn Storage,
+    shared_path: &str,
+    relative: &str,
+) -> Result<SharePageContent, ShareError> {
+    let dir_path = normalize_file_path(&join_share_path(shared_path, relative))?;
+    if !storage
+        .stat(&dir_path)
+        .is_ok_and(|metadata| metadata.is_dir)
+    {
+        return Err(ShareError::Invalid(format!(
+            "{relative} is not a directory"
+        )));
+    }
+
+    let mut entries: Vec<ShareEntryTemplate> = storage
+        .list(&dir_path)?
+        .into_iter()
+        .filter_map(|entry| {
+            let entry_path = join_share_path(relative, &entry.name);
+            // Skips the data directory and anything else that can't be resolved
+            normalize_file_path(&join_share_path(shared_path, &entry_path)).ok()?;
+            let metadata = entry.metadata;
+
+            Some(ShareEntryTemplate {
+                name: escape_html(&entry.name),
+                is_dir: metadata.is_dir,
+                query: utf8_percent_encode(&entry_path, NON_ALPHANUMERIC).to_string(),
+                size: if metadata.is_dir {
+                    String::new()
+                } else {
+                    format!("{} B", metadata.len)
+                },
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+
+    let parent = (!relative.is_empty()).then(|| {
+        let parent = relative.rsplit_once('/').map_or("", |(parent, _)| parent);
+        utf8_percent_encode(parent, NON_ALPHANUMERIC).to_string()
+    });
+
+    Ok(SharePageContent::Directory {
+        path: escape_html(relative),
+        parent,
+        entries,
+    })
+}
+
+fn share_page_response(status: StatusCode, template: SharePageTemplate) -> HttpResponse {
+    HttpResponse::build(status)
+        .insert_header(ContentType::html())
+        .body(template.render().unwrap())
+}
+
+fn share_error_page(err: ShareError) -> HttpResponse {
+    let template = ShareErrorTemplate {
+        message: escape_html(&err.to_string()),
+    }
+    .render()
+    .unwrap();
+
+    HttpResponse::build(share_error_status(&err))
+        .insert_header(ContentType::html())
+        .body(template)
+}
+
+fn share_error_response(err: ShareError) -> HttpResponse {
+    let message = err.to_string();
+    info!("Share request failed: {}", message);
+
+    HttpResponse::build(share_error_status(&err)).body(message)
+}
+
+fn share_error_status(err: &ShareError) -> StatusCode {
+    match err {
+        ShareError::NotFound => StatusCode::NOT_FOUND,
+        ShareError::Expired | ShareError::LimitReached => StatusCode::GONE,
+        ShareError::Invalid(_) => StatusCode::BAD_REQUEST,
+        ShareError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::{
+        test::{self, TestRequest},
+        App,
+    };
+    use file_server_core::storage::LocalStorage;
+
+    use super::*;
+    use crate::shares;
+
+    #[actix_web::test]
+    async fn ranges_from_the_start_count_as_downloads() {
+        let base_dir =
+            std::env::temp_dir().join(format!("file-server-test-{:016x}", rand::random::<u64>()));
+        fs::create_dir_all(&base_dir).unwrap();
+        fs::write(base_dir.join("report.txt"), "confidential").unwrap();
+        let mut builder = ServerConfigs::builder();
+        builder.base_dir(&base_dir);
+        builder.data_dir(&base_dir.join(".file-server"));
+        let configs = builder.build();
+
+        let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(&base_dir));
+        let store = ShareStore::open(&configs.data_dir).unwrap();
+        let share = store
+            .create(
+                storage.as_ref(),
+                NewShare {
+                    path: "report.txt".to_owned(),
+                    max_downloads: Some(1),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let uri = format!("/s/{}/download", store.token(&share));
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(configs))
+                .app_data(Data::from(storage))
+                .app_data(Data::new(store))
+                .configure(shares::config),
+        )
+        .await;
+        let download = |range: &str| {
+            TestRequest::get()
+                .uri(&uri)
+                .insert_header((header::RANGE, range))
+                .to_request()
+        };
+
+        // Parts after the first byte don't use up the share
+        let response = test::call_service(&app, download("bytes=4-")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        let response = test::call_service(&app, download("bytes=0-")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(test::read_body(response).await, "confidential");
+
+        for range in ["bytes=0-", "bytes=4-"] {
+            let response = test::call_service(&app, download(range)).await;
+            assert_eq!(response.status(), StatusCode::GONE, "{range}");
+        }
+        let response = test::call_service(&app, TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(response.status(), StatusCode::GONE);
+
+        fs::remove_dir_all(&base_dir).unwrap();
+    }
+}
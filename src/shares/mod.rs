@@ -0,0 +1,14 @@
+use actix_web::web;
+
+pub mod handlers;
+pub mod templates;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(handlers::create_share)
+        .service(handlers::list_shares)
+        .service(handlers::revoke_share)
+        .service(handlers::share_page)
+        .service(handlers::unlock_share)
+        .service(handlers::share_download)
+        .service(handlers::share_upload);
+}
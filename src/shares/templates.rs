@@ -0,0 +1,67 @@
+use askama::Template;
+use file_server_core::{escape_html, shares::Share};
+
+use crate::file_manager::templates::format_timestamp;
+
+/// Public page of a share link
+#[derive(Debug, Template)]
+#[template(path = "share.html", escape = "none")]
+pub struct SharePageTemplate {
+    /// Escaped name of the shared file or directory
+    pub name: String,
+    pub token: String,
+    pub expires_at: String,
+    pub remaining_downloads: Option<u64>,
+    pub content: SharePageContent,
+}
+
+impl SharePageTemplate {
+    pub fn new(share: &Share, token: &str, content: SharePageContent) -> Self {
+        let name = share.path.rsplit('/').next().unwrap_or(&share.path);
+
+        Self {
+            name: escape_html(if name.is_empty() { "Files" } else { name }),
+            token: token.to_owned(),
+            expires_at: format_timestamp(share.expires_at),
+            remaining_downloads: share.remaining_downloads(),
+            content,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SharePageContent {
+    /// Escaped error of the last attempt, if any
+    Password {
+        error: Option<String>,
+    },
+    File {
+        size: u64,
+    },
+    Directory {
+        /// Escaped path within the share
+        path: String,
+        /// Query of the parent directory link, if not at the root of the share
+        parent: Option<String>,
+        entries: Vec<ShareEntryTemplate>,
+    },
+    Upload,
+}
+
+#[derive(Debug)]
+pub struct ShareEntryTemplate {
+    /// Escaped name of the entry
+    pub name: String,
+    pub is_dir: bool,
+    /// Escaped and URL-encoded path within the share, for the `path` query parameter
+    pub query: String,
+    pub size: String,
+}
+
+/// Page shown instead of a share that can't be used
+#[derive(Debug, Template)]
+#[template(path = "share-error.html", escape = "none")]
+pub struct ShareErrorTemplate {
+    /// Escaped reason
+    pub message: String,
+}
@@ -0,0 +1,184 @@
+use actix_web::{
+    body::SizedStream,
+    http::{
+        header::{self, ContentType},
+        Method, StatusCode,
+    },
+    web::{self, Data},
+    HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use file_server_core::storage::{Storage, StorageMetadata};
+use file_server_core::FileStream;
+use log::warn;
+use percent_encoding::percent_decode_str;
+
+use std::path::{Path, PathBuf};
+
+use crate::configs::ServerConfigs;
+use crate::file_server::handlers::{serve_storage_file, storage_path};
+
+/// Page served for a directory, and for every unknown path with the SPA fallback
+const INDEX_PAGE: &str = "index.html";
+/// Pages of the site root answering errors, plain messages are sent without them
+const NOT_FOUND_PAGE: &str = "404.html";
+const SERVER_ERROR_PAGE: &str = "500.html";
+
+/// What a requested path of the site leads to
+enum Resolution {
+    File(PathBuf, StorageMetadata),
+    /// Location of the canonical URL of the page
+    Redirect(String),
+    NotFound,
+}
+
+pub async fn serve_site(
+    req: HttpRequest,
+    configs: Data<ServerConfigs>,
+    storage: Data<dyn Storage>,
+) -> HttpResponse {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return HttpResponse::MethodNotAllowed()
+            .insert_header((header::ALLOW, "GET, HEAD"))
+            .finish();
+    }
+
+    let request_path = percent_decode_str(req.path()).decode_utf8_lossy();
+    // Paths outside of the site and into the data directory don't exist for visitors
+    let resolution = match storage_path(&request_path) {
+        Ok(file_path) => {
+            let resolving_storage = storage.clone().into_inner();
+            let url_path = req.path().to_owned();
+            match web::block(move || resolve(resolving_storage.as_ref(), &url_path, &file_path))
+                .await
+            {
+                Ok(resolution) => resolution,
+                Err(err) => return HttpResponse::InternalServerError().body(err.to_string()),
+            }
+        }
+        Err(_) => Resolution::NotFound,
+    };
+
+    match resolution {
+        Resolution::File(file_path, metadata) => {
+            serve_page(&req, &configs, &storage, &file_path, metadata).await
+        }
+        Resolution::Redirect(location) => {
+            let location = match req.query_string() {
+                "" => location,
+                query_string => format!("{}?{}", location, query_string),
+            };
+            HttpResponse::MovedPermanently()
+                .insert_header((header::LOCATION, location))
+                .finish()
+        }
+        Resolution::NotFound => {
+            // Single-page apps route the paths themselves, missing assets stay missing
+            let index_path = Path::new(INDEX_PAGE);
+            let is_asset = Path::new(request_path.as_ref()).extension().is_some();
+            if configs.spa_fallback && !is_asset {
+                let stat_storage = storage.clone().into_inner();
+                if let Ok(Ok(metadata)) = web::block(move || stat_storage.stat(index_path)).await {
+                    if !metadata.is_dir {
+                        return serve_page(&req, &configs, &storage, index_path, metadata).await;
+                    }
+                }
+            }
+
+            error_page(
+                &storage,
+                StatusCode::NOT_FOUND,
+                format!("{} was not found", request_path),
+            )
+            .await
+        }
+    }
+}
+
+/// Resolves `file_path`, requested as `url_path`, to a file of the site. Directories are
+/// served by their index page and `/about` by `about.html`, directories being redirected to
+/// their path with a trailing slash and files to their path without one.
+fn resolve(storage: &dyn Storage, url_path: &str, file_path: &Path) -> Resolution {
+    let has_trailing_slash = url_path.ends_with('/');
+    let without_slash = || match url_path.trim_end_matches('/') {
+        "" => String::from("/"),
+        trimmed => trimmed.to_owned(),
+    };
+
+    match storage.stat(file_path) {
+        Ok(metadata) if metadata.is_dir => {
+            if !has_trailing_slash {
+                return Resolution::Redirect(format!("{}/", url_path));
+            }
+
+            let index_path = file_path.join(INDEX_PAGE);
+            match storage.stat(&index_path) {
+                Ok(metadata) if !metadata.is_dir => Resolution::File(index_path, metadata),
+                _ => Resolution::NotFound,
+            }
+        }
+        Ok(_) if has_trailing_slash => Resolution::Redirect(without_slash()),
+        Ok(metadata) => Resolution::File(file_path.to_owned(), metadata),
+        Err(_) => {
+            let Some(name) = file_path.file_name() else {
+                return Resolution::NotFound;
+            };
+            let page_path = file_path.with_file_name(format!("{}.html", name.to_string_lossy()));
+
+            match storage.stat(&page_path) {
+                Ok(metadata) if metadata.is_dir => Resolution::NotFound,
+                Ok(_) if has_trailing_slash => Resolution::Redirect(without_slash()),
+                Ok(metadata) => Resolution::File(page_path, metadata),
+                Err(_) => Resolution::NotFound,
+            }
+        }
+    }
+}
+
+/// Streams a file of the site, honoring conditional and `Range` headers
+async fn serve_page(
+    req: &HttpRequest,
+    configs: &ServerConfigs,
+    storage: &Data<dyn Storage>,
+    file_path: &Path,
+    metadata: StorageMetadata,
+) -> HttpResponse {
+    let response = serve_storage_file(req, storage, file_path, metadata, &configs.mime_types).await;
+    if response.status() != StatusCode::INTERNAL_SERVER_ERROR {
+        return response;
+    }
+
+    let message = format!("Failed to read {:?}", file_path);
+    warn!("{}", message);
+    error_page(storage, StatusCode::INTERNAL_SERVER_ERROR, message).await
+}
+
+/// Answers with the custom page of the site for `status`, or `message` without one
+async fn error_page(
+    storage: &Data<dyn Storage>,
+    status: StatusCode,
+    message: String,
+) -> HttpResponse {
+    let page_path = Path::new(match status {
+        StatusCode::NOT_FOUND => NOT_FOUND_PAGE,
+        _ => SERVER_ERROR_PAGE,
+    });
+
+    let storage = storage.clone().into_inner();
+    let page = web::block(move || {
+        let metadata = storage
+            .stat(page_path)
+            .ok()
+            .filter(|metadata| !metadata.is_dir)?;
+        Some((storage.open(page_path, None).ok()?, metadata.len))
+    })
+    .await
+    .ok()
+    .flatten();
+
+    match page {
+        Some((reader, len)) => HttpResponseBuilder::new(status)
+            .insert_header(ContentType::html())
+            .body(SizedStream::new(len, FileStream::new(reader, len))),
+        None => HttpResponseBuilder::new(status).body(message),
+    }
+}
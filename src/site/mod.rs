@@ -0,0 +1,8 @@
+use actix_web::web;
+
+pub mod handlers;
+
+/// Serves the site for every path no other route matches
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.default_service(web::to(handlers::serve_site));
+}
@@ -1,9 +1,30 @@
-use std::env;
+use std::{env, io, sync::Arc, time::Duration};
 
-use actix_web::{middleware::Logger, web::Data, App, HttpServer};
-use log::info;
+use actix_web::{
+    middleware::{DefaultHeaders, Logger},
+    web::{self, Data, PayloadConfig},
+    App, HttpServer,
+};
+use file_server_core::{
+    archive::{is_archive, ArchiveStorage},
+    dav::LockTable,
+    drop_box::DropBoxLog,
+    images::ImageLimits,
+    lines::LineIndexCache,
+    overlay::{OverlayLayer, OverlayStorage},
+    pastes::PasteStore,
+    s3::MultipartStore,
+    shares::ShareStore,
+    storage::{LocalStorage, Storage},
+    trash::Trash,
+    uploads::UploadStore,
+    versions::{Retention, VersionStore},
+};
+use log::{info, warn};
 
-use crate::{configs::ServerConfigs, file_manager, file_server};
+use crate::{
+    configs::ServerConfigs, dav, drop_box, file_manager, file_server, pastes, s3, shares, site,
+};
 
 pub async fn start(configs: ServerConfigs) -> std::io::Result<()> {
     env::set_var("RUST_LOG", configs.log_level.to_string());
@@ -16,16 +37,177 @@ pub async fn start(configs: ServerConfigs) -> std::io::Result<()> {
     );
 
     let shared_configs = configs.clone();
+    let storage: Arc<dyn Storage> = if is_archive(&configs.base_dir) {
+        let archive = ArchiveStorage::open(&configs.base_dir)?;
+        info!(
+            "Serving {} members of {:?} read-only",
+            archive.len(),
+            configs.base_dir
+        );
+        Arc::new(archive)
+    } else {
+        Arc::new(LocalStorage::new(&configs.base_dir))
+    };
+    let storage = match configs.layers.is_empty() {
+        true => storage,
+        false => {
+            let mut layers: Vec<OverlayLayer> = configs
+                .layers
+                .iter()
+                .map(|(name, path)| OverlayLayer::new(name, Arc::new(LocalStorage::new(path))))
+                .collect();
+            let top_name = configs
+                .base_dir
+                .file_name()
+                .map_or("top".into(), |name| name.to_string_lossy());
+            layers.push(OverlayLayer::new(&top_name, storage));
+            info!(
+                "Merging layers {}",
+                layers
+                    .iter()
+                    .map(|layer| layer.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Arc::new(OverlayStorage::new(layers)?)
+        }
+    };
+    if configs.site {
+        info!(
+            "Serving {:?} as a website, the file manager is at {}",
+            configs.base_dir, configs.manager_path
+        );
+    }
+    let storage = Data::from(storage);
+    let line_indices = Data::new(LineIndexCache::default());
+    let image_limits = Data::new(ImageLimits::new(
+        configs.max_image_pixels,
+        configs.image_concurrency,
+    ));
 
-    HttpServer::new(move || {
-        App::new()
+    let version_store = Data::new(VersionStore::new(
+        &configs.data_dir,
+        Retention {
+            max_count: configs.max_versions,
+            max_age: (configs.version_max_age_days > 0)
+                .then(|| Duration::from_secs(configs.version_max_age_days * 24 * 60 * 60)),
+        },
+    ));
+
+    let trash = Data::new(Trash::new(
+        &configs.data_dir,
+        (configs.trash_max_age_days > 0)
+            .then(|| Duration::from_secs(configs.trash_max_age_days * 24 * 60 * 60)),
+    ));
+    if let Err(err) = trash.expire() {
+        warn!("Failed to purge expired trash entries: {}", err);
+    }
+
+    let uploads = Data::new(UploadStore::new(
+        &configs.data_dir,
+        Duration::from_secs(configs.upload_expiry_hours * 60 * 60),
+    ));
+    if let Err(err) = uploads.expire() {
+        warn!("Failed to remove expired uploads: {}", err);
+    }
+    let dav_locks = Data::new(LockTable::default());
+    let shares = Data::new(ShareStore::open(&configs.data_dir)?);
+    if let Err(err) = shares.expire() {
+        warn!("Failed to remove expired shares: {}", err);
+    }
+    for (name, path) in &configs.drop_boxes {
+        std::fs::create_dir_all(path).map_err(|err| {
+            io::Error::new(err.kind(), format!("Drop box {name} is unusable: {err}"))
+        })?;
+    }
+    let drop_box_log = configs
+        .drop_box_log
+        .as_deref()
+        .map(|path| Data::new(DropBoxLog::new(path)));
+    let pastes = Data::new(PasteStore::new(&configs.paste_dir, &configs.data_dir));
+    if let Err(err) = pastes.expire() {
+        warn!("Failed to remove expired pastes: {}", err);
+    }
+    let (s3_storage, s3_version_store, s3_trash) =
+        (storage.clone(), version_store.clone(), trash.clone());
+
+    let server = HttpServer::new(move || {
+        let mut app = App::new()
             .wrap(Logger::default())
+            // Browsers must not second-guess the detected content types
+            .wrap(DefaultHeaders::new().add(("X-Content-Type-Options", "nosniff")))
             .app_data(Data::new(shared_configs.clone()))
+            .app_data(storage.clone())
+            .app_data(line_indices.clone())
+            .app_data(image_limits.clone())
+            .app_data(version_store.clone())
+            .app_data(trash.clone())
+            .app_data(uploads.clone())
+            .app_data(dav_locks.clone())
+            .app_data(shares.clone())
+            .app_data(pastes.clone())
             .configure(file_server::config)
-            .configure(file_manager::config)
+            .configure(dav::config)
+            .configure(shares::config)
+            .configure(drop_box::config)
+            .configure(pastes::config);
+        if let Some(drop_box_log) = &drop_box_log {
+            app = app.app_data(drop_box_log.clone());
+        }
+        let manager_scope =
+            web::scope(shared_configs.manager_scope()).configure(file_manager::api_config);
+        app = match shared_configs.site {
+            // The site takes `/` and every path the other routes leave
+            true => app
+                .service(
+                    manager_scope
+                        .route("", web::get().to(file_manager::handlers::manager_page))
+                        .route("/", web::get().to(file_manager::handlers::manager_page)),
+                )
+                .configure(site::config),
+            false => app.configure(file_manager::config).service(manager_scope),
+        };
+
+        app
     })
     .workers(2)
-    .bind((configs.host, configs.port))?
-    .run()
-    .await
+    .bind((configs.host.clone(), configs.port))?
+    .run();
+
+    let Some(s3_port) = configs.s3_port else {
+        return server.await;
+    };
+
+    info!(
+        "S3-compatible API will be listening at {}:{}",
+        configs.host, s3_port
+    );
+    let multipart = Data::new(MultipartStore::new(
+        &configs.data_dir,
+        Duration::from_secs(configs.upload_expiry_hours * 60 * 60),
+    ));
+    if let Err(err) = multipart.expire() {
+        warn!("Failed to remove expired multipart uploads: {}", err);
+    }
+
+    let s3_configs = configs.clone();
+    let s3_server = HttpServer::new(move || {
+        App::new()
+            .wrap(Logger::default())
+            .app_data(Data::new(s3_configs.clone()))
+            .app_data(s3_storage.clone())
+            .app_data(s3_version_store.clone())
+            .app_data(s3_trash.clone())
+            .app_data(multipart.clone())
+            // CompleteMultipartUpload lists up to 10000 parts
+            .app_data(PayloadConfig::new(4 * 1024 * 1024))
+            .configure(s3::config)
+    })
+    .workers(2)
+    .bind((configs.host, s3_port))?
+    .run();
+
+    futures_util::future::try_join(server, s3_server)
+        .await
+        .map(|_| ())
 }